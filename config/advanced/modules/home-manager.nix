@@ -0,0 +1,11 @@
+# Home Manager configuration of your user. Options can be found in the
+# Home Manager manual (https://nix-community.github.io/home-manager).
+{ config, ... }:
+
+{
+  home-manager.useGlobalPkgs = true;
+  home-manager.useUserPackages = true;
+  home-manager.users."@USERNAME@" = {
+    home.stateVersion = config.system.stateVersion;
+  };
+}
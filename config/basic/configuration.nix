@@ -2,15 +2,20 @@
 # your system.  Help is available in the configuration.nix(5) man page
 # and in the NixOS manual (accessible by running ‘nixos-help’).
 { config, pkgs, ... }:
-@NVIDIAOFFLOAD@
+
 {
   imports =
     [ # Include the results of the hardware scan.
       ./hardware-configuration.nix
+@IMPORTS@
     ];
 
 @BOOTLOADER@
 
+@QUIRKS@
+
+@NVIDIAOFFLOAD@
+
 @NETWORK@
 
 @TIMEZONE@
@@ -6,6 +6,7 @@
     nix-software-center.url = "github:vlinkz/nix-software-center";
     nixos-conf-editor.url = "github:vlinkz/nixos-conf-editor";
     snow.url = "github:snowflakelinux/snow";
+@FLAKEINPUTS@
   };
   outputs = { self, nixpkgs, ... }@inputs:
     let
@@ -19,6 +20,7 @@
           ./snowflake.nix
           inputs.snowflake.nixosModules.snowflake
           inputs.nix-data.nixosModules.${system}.nix-data
+@FLAKEMODULES@
         ];
         specialArgs = { inherit inputs; inherit system; };
     };
@@ -11,6 +11,5 @@
     flake = "/etc/nixos/flake.nix";
     flakearg = "@HOSTNAME@";
   };
-  snowflakeos.gnome.enable = true;
   snowflakeos.osInfo.enable = true;
 }
@@ -10,10 +10,10 @@ pkgs.stdenv.mkDerivation rec {
 
   src = [ ./. ];
 
-  cargoDeps = pkgs.rustPlatform.fetchCargoTarball {
-    inherit src;
-    name = "${pname}-${version}";
-    hash = "sha256-f3L2x2NRv4aqYoAFvXBU1tcCHPeTp7PLl/87HiC9y0s=";
+  # Vendored from Cargo.lock, so the dependencies need no hash of their own
+  cargoDeps = pkgs.rustPlatform.importCargoLock {
+    lockFile = ./Cargo.lock;
+    allowBuiltinFetchGit = true;
   };
 
   nativeBuildInputs = with pkgs; [
@@ -0,0 +1,85 @@
+use crate::{
+    bootloader::{BootloaderConfig, Windows},
+    config::{Choice, Desktop},
+    form::FieldValue,
+    nvidia::NvidiaConfig,
+    protocol::{DiskLayout, PartitionSchema, RootFilesystem},
+    quirks::Quirk,
+};
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UserConfig {
+    pub name: String,
+    pub username: String,
+    pub password: String,
+    pub hostname: String,
+    pub rootpassword: Option<String>,
+    pub autologin: bool,
+}
+
+/// Everything the user chose while going through the installation steps.
+#[derive(Debug, Default, Clone)]
+pub struct Answers {
+    pub language: Option<String>,
+    pub timezone: Option<String>,
+    pub keyboard: Option<String>,
+    pub partitions: Option<PartitionSchema>,
+    pub user: Option<UserConfig>,
+    pub desktop: Option<Desktop>,
+    /// Selected choices of each `!list` step, by list id
+    pub lists: HashMap<String, HashMap<String, Choice>>,
+    /// Values of every `!form` field, by field id
+    pub forms: HashMap<String, FieldValue>,
+    /// Hardware quirks that apply to the machine
+    pub quirks: Vec<Quirk>,
+    /// nixos-hardware module for the machine, e.g. `lenovo-thinkpad-t480`
+    pub nixos_hardware: Option<String>,
+    /// PRIME configuration of a hybrid graphics machine
+    pub nvidia: Option<NvidiaConfig>,
+    pub bootloader: BootloaderConfig,
+    /// Disk GRUB is installed to on BIOS machines when partitioning by hand,
+    /// the disk holding the root partition when not chosen
+    pub bootdisk: Option<String>,
+    /// Windows installation kept next to the installed system
+    pub windows: Option<Windows>,
+    /// Installing to a removable disk that should boot on other machines too
+    pub portable: bool,
+    /// Filesystem of the root partition when partitioning a whole disk, or as found on
+    /// a premounted target
+    pub filesystem: RootFilesystem,
+    /// Layout of a whole disk, plain partitions or an encrypted volume group
+    pub layout: DiskLayout,
+}
+
+impl Answers {
+    pub fn hostname(&self) -> Option<&str> {
+        self.user.as_ref().map(|u| u.hostname.as_str())
+    }
+
+    /// Whether the root filesystem is on ZFS.
+    pub fn zfs(&self) -> bool {
+        match self.partitions.as_ref() {
+            Some(PartitionSchema::Custom(partitions)) => partitions
+                .values()
+                .any(|part| part.format.as_deref() == Some("zfs")),
+            Some(_) => self.filesystem == RootFilesystem::Zfs,
+            None => false,
+        }
+    }
+
+    /// Disk GRUB is installed to on BIOS systems, the chosen disk or the disk holding
+    /// the root partition.
+    pub fn bootdisk(&self) -> Option<String> {
+        match self.partitions.as_ref()? {
+            PartitionSchema::FullDisk(disk) => Some(disk.to_string()),
+            PartitionSchema::Custom(partitions) => self.bootdisk.clone().or_else(|| {
+                partitions
+                    .values()
+                    .find(|part| part.mountpoint.as_deref() == Some("/"))
+                    .map(|part| part.device.to_string())
+            }),
+            PartitionSchema::Premounted { bootdisk } => bootdisk.clone(),
+        }
+    }
+}
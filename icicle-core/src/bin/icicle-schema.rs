@@ -0,0 +1,20 @@
+//! Writes the JSON Schemas of the config files to the given directory.
+
+use anyhow::{Context, Result};
+use icicle_core::schema::schemas;
+use std::{env, fs, path::PathBuf};
+
+fn main() -> Result<()> {
+    let dir = PathBuf::from(
+        env::args()
+            .nth(1)
+            .context("Usage: icicle-schema <directory>")?,
+    );
+    fs::create_dir_all(&dir)?;
+    for (filename, schema) in schemas() {
+        let path = dir.join(filename);
+        fs::write(&path, serde_json::to_string_pretty(&schema)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
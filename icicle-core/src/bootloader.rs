@@ -0,0 +1,271 @@
+//! Bootloader and EFI system partition of the installed system.
+
+use crate::protocol::Disk;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Where sbctl keeps the Secure Boot keys lanzaboote signs with.
+pub const PKI_BUNDLE: &str = "/var/lib/sbctl";
+
+/// Kernel modules the initrd of a portable installation carries, so that it finds its
+/// disk on other machines: USB, SATA, NVMe, SD card and virtual disk controllers.
+const PORTABLE_INITRD_MODULES: [&str; 18] = [
+    "xhci_pci",
+    "ehci_pci",
+    "ohci_pci",
+    "uhci_hcd",
+    "usb_storage",
+    "uas",
+    "sd_mod",
+    "ahci",
+    "ata_piix",
+    "nvme",
+    "sdhci_pci",
+    "rtsx_pci_sdmmc",
+    "mmc_block",
+    "virtio_pci",
+    "virtio_blk",
+    "virtio_scsi",
+    "usbhid",
+    "hid_generic",
+];
+
+/// Room GRUB needs before the first partition of an `msdos` disk.
+const MBR_GAP: u64 = 1024 * 1024;
+
+/// Bootloader installed on EFI machines, BIOS machines always use GRUB.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Bootloader {
+    #[default]
+    SystemdBoot,
+    Grub,
+    Limine,
+}
+
+/// Where the EFI system partition is mounted.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Esp {
+    /// The ESP holds the kernels and initrds too, and must be large enough for them
+    #[serde(rename = "/boot")]
+    Boot,
+    #[default]
+    #[serde(rename = "/boot/efi")]
+    BootEfi,
+}
+
+/// Bootloader settings of an installation config.
+///
+/// ```yml
+/// bootloader:
+///   loader: grub
+///   esp: /boot
+///   timeout: 3
+///   configuration_limit: 10
+/// ```
+///
+/// ```yml
+/// bootloader:
+///   loader: systemd-boot
+///   lanzaboote: true
+/// ```
+///
+/// ```yml
+/// bootloader:
+///   loader: limine
+///   configuration_limit: 10
+/// ```
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct BootloaderConfig {
+    /// Bootloader used on EFI machines, `systemd-boot` by default
+    #[serde(default)]
+    pub loader: Bootloader,
+    /// Mountpoint of the EFI system partition, `/boot/efi` by default
+    #[serde(default)]
+    pub esp: Esp,
+    /// Seconds the boot menu is shown, NixOS' default of 5 when not given
+    pub timeout: Option<u32>,
+    /// Most generations listed in the boot menu, all of them when not given
+    pub configuration_limit: Option<u32>,
+    /// Whether GRUB lists other operating systems found by os-prober, `true` by default
+    /// except on portable installations
+    pub os_prober: Option<bool>,
+    /// Whether systemd-boot is replaced by lanzaboote, which signs the boot files for
+    /// Secure Boot. Off unless chosen, the created keys have to be enrolled before the
+    /// installed system boots with Secure Boot enabled
+    pub lanzaboote: Option<bool>,
+}
+
+/// How the installed system lives next to a Windows installation that is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Windows {
+    /// List Windows in the boot menu. GRUB finds it with os-prober, systemd-boot
+    /// lists it on its own when it shares the EFI system partition
+    pub boot_entry: bool,
+    /// Keep the hardware clock in local time, as Windows does
+    pub local_time: bool,
+}
+
+/// Why GRUB can not be installed to a disk on a BIOS machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BiosDiskProblem {
+    /// The disk has no partition table
+    NoTable,
+    /// A `gpt` disk without a BIOS boot partition
+    NoBiosBootPartition,
+    /// An `msdos` disk whose first partition starts within the first MiB
+    NoMbrGap,
+}
+
+impl Esp {
+    /// Mountpoint inside the target, e.g. `/boot/efi`.
+    pub fn mountpoint(&self) -> &'static str {
+        match self {
+            Esp::Boot => "/boot",
+            Esp::BootEfi => "/boot/efi",
+        }
+    }
+}
+
+impl BootloaderConfig {
+    /// Whether lanzaboote is installed, only on EFI machines using systemd-boot.
+    pub fn uses_lanzaboote(&self, efi: bool) -> bool {
+        efi && self.loader == Bootloader::SystemdBoot && self.lanzaboote == Some(true)
+    }
+
+    /// Configuration substituted for `@BOOTLOADER@`. `bios_disk` is the disk GRUB is
+    /// installed to on BIOS machines, `None` on EFI machines. A `portable` installation
+    /// boots on other machines too, and leaves the EFI variables of this one alone.
+    pub fn to_nix(&self, bios_disk: Option<&str>, portable: bool) -> String {
+        // A portable installation should not list the systems of the machine it was made on
+        let os_prober = self.os_prober.unwrap_or(!portable);
+        let lanzaboote = self.uses_lanzaboote(bios_disk.is_none());
+        let mut lines = vec!["  # Bootloader.".to_string()];
+        match (bios_disk, self.loader) {
+            _ if lanzaboote => {
+                lines.push("  # Signed for Secure Boot by lanzaboote.".to_string());
+                lines.push("  boot.lanzaboote.enable = true;".to_string());
+                lines.push(format!("  boot.lanzaboote.pkiBundle = \"{}\";", PKI_BUNDLE));
+            }
+            (Some(disk), _) => {
+                lines.push("  boot.loader.grub.enable = true;".to_string());
+                lines.push(format!("  boot.loader.grub.device = \"{}\";", disk));
+                lines.push(format!("  boot.loader.grub.useOSProber = {};", os_prober));
+            }
+            (None, Bootloader::SystemdBoot) => {
+                lines.push("  boot.loader.systemd-boot.enable = true;".to_string());
+            }
+            (None, Bootloader::Grub) => {
+                lines.push("  boot.loader.grub.enable = true;".to_string());
+                lines.push("  boot.loader.grub.efiSupport = true;".to_string());
+                lines.push("  boot.loader.grub.device = \"nodev\";".to_string());
+                lines.push(format!("  boot.loader.grub.useOSProber = {};", os_prober));
+            }
+            (None, Bootloader::Limine) => {
+                lines.push("  boot.loader.limine.enable = true;".to_string());
+            }
+        }
+        if let Some(limit) = self.configuration_limit {
+            let option = match (bios_disk, self.loader) {
+                _ if lanzaboote => "boot.lanzaboote.configurationLimit",
+                (None, Bootloader::SystemdBoot) => "boot.loader.systemd-boot.configurationLimit",
+                (None, Bootloader::Limine) => "boot.loader.limine.maxGenerations",
+                _ => "boot.loader.grub.configurationLimit",
+            };
+            lines.push(format!("  {} = {};", option, limit));
+        }
+        if bios_disk.is_none() {
+            lines.push(format!(
+                "  boot.loader.efi.canTouchEfiVariables = {};",
+                !portable
+            ));
+            // systemd-boot always installs to the fallback path as well
+            match self.loader {
+                Bootloader::Grub if portable => {
+                    lines.push("  boot.loader.grub.efiInstallAsRemovable = true;".to_string());
+                }
+                Bootloader::Limine if portable => {
+                    lines.push("  boot.loader.limine.efiInstallAsRemovable = true;".to_string());
+                }
+                _ => {}
+            }
+            lines.push(format!(
+                "  boot.loader.efi.efiSysMountPoint = \"{}\";",
+                self.esp.mountpoint()
+            ));
+        }
+        if let Some(timeout) = self.timeout {
+            lines.push(format!("  boot.loader.timeout = {};", timeout));
+        }
+        if portable {
+            lines.push(String::new());
+            lines.push("  # Find the disk on other machines too.".to_string());
+            lines.push(format!(
+                "  boot.initrd.availableKernelModules = [ {} ];",
+                PORTABLE_INITRD_MODULES
+                    .iter()
+                    .map(|module| format!("\"{}\"", module))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ));
+            lines.push("  hardware.enableRedistributableFirmware = true;".to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+/// Whether GRUB can embed itself on `disk` on a BIOS machine.
+pub fn check_bios_disk(disk: &Disk) -> Option<BiosDiskProblem> {
+    match disk.table.as_deref() {
+        None => Some(BiosDiskProblem::NoTable),
+        Some("gpt") if !disk.partitions.iter().any(|part| part.bios_boot) => {
+            Some(BiosDiskProblem::NoBiosBootPartition)
+        }
+        Some("gpt") => None,
+        Some(_) if disk.gap < MBR_GAP => Some(BiosDiskProblem::NoMbrGap),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Partition;
+
+    fn disk(table: Option<&str>, gap: u64, bios_boot: bool) -> Disk {
+        Disk {
+            name: "/dev/sda".to_string(),
+            size: 64 << 30,
+            partitions: vec![Partition {
+                name: "/dev/sda1".to_string(),
+                format: if bios_boot { "" } else { "ext4" }.to_string(),
+                size: 1 << 20,
+                bios_boot,
+                os: None,
+            }],
+            table: table.map(String::from),
+            gap,
+            removable: false,
+            volume_group: false,
+        }
+    }
+
+    #[test]
+    fn checks_where_grub_embeds_itself() {
+        assert_eq!(
+            check_bios_disk(&disk(None, 0, false)),
+            Some(BiosDiskProblem::NoTable)
+        );
+        assert_eq!(
+            check_bios_disk(&disk(Some("gpt"), MBR_GAP, false)),
+            Some(BiosDiskProblem::NoBiosBootPartition)
+        );
+        assert_eq!(check_bios_disk(&disk(Some("gpt"), 0, true)), None);
+        assert_eq!(
+            check_bios_disk(&disk(Some("msdos"), 32 * 1024, false)),
+            Some(BiosDiskProblem::NoMbrGap)
+        );
+        assert_eq!(check_bios_disk(&disk(Some("msdos"), MBR_GAP, false)), None);
+    }
+}
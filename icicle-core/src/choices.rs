@@ -0,0 +1,109 @@
+//! Defaults, dependencies and conflicts between the choices of a `!list` step.
+
+use crate::config::Choice;
+
+/// Choices selected when the list is first shown.
+pub fn defaults(choices: &[(String, Choice)]) -> Vec<String> {
+    choices
+        .iter()
+        .filter(|(_, choice)| choice.default)
+        .map(|(key, _)| key.to_string())
+        .collect()
+}
+
+/// `key` and every choice it requires, directly or through other requirements.
+pub fn required(choices: &[(String, Choice)], key: &str) -> Vec<String> {
+    let mut required = vec![key.to_string()];
+    let mut i = 0;
+    while let Some(current) = required.get(i).cloned() {
+        if let Some((_, choice)) = choices.iter().find(|(k, _)| k == &current) {
+            for requirement in choice.requires.iter().flatten() {
+                if !required.contains(requirement) {
+                    required.push(requirement.to_string());
+                }
+            }
+        }
+        i += 1;
+    }
+    required
+}
+
+/// Choices requiring `key`, directly or through other requirements.
+pub fn dependents(choices: &[(String, Choice)], key: &str) -> Vec<String> {
+    choices
+        .iter()
+        .filter(|(k, _)| k != key && required(choices, k).iter().any(|r| r == key))
+        .map(|(k, _)| k.to_string())
+        .collect()
+}
+
+/// A selected choice that conflicts with `key` or one of its requirements.
+pub fn conflict(choices: &[(String, Choice)], key: &str, selected: &[String]) -> Option<String> {
+    let conflicts = |a: &str, b: &str| {
+        choices.iter().any(|(k, choice)| {
+            (k == a && choice.conflicts.iter().flatten().any(|c| c == b))
+                || (k == b && choice.conflicts.iter().flatten().any(|c| c == a))
+        })
+    };
+    let required = required(choices, key);
+    selected
+        .iter()
+        .find(|s| required.iter().any(|r| conflicts(r, s)))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Desktops of a `!list` step, where Plasma requires Qt theming and conflicts with GNOME.
+    fn choices() -> Vec<(String, Choice)> {
+        [
+            ("GNOME", "default: true"),
+            ("Plasma", "{requires: [Qt], conflicts: [GNOME]}"),
+            ("Qt", "requires: [Fonts]"),
+            ("Fonts", "default: true"),
+            ("Apps", "requires: [Plasma]"),
+        ]
+        .into_iter()
+        .map(|(key, yaml)| (key.to_string(), serde_yaml::from_str(yaml).unwrap()))
+        .collect()
+    }
+
+    #[test]
+    fn selects_defaults() {
+        assert_eq!(defaults(&choices()), ["GNOME", "Fonts"]);
+    }
+
+    #[test]
+    fn follows_requirements() {
+        let choices = choices();
+        assert_eq!(
+            required(&choices, "Apps"),
+            ["Apps", "Plasma", "Qt", "Fonts"]
+        );
+        assert_eq!(required(&choices, "GNOME"), ["GNOME"]);
+        assert_eq!(dependents(&choices, "Qt"), ["Plasma", "Apps"]);
+        assert!(dependents(&choices, "Apps").is_empty());
+    }
+
+    #[test]
+    fn finds_conflicts_of_requirements() {
+        let choices = choices();
+        let selected = ["GNOME".to_string(), "Fonts".to_string()];
+        assert_eq!(
+            conflict(&choices, "Plasma", &selected).as_deref(),
+            Some("GNOME")
+        );
+        assert_eq!(
+            conflict(&choices, "Apps", &selected).as_deref(),
+            Some("GNOME")
+        );
+        assert_eq!(conflict(&choices, "Qt", &selected), None);
+        let selected = ["Plasma".to_string()];
+        assert_eq!(
+            conflict(&choices, "GNOME", &selected).as_deref(),
+            Some("Plasma")
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! `when:` conditions on steps and list choices.
+
+use crate::facts::{CpuVendor, Facts, Firmware, GpuVendor};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Holds if every given fact matches.
+///
+/// ```yml
+/// when:
+///   firmware: efi
+///   gpu: nvidia
+///   min_ram: 4096
+///   selected:
+///     KERNEL: Zen
+/// ```
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    pub firmware: Option<Firmware>,
+    pub cpu: Option<CpuVendor>,
+    /// A GPU of this vendor is present
+    pub gpu: Option<GpuVendor>,
+    /// Minimum installed memory in MiB
+    pub min_ram: Option<u64>,
+    /// Maximum installed memory in MiB
+    pub max_ram: Option<u64>,
+    /// Choices selected in earlier `!list` steps, by list id
+    pub selected: Option<HashMap<String, String>>,
+    /// Holds if any of these conditions hold
+    pub any: Option<Vec<Condition>>,
+    /// Holds if this condition does not hold
+    pub not: Option<Box<Condition>>,
+}
+
+/// Selected choice keys of every `!list` step shown so far, by list id.
+pub type Selections = HashMap<String, Vec<String>>;
+
+impl Condition {
+    pub fn holds(&self, facts: &Facts, selections: &Selections) -> bool {
+        self.firmware.map_or(true, |x| x == facts.firmware)
+            && self.cpu.map_or(true, |x| Some(x) == facts.cpu)
+            && self.gpu.map_or(true, |x| facts.gpus.contains(&x))
+            && self.min_ram.map_or(true, |x| facts.ram >= x)
+            && self.max_ram.map_or(true, |x| facts.ram <= x)
+            && self.selected.as_ref().map_or(true, |selected| {
+                selected.iter().all(|(list, choice)| {
+                    selections
+                        .get(list)
+                        .map_or(false, |choices| choices.contains(choice))
+                })
+            })
+            && self.any.as_ref().map_or(true, |any| {
+                any.iter()
+                    .any(|condition| condition.holds(facts, selections))
+            })
+            && self
+                .not
+                .as_ref()
+                .map_or(true, |not| !not.holds(facts, selections))
+    }
+}
+
+/// Whether something guarded by `when` is shown.
+pub fn is_shown(when: Option<&Condition>, facts: &Facts, selections: &Selections) -> bool {
+    when.map_or(true, |condition| condition.holds(facts, selections))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::FakeSystem;
+
+    /// An EFI machine with an Intel CPU, an NVIDIA GPU and 8 GiB of memory.
+    fn facts() -> Facts {
+        let gpu = "/sys/bus/pci/devices/0000:01:00.0";
+        let system = FakeSystem::new()
+            .with_file("/sys/firmware/efi/fw_platform_size", "64\n")
+            .with_file(
+                "/proc/cpuinfo",
+                "processor\t: 0\nvendor_id\t: GenuineIntel\n",
+            )
+            .with_file("/proc/meminfo", "MemTotal:        8388608 kB\n")
+            .with_file(format!("{}/vendor", gpu), "0x10de\n")
+            .with_file(format!("{}/device", gpu), "0x2520\n")
+            .with_file(format!("{}/class", gpu), "0x030000\n");
+        Facts::detect(&system)
+    }
+
+    fn condition(yaml: &str) -> Condition {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn matches_detected_facts() {
+        let facts = facts();
+        let selections = Selections::new();
+        assert_eq!(facts.firmware, Firmware::Efi);
+        assert_eq!(facts.ram, 8192);
+        assert!(
+            condition("{firmware: efi, cpu: intel, gpu: nvidia, min_ram: 4096}")
+                .holds(&facts, &selections)
+        );
+        assert!(!condition("firmware: bios").holds(&facts, &selections));
+        assert!(!condition("cpu: amd").holds(&facts, &selections));
+        assert!(!condition("gpu: amd").holds(&facts, &selections));
+        assert!(!condition("min_ram: 16384").holds(&facts, &selections));
+        assert!(!condition("max_ram: 4096").holds(&facts, &selections));
+    }
+
+    #[test]
+    fn matches_selected_choices() {
+        let facts = facts();
+        let selections = Selections::from([("KERNEL".to_string(), vec!["Zen".to_string()])]);
+        assert!(condition("selected: {KERNEL: Zen}").holds(&facts, &selections));
+        assert!(!condition("selected: {KERNEL: Latest}").holds(&facts, &selections));
+        assert!(!condition("selected: {DESKTOP: GNOME}").holds(&facts, &selections));
+    }
+
+    #[test]
+    fn combines_conditions() {
+        let facts = facts();
+        let selections = Selections::new();
+        assert!(condition("any: [{gpu: amd}, {gpu: nvidia}]").holds(&facts, &selections));
+        assert!(!condition("any: [{gpu: amd}, {firmware: bios}]").holds(&facts, &selections));
+        assert!(condition("not: {firmware: bios}").holds(&facts, &selections));
+        assert!(!condition("not: {gpu: nvidia}").holds(&facts, &selections));
+        assert!(is_shown(None, &facts, &selections));
+        assert!(!is_shown(
+            Some(&condition("firmware: bios")),
+            &facts,
+            &selections
+        ));
+    }
+}
@@ -0,0 +1,666 @@
+use crate::{
+    bootloader::BootloaderConfig,
+    conditions::Condition,
+    form::Pattern,
+    migrate::migrate,
+    quirks::{HardwareModule, Quirk, QuirksConfig},
+    schema::untagged_variants,
+    text::Text,
+};
+use anyhow::{bail, Context, Result};
+use log::debug;
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, SubschemaValidation},
+    JsonSchema,
+};
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_yaml::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Layout version of the configs read by this version of Icicle, see [`migrate`].
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Choice names of `!list` steps, by list id.
+pub type ListChoices = BTreeMap<String, BTreeSet<String>>;
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+pub struct IcicleConfig {
+    /// Layout version of the config, older layouts are migrated when loaded
+    #[serde(default)]
+    pub version: u32,
+    pub distribution_name: String,
+    pub branding: String,
+    pub internet_check_url: String,
+    pub default_hostname: String,
+    /// Gettext domain translating the strings of the configs, Icicle's own by default
+    pub gettext_domain: Option<String>,
+    /// Directory holding the `gettext_domain` translations, relative to the config
+    /// directory, `locale` by default
+    pub localedir: Option<String>,
+    #[schemars(schema_with = "tagged_list::<ChoiceEnum>")]
+    pub choices: Vec<ChoiceEnum>,
+    /// Desktops from `desktops.yml`
+    #[serde(skip)]
+    pub desktops: Vec<Desktop>,
+    /// Hardware quirks from `quirks.yml`
+    #[serde(skip)]
+    pub quirks: Vec<Quirk>,
+    /// nixos-hardware modules from `quirks.yml`
+    #[serde(skip)]
+    pub nixos_hardware: Vec<HardwareModule>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum ChoiceEnum {
+    Configuration {
+        file: String,
+        #[serde(skip)]
+        config: Box<InstallationConfig>,
+    },
+    Live,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Default, Clone, Debug)]
+pub struct InstallationConfig {
+    /// Layout version of the config, older layouts are migrated when loaded
+    #[serde(default)]
+    pub version: u32,
+    pub config_id: String,
+    pub config_name: Text,
+    pub config_logo: String,
+    /// Id of the desktop installed when there is no `desktop` step,
+    /// and selected at first when there is one
+    pub desktop: Option<String>,
+    /// Id of the installation config this one is based on
+    pub extends: Option<String>,
+    /// Bootloader and EFI system partition, systemd-boot with the ESP at `/boot/efi`
+    /// when not given
+    pub bootloader: Option<BootloaderConfig>,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    /// Ids of the configs this one extends, base config first
+    #[serde(skip)]
+    pub parents: Vec<String>,
+}
+
+impl InstallationConfig {
+    /// Template directories of this config, base config first. Files in later
+    /// directories replace files with the same name in earlier ones.
+    pub fn template_dirs(&self, config_dir: &Path) -> Vec<PathBuf> {
+        self.parents
+            .iter()
+            .chain([&self.config_id])
+            .map(|id| config_dir.join(id))
+            .collect()
+    }
+
+    /// Choices of every `!list` step, by list id.
+    pub fn list_choices(&self) -> ListChoices {
+        let mut lists = ListChoices::new();
+        for step in &self.steps {
+            if let StepType::List { id, choices, .. } = &step.step {
+                lists
+                    .entry(id.to_string())
+                    .or_default()
+                    .extend(choices.iter().flat_map(|choice| choice.keys().cloned()));
+            }
+        }
+        lists
+    }
+
+    /// Applies `child` on top of this config. Steps of `child` replace steps of the
+    /// same kind and id, other steps are added after the step before them in `child`,
+    /// or before the summary if they come first.
+    fn extend(mut self, child: InstallationConfig) -> InstallationConfig {
+        let mut previous: Option<usize> = None;
+        for step in child.steps {
+            let i = if let Some(i) = self.steps.iter().position(|s| s.key() == step.key()) {
+                self.steps[i] = step;
+                i
+            } else {
+                let i = match previous {
+                    Some(previous) => previous + 1,
+                    None => self
+                        .steps
+                        .iter()
+                        .position(|s| matches!(s.step, StepType::Summary))
+                        .unwrap_or(self.steps.len()),
+                };
+                self.steps.insert(i, step);
+                i
+            };
+            previous = Some(i);
+        }
+        self.parents.push(self.config_id);
+        InstallationConfig {
+            version: child.version,
+            config_id: child.config_id,
+            config_name: child.config_name,
+            config_logo: child.config_logo,
+            desktop: child.desktop.or(self.desktop),
+            extends: child.extends,
+            bootloader: child.bootloader.or(self.bootloader),
+            steps: self.steps,
+            parents: self.parents,
+        }
+    }
+}
+
+/// An installation step, only shown while its `when` condition holds.
+///
+/// Tagged steps take the condition inline, others are wrapped:
+/// ```yml
+/// - !list
+///   when:
+///     gpu: nvidia
+///   ...
+/// - step: partitioning
+///   when:
+///     firmware: efi
+/// ```
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub step: StepType,
+    pub when: Option<Condition>,
+}
+
+impl Step {
+    /// Identifies the step when extending a config, the kind of step and its id.
+    fn key(&self) -> (&'static str, Option<&str>) {
+        match &self.step {
+            StepType::Welcome => ("welcome", None),
+            StepType::Location => ("location", None),
+            StepType::Keyboard => ("keyboard", None),
+            StepType::User { .. } => ("user", None),
+            StepType::List { id, .. } => ("list", Some(id)),
+            StepType::Form { id, .. } => ("form", Some(id)),
+            StepType::Desktop { .. } => ("desktop", None),
+            StepType::Nvidia => ("nvidia", None),
+            StepType::Bootloader => ("bootloader", None),
+            StepType::Partitioning => ("partitioning", None),
+            StepType::Manual => ("manual", None),
+            StepType::Summary => ("summary", None),
+        }
+    }
+}
+
+impl JsonSchema for Step {
+    fn schema_name() -> String {
+        "Step".to_string()
+    }
+
+    /// YAML tags can not be expressed in JSON Schema, so tagged steps are validated
+    /// by their contents alone.
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let when = gen.subschema_for::<Condition>();
+        let mut variants = untagged_variants(StepType::json_schema(gen));
+        let step = any_of(variants.clone());
+        for variant in &mut variants {
+            add_property(variant, "when", &when);
+        }
+        let mut wrapped = SchemaObject {
+            instance_type: Some(InstanceType::Object.into()),
+            ..Default::default()
+        };
+        let object = wrapped.object();
+        object.properties.insert("step".to_string(), step);
+        object.properties.insert("when".to_string(), when);
+        object.required.insert("step".to_string());
+        variants.push(wrapped.into());
+        any_of(variants)
+    }
+}
+
+impl<'de> Deserialize<'de> for Step {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let mut value = Value::deserialize(deserializer)?;
+        let when = match &mut value {
+            Value::Tagged(tagged) => match &mut tagged.value {
+                Value::Mapping(mapping) => mapping.remove("when"),
+                // A tag without options, e.g. `- !user`
+                Value::Null => {
+                    tagged.value = Value::Mapping(Default::default());
+                    None
+                }
+                _ => None,
+            },
+            Value::Mapping(mapping) => {
+                let when = mapping.remove("when");
+                value = mapping
+                    .remove("step")
+                    .ok_or_else(|| D::Error::missing_field("step"))?;
+                when
+            }
+            _ => None,
+        };
+        Ok(Step {
+            step: serde_yaml::from_value(value).map_err(D::Error::custom)?,
+            when: when
+                .map(serde_yaml::from_value)
+                .transpose()
+                .map_err(D::Error::custom)?,
+        })
+    }
+}
+
+impl Serialize for Step {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match &self.when {
+            Some(when) => {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("step", &self.step)?;
+                map.serialize_entry("when", when)?;
+                map.end()
+            }
+            None => self.step.serialize(serializer),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum StepType {
+    Welcome,
+    Location,
+    Keyboard,
+    User {
+        root: Option<bool>,
+        hostname: Option<bool>,
+    },
+    List {
+        id: String,
+        multiple: bool,
+        required: bool,
+        title: Text,
+        choices: Vec<HashMap<String, Choice>>,
+    },
+    Form {
+        id: String,
+        title: Text,
+        fields: Vec<Field>,
+    },
+    Desktop {
+        /// Ids of the desktops to choose from, all of them by default
+        desktops: Option<Vec<String>>,
+    },
+    /// Driver and PRIME mode of hybrid graphics machines, hidden on others
+    Nvidia,
+    /// Bootloader and EFI system partition, hidden on BIOS machines
+    Bootloader,
+    Partitioning,
+    Manual,
+    Summary,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
+pub struct Choice {
+    pub description: Option<Text>,
+    pub packages: Option<Vec<String>>,
+    pub config: Option<String>,
+    /// The choice is only offered while this holds
+    pub when: Option<Condition>,
+    /// Selected when the list is first shown
+    #[serde(default)]
+    pub default: bool,
+    /// Choices of the same list that are selected along with this one
+    pub requires: Option<Vec<String>>,
+    /// Choices of the same list that can not be selected along with this one
+    pub conflicts: Option<Vec<String>>,
+    /// Files from the `modules` directory of the templates, written next to
+    /// `configuration.nix` and added to `@IMPORTS@`
+    pub modules: Option<Vec<String>>,
+    /// Inputs added to `@FLAKEINPUTS@` in `flake.nix`, by name
+    pub flake_inputs: Option<BTreeMap<String, FlakeInput>>,
+    /// Modules added to `@FLAKEMODULES@` in `flake.nix`,
+    /// e.g. `inputs.home-manager.nixosModules.home-manager`
+    pub flake_modules: Option<Vec<String>>,
+    /// Snippets added to other placeholders, by placeholder name without the `@`s
+    pub targets: Option<BTreeMap<String, String>>,
+}
+
+/// An input a choice adds to `flake.nix`.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+pub struct FlakeInput {
+    pub url: String,
+    /// Inputs of this input that follow the input of the same name, e.g. `nixpkgs`
+    #[serde(default)]
+    pub follows: Vec<String>,
+}
+
+/// A field of a `!form` step, substituted as `@id@` in the templates.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
+pub struct Field {
+    pub id: String,
+    pub label: Text,
+    pub description: Option<Text>,
+    #[serde(flatten)]
+    pub kind: FieldKind,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum FieldKind {
+    Text {
+        default: Option<String>,
+        /// Regular expression the whole value must match
+        regex: Option<Pattern>,
+    },
+    Number {
+        default: Option<i64>,
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    Toggle {
+        #[serde(default)]
+        default: bool,
+    },
+    Dropdown {
+        options: Vec<String>,
+        default: Option<String>,
+    },
+}
+
+/// Adds an optional property to object schemas, including those in `anyOf`.
+fn add_property(schema: &mut Schema, name: &str, property: &Schema) {
+    if let Schema::Object(schema) = schema {
+        if let Some(object) = &mut schema.object {
+            object.properties.insert(name.to_string(), property.clone());
+        }
+        if let Some(any_of) = schema.subschemas.as_mut().and_then(|s| s.any_of.as_mut()) {
+            for schema in any_of {
+                add_property(schema, name, property);
+            }
+        }
+    }
+}
+
+fn any_of(schemas: Vec<Schema>) -> Schema {
+    SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation {
+            any_of: Some(schemas),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// Schema of a list of the YAML tagged enum `T`.
+fn tagged_list<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    let mut list = SchemaObject::default();
+    list.array().items = Some(any_of(untagged_variants(T::json_schema(gen))).into());
+    list.into()
+}
+
+/// A desktop environment and how to configure it.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+pub struct Desktop {
+    pub id: String,
+    pub name: Text,
+    pub description: Option<Text>,
+    /// Substituted for `@DESKTOP@`, enables the desktop and its display manager
+    pub config: String,
+    /// Substituted for `@AUTOLOGIN@` with `@USERNAME@` set
+    pub autologin: Option<String>,
+    /// Substituted for `@KEYBOARD@` with `@LAYOUT@` and `@VARIANT@` set
+    pub keyboard: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
+pub struct DesktopsConfig {
+    /// Layout version of the config, older layouts are migrated when loaded
+    #[serde(default)]
+    pub version: u32,
+    pub desktops: Vec<Desktop>,
+}
+
+/// Parses `config.yml`, every installation config it references, `desktops.yml`
+/// and `quirks.yml` from `config_dir`.
+pub fn parse_config(config_dir: &Path) -> Result<IcicleConfig> {
+    let path = config_dir.join("config.yml");
+    debug!("Parsing config {}", path.display());
+    let mut config: IcicleConfig = parse_versioned(&path)?;
+    for choice in &mut config.choices {
+        match choice {
+            ChoiceEnum::Configuration { file, config } => {
+                **config = parse_installation_config(config_dir, file, &mut vec![])?;
+            }
+            ChoiceEnum::Live => {}
+        }
+    }
+    let path = config_dir.join("desktops.yml");
+    if path.exists() {
+        config.desktops = parse_versioned::<DesktopsConfig>(&path)?.desktops;
+    }
+    let path = config_dir.join("quirks.yml");
+    if path.exists() {
+        let quirks = parse_versioned::<QuirksConfig>(&path)?;
+        config.quirks = quirks.quirks;
+        config.nixos_hardware = quirks.nixos_hardware;
+    }
+    Ok(config)
+}
+
+/// Parses a config file, migrating it from older layouts first.
+fn parse_versioned<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let f =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut value: Value =
+        serde_yaml::from_str(&f).with_context(|| format!("Failed to parse {}", path.display()))?;
+    migrate(&mut value, path)?;
+    serde_yaml::from_value(value).with_context(|| format!("Invalid config {}", path.display()))
+}
+
+/// Parses the installation config in `file`, applied on top of the config it extends.
+/// `children` are the paths of the configs extending it, to catch cycles.
+fn parse_installation_config(
+    config_dir: &Path,
+    file: &str,
+    children: &mut Vec<PathBuf>,
+) -> Result<InstallationConfig> {
+    debug!("Parsing installation config {}", file);
+    let path = config_dir.join(file);
+    let canonical =
+        fs::canonicalize(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if children.contains(&canonical) {
+        bail!("Installation config {} extends itself", file);
+    }
+    children.push(canonical);
+    let config: InstallationConfig = parse_versioned(&path)?;
+    match &config.extends {
+        Some(id) => {
+            // Base configs are found by their id, which must not lead out of config_dir
+            if id.is_empty() || id.starts_with('.') || id.contains('/') {
+                bail!(
+                    "Installation config {} extends invalid config id {}",
+                    file,
+                    id
+                );
+            }
+            let parent = parse_installation_config(config_dir, &format!("{}.yml", id), children)?;
+            if parent.config_id != *id {
+                bail!(
+                    "Installation config {}.yml extended by {} has config_id {}",
+                    id,
+                    file,
+                    parent.config_id
+                );
+            }
+            Ok(parent.extend(config))
+        }
+        None => Ok(config),
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BrandingConfig {
+    pub slides: Vec<Slide>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Slide {
+    pub title: Text,
+    pub subtitle: Text,
+    pub image: String,
+}
+
+pub fn parse_branding(config_dir: &Path, brand: &str) -> Result<BrandingConfig> {
+    let f = fs::read_to_string(config_dir.join("branding").join(brand).join("slides.yml"))?;
+    let config: BrandingConfig = serde_yaml::from_str(&f)?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `files` to a fresh config directory.
+    fn config_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("icicle-config-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    const BASIC: &str = r#"
+version: 1
+config_name: Basic
+config_logo: emoji-symbols-symbolic
+config_id: basic
+desktop: gnome
+steps:
+- welcome
+- location
+- !list
+  id: KERNEL
+  multiple: false
+  required: true
+  title: Kernel
+  choices:
+  - Latest: {}
+- partitioning
+- summary
+"#;
+
+    fn step_keys(config: &InstallationConfig) -> Vec<(&'static str, Option<&str>)> {
+        config.steps.iter().map(|step| step.key()).collect()
+    }
+
+    #[test]
+    fn extends_base_config() {
+        let advanced = r#"
+version: 1
+config_name: Advanced
+config_logo: preferences-system-symbolic
+config_id: advanced
+extends: basic
+steps:
+- location
+- !desktop
+- !list
+  id: KERNEL
+  multiple: false
+  required: true
+  title: Kernel
+  choices:
+  - Latest: {}
+  - Zen: {}
+- !user
+  root: true
+"#;
+        let dir = config_dir(
+            "extends",
+            &[("basic.yml", BASIC), ("advanced.yml", advanced)],
+        );
+        let config = parse_installation_config(&dir, "advanced.yml", &mut vec![]).unwrap();
+        assert_eq!(config.config_id, "advanced");
+        assert_eq!(config.desktop.as_deref(), Some("gnome"));
+        assert_eq!(config.parents, ["basic"]);
+        assert_eq!(
+            config.template_dirs(Path::new("/etc/icicle")),
+            [
+                PathBuf::from("/etc/icicle/basic"),
+                PathBuf::from("/etc/icicle/advanced")
+            ]
+        );
+        assert_eq!(
+            step_keys(&config),
+            [
+                ("welcome", None),
+                ("location", None),
+                ("desktop", None),
+                ("list", Some("KERNEL")),
+                ("user", None),
+                ("partitioning", None),
+                ("summary", None),
+            ]
+        );
+        assert_eq!(
+            config.list_choices()["KERNEL"],
+            BTreeSet::from(["Latest".to_string(), "Zen".to_string()])
+        );
+    }
+
+    #[test]
+    fn rejects_extends_cycles() {
+        let basic = BASIC.replace("desktop: gnome", "extends: advanced");
+        let advanced =
+            "version: 1\nconfig_name: A\nconfig_logo: x\nconfig_id: advanced\nextends: basic\n";
+        let dir = config_dir(
+            "cycle",
+            &[("basic.yml", &basic), ("advanced.yml", advanced)],
+        );
+        let error = parse_installation_config(&dir, "advanced.yml", &mut vec![]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Installation config advanced.yml extends itself"
+        );
+    }
+
+    #[test]
+    fn rejects_extends_that_are_not_config_ids() {
+        let child = |extends: &str| {
+            format!(
+                "version: 1\nconfig_name: C\nconfig_logo: x\nconfig_id: child\nextends: {}\n",
+                extends
+            )
+        };
+        let dir = config_dir(
+            "ids",
+            &[
+                ("basic.yml", BASIC),
+                ("renamed.yml", BASIC),
+                ("escape.yml", &child("../basic")),
+                ("hidden.yml", &child(".basic")),
+                ("renamed-child.yml", &child("renamed")),
+            ],
+        );
+        for file in ["escape.yml", "hidden.yml"] {
+            let error = parse_installation_config(&dir, file, &mut vec![]).unwrap_err();
+            assert!(
+                error.to_string().contains("extends invalid config id"),
+                "{}",
+                error
+            );
+        }
+        let error = parse_installation_config(&dir, "renamed-child.yml", &mut vec![]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Installation config renamed.yml extended by renamed-child.yml has config_id basic"
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Facts about the machine being installed on, used to evaluate `when:` conditions.
+
+use crate::{is_efi, system::System};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Firmware {
+    Efi,
+    Bios,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuVendor {
+    Intel,
+    Amd,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GpuVendor {
+    Nvidia,
+    Amd,
+    Intel,
+}
+
+/// Secure Boot state of the firmware, as found in the EFI variables.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SecureBoot {
+    /// Disabled, or a BIOS machine
+    #[default]
+    Disabled,
+    Enabled,
+    /// No platform key is enrolled, so new keys can be enrolled from the installed system
+    SetupMode,
+}
+
+/// A device on the PCI bus, as listed in `/sys/bus/pci/devices`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PciDevice {
+    /// Slot name, e.g. `0000:01:00.0`
+    pub slot: String,
+    pub vendor: u16,
+    pub device: u16,
+    /// Class code, e.g. `0x030000` for a VGA controller
+    pub class: u32,
+}
+
+impl PciDevice {
+    /// Display controllers, VGA, 3D and others.
+    pub fn is_gpu(&self) -> bool {
+        self.class >> 16 == 0x03
+    }
+}
+
+/// A device on the USB bus, as listed in `/sys/bus/usb/devices`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbDevice {
+    pub vendor: u16,
+    pub product: u16,
+}
+
+/// Fields of `/sys/class/dmi/id` readable without root.
+pub const DMI_FIELDS: [&str; 10] = [
+    "bios_vendor",
+    "bios_version",
+    "board_name",
+    "board_vendor",
+    "board_version",
+    "chassis_vendor",
+    "product_family",
+    "product_name",
+    "product_version",
+    "sys_vendor",
+];
+
+#[derive(Debug, Clone)]
+pub struct Facts {
+    pub firmware: Firmware,
+    pub secure_boot: SecureBoot,
+    pub cpu: Option<CpuVendor>,
+    pub gpus: Vec<GpuVendor>,
+    /// Installed memory in MiB
+    pub ram: u64,
+    pub pci_devices: Vec<PciDevice>,
+    pub usb_devices: Vec<UsbDevice>,
+    /// DMI strings by field name, e.g. `sys_vendor: QEMU`
+    pub dmi: BTreeMap<String, String>,
+}
+
+impl Facts {
+    pub fn detect(system: &dyn System) -> Self {
+        let pci_devices = pci_devices(system);
+        Facts {
+            firmware: if is_efi(system) {
+                Firmware::Efi
+            } else {
+                Firmware::Bios
+            },
+            secure_boot: secure_boot(system),
+            cpu: cpu_vendor(system),
+            gpus: pci_devices
+                .iter()
+                .filter(|dev| dev.is_gpu())
+                .filter_map(|dev| match dev.vendor {
+                    0x10de => Some(GpuVendor::Nvidia),
+                    0x1002 => Some(GpuVendor::Amd),
+                    0x8086 => Some(GpuVendor::Intel),
+                    _ => None,
+                })
+                .collect(),
+            ram: ram(system).unwrap_or(0),
+            pci_devices,
+            usb_devices: usb_devices(system),
+            dmi: dmi(system),
+        }
+    }
+}
+
+/// Every device on the PCI bus, sorted by slot.
+pub fn pci_devices(system: &dyn System) -> Vec<PciDevice> {
+    let mut devices = system
+        .read_dir(Path::new("/sys/bus/pci/devices"))
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|path| {
+            Some(PciDevice {
+                slot: path.file_name()?.to_string_lossy().to_string(),
+                vendor: read_hex(system, &path.join("vendor"))? as u16,
+                device: read_hex(system, &path.join("device"))? as u16,
+                class: read_hex(system, &path.join("class"))?,
+            })
+        })
+        .collect::<Vec<_>>();
+    devices.sort_by(|a, b| a.slot.cmp(&b.slot));
+    devices
+}
+
+/// Every device on the USB bus, including hubs. Interfaces are skipped.
+pub fn usb_devices(system: &dyn System) -> Vec<UsbDevice> {
+    system
+        .read_dir(Path::new("/sys/bus/usb/devices"))
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|path| {
+            Some(UsbDevice {
+                vendor: read_hex(system, &path.join("idVendor"))? as u16,
+                product: read_hex(system, &path.join("idProduct"))? as u16,
+            })
+        })
+        .collect()
+}
+
+/// The readable DMI strings of the machine, by field name.
+pub fn dmi(system: &dyn System) -> BTreeMap<String, String> {
+    DMI_FIELDS
+        .iter()
+        .filter_map(|field| {
+            let path = Path::new("/sys/class/dmi/id").join(field);
+            let value = system.read_to_string(&path).ok()?;
+            Some((field.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Secure Boot state from the `SecureBoot` and `SetupMode` EFI variables.
+pub fn secure_boot(system: &dyn System) -> SecureBoot {
+    // Four attribute bytes precede the value of an EFI variable
+    let variable = |name: &str| {
+        let path = Path::new("/sys/firmware/efi/efivars")
+            .join(format!("{}-8be4df61-93ca-11d2-aa0d-00e098032b8c", name));
+        system
+            .read_to_string(&path)
+            .ok()
+            .and_then(|value| value.as_bytes().get(4).copied())
+            == Some(1)
+    };
+    if variable("SetupMode") {
+        SecureBoot::SetupMode
+    } else if variable("SecureBoot") {
+        SecureBoot::Enabled
+    } else {
+        SecureBoot::Disabled
+    }
+}
+
+fn read_hex(system: &dyn System, path: &Path) -> Option<u32> {
+    let value = system.read_to_string(path).ok()?;
+    u32::from_str_radix(value.trim().trim_start_matches("0x"), 16).ok()
+}
+
+fn cpu_vendor(system: &dyn System) -> Option<CpuVendor> {
+    let cpuinfo = system.read_to_string(Path::new("/proc/cpuinfo")).ok()?;
+    let vendor = cpuinfo
+        .lines()
+        .find(|line| line.starts_with("vendor_id"))?
+        .split(':')
+        .nth(1)?
+        .trim()
+        .to_string();
+    match vendor.as_str() {
+        "GenuineIntel" => Some(CpuVendor::Intel),
+        "AuthenticAMD" => Some(CpuVendor::Amd),
+        _ => None,
+    }
+}
+
+fn ram(system: &dyn System) -> Option<u64> {
+    let meminfo = system.read_to_string(Path::new("/proc/meminfo")).ok()?;
+    let kib = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemTotal:"))?
+        .split_whitespace()
+        .nth(1)?
+        .parse::<u64>()
+        .ok()?;
+    Some(kib / 1024)
+}
@@ -0,0 +1,191 @@
+//! Values of `!form` fields, their validation and how they end up in the templates.
+
+use crate::config::{Field, FieldKind};
+use regex::Regex;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Text(String),
+    Number(i64),
+    Toggle(bool),
+}
+
+impl FieldValue {
+    /// The value as a Nix expression, text is quoted and escaped.
+    pub fn to_nix(&self) -> String {
+        match self {
+            FieldValue::Text(text) => nix_string(text),
+            FieldValue::Number(number) => number.to_string(),
+            FieldValue::Toggle(toggle) => toggle.to_string(),
+        }
+    }
+}
+
+/// A regular expression the whole value of a text field must match. It is compiled when
+/// the config is loaded, so an invalid one is reported as an error in the config.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Self, regex::Error> {
+        Ok(Pattern {
+            source: source.to_string(),
+            regex: Regex::new(&format!("^(?:{})$", source))?,
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Pattern::new(&source)
+            .map_err(|e| D::Error::custom(format!("Invalid regex {}: {}", source, e)))
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl JsonSchema for Pattern {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        String::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+/// Quotes `text` as a Nix string literal.
+pub fn nix_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            // Keep `${` from starting an interpolation
+            '$' if chars.peek() == Some(&'{') => escaped.push_str("\\$"),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+impl Field {
+    pub fn default_value(&self) -> FieldValue {
+        match &self.kind {
+            FieldKind::Text { default, .. } => {
+                FieldValue::Text(default.clone().unwrap_or_default())
+            }
+            FieldKind::Number { default, min, .. } => {
+                FieldValue::Number(default.or(*min).unwrap_or(0))
+            }
+            FieldKind::Toggle { default } => FieldValue::Toggle(*default),
+            FieldKind::Dropdown { options, default } => FieldValue::Text(
+                default
+                    .clone()
+                    .or_else(|| options.first().cloned())
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+
+    /// Whether `value` is of the right kind and passes the field's validation.
+    pub fn is_valid(&self, value: &FieldValue) -> bool {
+        match (&self.kind, value) {
+            (FieldKind::Text { regex, .. }, FieldValue::Text(text)) => {
+                regex.as_ref().map_or(true, |regex| regex.is_match(text))
+            }
+            (FieldKind::Number { min, max, .. }, FieldValue::Number(number)) => {
+                min.map_or(true, |min| *number >= min) && max.map_or(true, |max| *number <= max)
+            }
+            (FieldKind::Toggle { .. }, FieldValue::Toggle(_)) => true,
+            (FieldKind::Dropdown { options, .. }, FieldValue::Text(text)) => options.contains(text),
+            _ => false,
+        }
+    }
+}
+
+/// Default values of `fields`, by field id.
+pub fn default_values(fields: &[Field]) -> HashMap<String, FieldValue> {
+    fields
+        .iter()
+        .map(|field| (field.id.to_string(), field.default_value()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(yaml: &str) -> Field {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn quotes_nix_strings() {
+        assert_eq!(nix_string("Alice"), r#""Alice""#);
+        assert_eq!(nix_string("say \"hi\"\n\\o/"), r#""say \"hi\"\n\\o/""#);
+        assert_eq!(
+            nix_string("${pkgs.hello} $HOME"),
+            r#""\${pkgs.hello} $HOME""#
+        );
+        assert_eq!(FieldValue::Text("a\tb".to_string()).to_nix(), r#""a\tb""#);
+        assert_eq!(FieldValue::Number(-3).to_nix(), "-3");
+        assert_eq!(FieldValue::Toggle(true).to_nix(), "true");
+    }
+
+    #[test]
+    fn validates_values() {
+        let text = field("{id: EMAIL, label: E-mail, type: text, regex: '[^@]+@[^@]+'}");
+        assert!(text.is_valid(&FieldValue::Text("alice@example.org".to_string())));
+        // The whole value has to match
+        assert!(!text.is_valid(&FieldValue::Text("alice@example.org@".to_string())));
+        assert!(!text.is_valid(&FieldValue::Number(1)));
+
+        let number = field("{id: SWAP, label: Swap, type: number, min: 0, max: 64}");
+        assert_eq!(number.default_value(), FieldValue::Number(0));
+        assert!(number.is_valid(&FieldValue::Number(64)));
+        assert!(!number.is_valid(&FieldValue::Number(65)));
+        assert!(!number.is_valid(&FieldValue::Number(-1)));
+
+        let dropdown = field("{id: SHELL, label: Shell, type: dropdown, options: [bash, zsh]}");
+        assert_eq!(
+            dropdown.default_value(),
+            FieldValue::Text("bash".to_string())
+        );
+        assert!(dropdown.is_valid(&FieldValue::Text("zsh".to_string())));
+        assert!(!dropdown.is_valid(&FieldValue::Text("fish".to_string())));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns_when_loading() {
+        let error = serde_yaml::from_str::<Field>("{id: X, label: X, type: text, regex: '(a'}")
+            .unwrap_err();
+        assert!(error.to_string().contains("Invalid regex (a"), "{}", error);
+    }
+}
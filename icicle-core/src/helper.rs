@@ -0,0 +1,70 @@
+use crate::protocol::{decode, ErrorCode, HelperError, Output, Response, WithCode};
+use log::{debug, error, info};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// Runs `helper` through pkexec with `input` on stdin and waits for its response.
+pub fn run_helper(
+    helper: &Path,
+    args: &[&str],
+    input: Option<&[u8]>,
+) -> Result<Response, HelperError> {
+    let mut child = Command::new("pkexec")
+        .arg(helper)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .code(ErrorCode::Io, "Failed to run icicle-helper")?;
+
+    // Dropping stdin closes it, so the helper sees the end of the input
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Some(input) = input {
+            stdin
+                .write_all(input)
+                .code(ErrorCode::Io, "Failed to write to icicle-helper")?;
+        }
+    }
+
+    let mut response = None;
+    let stdout = child
+        .stdout
+        .take()
+        .code(ErrorCode::Io, "Failed to get icicle-helper stdout")?;
+    for line in BufReader::new(stdout).lines() {
+        let line = line.code(ErrorCode::Io, "Failed to read icicle-helper output")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match decode(&line) {
+            Ok(Output::Progress(progress)) => info!("icicle-helper: {:?}", progress),
+            Ok(Output::Log(message)) => debug!("icicle-helper: {}", message),
+            Ok(Output::Response(r)) => response = Some(Ok(r)),
+            Ok(Output::Error(e)) => response = Some(Err(e)),
+            Err(e) => error!("{}: {}", e, line),
+        }
+    }
+
+    let mut stderr = String::new();
+    if let Some(mut childstderr) = child.stderr.take() {
+        let _ = childstderr.read_to_string(&mut stderr);
+    }
+    let status = child
+        .wait()
+        .code(ErrorCode::Io, "Failed to wait for icicle-helper")?;
+    match response {
+        Some(response) => response,
+        // pkexec exits with 126 if the dialog was dismissed and 127 if authorization failed
+        None if matches!(status.code(), Some(126 | 127)) => {
+            Err(HelperError::new(ErrorCode::NotAuthorized, stderr.trim()))
+        }
+        None => Err(HelperError::new(
+            ErrorCode::Io,
+            format!("icicle-helper exited without a response: {}", stderr.trim()),
+        )),
+    }
+}
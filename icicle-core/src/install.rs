@@ -0,0 +1,580 @@
+use crate::{
+    answers::Answers,
+    bootloader::PKI_BUNDLE,
+    config::{InstallationConfig, ListChoices},
+    is_efi,
+    mounts::{check_mounts, target_mounts, Mount},
+    protocol::{
+        Envelope, ImageFormat, PartitionRequest, PartitionSchema, Response, RootFilesystem,
+        TARGET_ROOT,
+    },
+    render::{render_dir, RenderContext},
+    system::{Cmd, System},
+    Paths,
+};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use std::{path::PathBuf, sync::Arc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallStep {
+    Clear,
+    Partition,
+    GenerateConfig,
+    MakeConfig,
+    CreateKeys,
+    Install,
+    SetPasswords,
+}
+
+/// Disk image installed to instead of the disks in the answers.
+#[derive(Debug, Clone)]
+pub struct ImageTarget {
+    pub path: PathBuf,
+    /// Size in bytes, required if the image does not exist yet
+    pub size: Option<u64>,
+    pub format: ImageFormat,
+}
+
+/// Runs the installation of a single installation config with the given answers.
+pub struct Installer {
+    system: Arc<dyn System>,
+    /// Template directories, base config first
+    template_dirs: Vec<PathBuf>,
+    /// Choices of every `!list` step of the config, for the choice flags
+    lists: ListChoices,
+    answers: Answers,
+    image: Option<ImageTarget>,
+}
+
+impl Installer {
+    pub fn new(
+        system: Arc<dyn System>,
+        paths: Paths,
+        config: &InstallationConfig,
+        answers: Answers,
+    ) -> Self {
+        Installer {
+            template_dirs: config.template_dirs(&paths.config_dir()),
+            lists: config.list_choices(),
+            system,
+            answers,
+            image: None,
+        }
+    }
+
+    /// Installs to a full disk layout on `image` instead of the partitions in the answers.
+    pub fn with_image(mut self, image: ImageTarget) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    pub fn answers(&self) -> &Answers {
+        &self.answers
+    }
+
+    /// Runs every step up to `nixos-install` and returns its command, so front ends
+    /// can run it wherever they want, e.g. inside a terminal widget.
+    pub fn prepare(&self, mut progress: impl FnMut(InstallStep)) -> Result<Cmd> {
+        let answers = if self.premounted() {
+            let mounts = self.check_premounted()?;
+            let mut answers = self.answers.clone();
+            if mounts
+                .iter()
+                .any(|mount| mount.mountpoint == "/" && mount.fstype == "zfs")
+            {
+                answers.filesystem = RootFilesystem::Zfs;
+            }
+            answers
+        } else {
+            if self.image.is_some() && !is_efi(self.system.as_ref()) {
+                // GRUB would be installed to the loop device, which is gone once detached
+                return Err(anyhow!(
+                    "Disk images can only be installed from EFI machines"
+                ));
+            }
+            progress(InstallStep::Clear);
+            self.clear().context("Failed to clear target root")?;
+
+            progress(InstallStep::Partition);
+            self.partition().context("Failed to partition")?
+        };
+
+        progress(InstallStep::GenerateConfig);
+        self.system
+            .run(&Cmd::privileged("nixos-generate-config").args(["--root", TARGET_ROOT]))
+            .context("Failed to generate base config")?;
+
+        progress(InstallStep::MakeConfig);
+        self.makeconfig(&answers).context("Failed to make config")?;
+
+        if answers
+            .bootloader
+            .uses_lanzaboote(is_efi(self.system.as_ref()))
+        {
+            progress(InstallStep::CreateKeys);
+            self.create_keys()
+                .context("Failed to create Secure Boot keys")?;
+        }
+
+        progress(InstallStep::Install);
+        self.install_command()
+    }
+
+    /// Runs the steps after `nixos-install` finished successfully, and detaches an image
+    /// target.
+    pub fn finish(&self, mut progress: impl FnMut(InstallStep)) -> Result<()> {
+        progress(InstallStep::SetPasswords);
+        let user = self.answers.user.as_ref().context("No user found")?;
+        self.chpasswd(&format!("{}:{}", user.username, user.password))
+            .context("Failed to set user password")?;
+        if let Some(rootpasswd) = &user.rootpassword {
+            self.chpasswd(&format!("root:{}", rootpasswd))
+                .context("Failed to set root password")?;
+        }
+        if !self.premounted() && self.answers.zfs() {
+            // The installed system only imports pools that were exported without forcing
+            self.system
+                .helper(&["unmount"], None)
+                .context("Failed to export ZFS pool")?;
+        }
+        if let Some(image) = &self.image {
+            self.detach(image).context("Failed to detach image")?;
+        }
+        Ok(())
+    }
+
+    /// Runs the whole pipeline including `nixos-install`, for front ends without a terminal.
+    /// An image target is detached afterwards, even if the installation failed.
+    pub fn run(&self, mut progress: impl FnMut(InstallStep)) -> Result<()> {
+        let result = self.run_steps(&mut progress);
+        if let (Err(_), Some(image)) = (&result, &self.image) {
+            // Detaching is part of finishing, this only cleans up after a failure
+            let _ = self.detach(image);
+        }
+        result
+    }
+
+    fn run_steps(&self, mut progress: impl FnMut(InstallStep)) -> Result<()> {
+        let cmd = self.prepare(&mut progress)?;
+        let output = self
+            .system
+            .run(&cmd)
+            .context("Failed to run nixos-install")?;
+        if !output.success() {
+            return Err(anyhow!(
+                "nixos-install failed with {:?}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        self.finish(progress)
+    }
+
+    /// Whether the user mounted the target themselves, so it must be left untouched.
+    fn premounted(&self) -> bool {
+        self.image.is_none()
+            && matches!(
+                self.answers.partitions,
+                Some(PartitionSchema::Premounted { .. })
+            )
+    }
+
+    fn check_premounted(&self) -> Result<Vec<Mount>> {
+        let mounts = target_mounts(self.system.as_ref())?;
+        let esp = self.answers.bootloader.esp.mountpoint();
+        let problems = check_mounts(&mounts, is_efi(self.system.as_ref()).then_some(esp));
+        if !problems.is_empty() {
+            return Err(anyhow!("Target root is not ready: {:?}", problems));
+        }
+        Ok(mounts)
+    }
+
+    fn clear(&self) -> Result<()> {
+        // Also closes the volume group and LUKS container left open by an earlier attempt
+        self.system.helper(&["unmount"], None)?;
+        self.system
+            .run(&Cmd::privileged("rm").args(["-rf", TARGET_ROOT]))?;
+        Ok(())
+    }
+
+    /// Partitions and mounts the target, returning the answers as they apply to it.
+    fn partition(&self) -> Result<Answers> {
+        let mut answers = self.answers.clone();
+        if let Some(image) = &self.image {
+            // The helper partitions the loop device it attaches the image to, which is
+            // never written to the config. The image boots through the removable
+            // fallback path on other machines and leaves this one's EFI variables alone
+            answers.partitions = Some(PartitionSchema::FullDisk(
+                image.path.to_string_lossy().to_string(),
+            ));
+            answers.portable = true;
+        }
+        let schema = answers
+            .partitions
+            .clone()
+            .context("No partitions specified")?;
+        let request = PartitionRequest {
+            schema,
+            esp: answers.bootloader.esp.mountpoint().to_string(),
+            filesystem: answers.filesystem,
+            layout: answers.layout.clone(),
+        };
+        // The request holds the passphrase of an encrypted disk, which is not logged
+        debug!("Executing partition with schema: {:?}", request.schema);
+        let request = serde_json::to_string(&Envelope::new(request))?;
+
+        let mut args = vec!["partition".to_string()];
+        if let Some(image) = &self.image {
+            args.push("--image".to_string());
+            args.push(image.path.to_string_lossy().to_string());
+            args.push("--format".to_string());
+            args.push(image.format.to_string());
+            if let Some(size) = image.size {
+                args.push("--size".to_string());
+                args.push(size.to_string());
+            }
+        }
+        let args = args.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        if let Response::Attached { device } =
+            self.system.helper(&args, Some(request.as_bytes()))?
+        {
+            info!("Installing to image through {}", device);
+        }
+        Ok(answers)
+    }
+
+    fn detach(&self, image: &ImageTarget) -> Result<()> {
+        self.system.helper(
+            &[
+                "detach",
+                "--image",
+                &image.path.to_string_lossy(),
+                "--format",
+                &image.format.to_string(),
+            ],
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn makeconfig(&self, answers: &Answers) -> Result<()> {
+        let ctx = RenderContext {
+            lists: self.lists.clone(),
+            ..RenderContext::detect(self.system.as_ref())?
+        };
+        let files = render_dir(self.system.as_ref(), &self.template_dirs, answers, &ctx)?;
+        for (filename, config) in files {
+            info!("Writing /etc/nixos/{}", filename);
+            self.system.helper(
+                &["write-file", "--path", &format!("etc/nixos/{}", filename)],
+                Some(config.as_bytes()),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Creates the Secure Boot keys in the target, lanzaboote signs with them during
+    /// `nixos-install` already.
+    fn create_keys(&self) -> Result<()> {
+        let bundle = format!("{}{}", TARGET_ROOT, PKI_BUNDLE);
+        let output = self.system.run(&Cmd::privileged("sbctl").args([
+            "create-keys",
+            "--export",
+            &format!("{}/keys", bundle),
+            "--database-path",
+            &format!("{}/GUID", bundle),
+        ]))?;
+        if !output.success() {
+            return Err(anyhow!(
+                "sbctl failed with {:?}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    fn install_command(&self) -> Result<Cmd> {
+        let hostname = self.answers.hostname().context("No hostname found")?;
+        Ok(Cmd::privileged("nixos-install").args([
+            "--root",
+            TARGET_ROOT,
+            "--no-root-passwd",
+            "--no-channel-copy",
+            "--flake",
+            &format!("{}/etc/nixos#{}", TARGET_ROOT, hostname),
+        ]))
+    }
+
+    fn chpasswd(&self, entry: &str) -> Result<()> {
+        let output = self.system.run(
+            &Cmd::privileged("nixos-enter")
+                .args(["--root", TARGET_ROOT, "-c", "chpasswd"])
+                .stdin(entry),
+        )?;
+        if !output.success() {
+            return Err(anyhow!(
+                "chpasswd failed with {:?}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        answers::UserConfig,
+        protocol::{ErrorCode, HelperError},
+        system::{Call, FakeSystem},
+    };
+
+    fn fake_system(efi: bool) -> FakeSystem {
+        let system = FakeSystem::new()
+            .with_file(
+                "/etc/icicle/test/configuration.nix",
+                "{\n@BOOTLOADER@\n\n@NETWORK@\n}\n",
+            )
+            .with_file(
+                "/etc/icicle/test/flake.nix",
+                "nixosConfigurations.@HOSTNAME@\n",
+            )
+            .with_file("/dev/urandom", "abcd")
+            .with_output("uname", "x86_64\n")
+            .with_output("nixos-version", "23.05.1234.abcdef (Stoat)\n");
+        if efi {
+            system.with_file("/sys/firmware/efi/fw_platform_size", "64\n")
+        } else {
+            system
+        }
+    }
+
+    fn answers(partitions: Option<PartitionSchema>) -> Answers {
+        Answers {
+            partitions,
+            user: Some(UserConfig {
+                name: "Alice".to_string(),
+                username: "alice".to_string(),
+                password: "hunter2".to_string(),
+                hostname: "icicle".to_string(),
+                rootpassword: Some("toor".to_string()),
+                autologin: false,
+            }),
+            ..Answers::default()
+        }
+    }
+
+    fn installer(system: &Arc<FakeSystem>, answers: Answers) -> Installer {
+        let config = InstallationConfig {
+            config_id: "test".to_string(),
+            ..InstallationConfig::default()
+        };
+        Installer::new(
+            system.clone(),
+            Paths::new("/etc", "/usr/libexec"),
+            &config,
+            answers,
+        )
+    }
+
+    fn image() -> ImageTarget {
+        ImageTarget {
+            path: PathBuf::from("/home/alice/nixos.qcow2"),
+            size: Some(8 << 30),
+            format: ImageFormat::Qcow2,
+        }
+    }
+
+    /// Arguments of every helper call.
+    fn helper_calls(system: &FakeSystem) -> Vec<Vec<String>> {
+        system
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::Helper { args, .. } => Some(args),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Every command run on the host.
+    fn commands(system: &FakeSystem) -> Vec<Cmd> {
+        system
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::Run(cmd) => Some(cmd),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn installs_to_full_disk() {
+        let system = Arc::new(fake_system(true));
+        let installer = installer(
+            &system,
+            answers(Some(PartitionSchema::FullDisk("/dev/sda".to_string()))),
+        );
+        let mut steps = vec![];
+        installer.run(|step| steps.push(step)).unwrap();
+        assert_eq!(
+            steps,
+            [
+                InstallStep::Clear,
+                InstallStep::Partition,
+                InstallStep::GenerateConfig,
+                InstallStep::MakeConfig,
+                InstallStep::Install,
+                InstallStep::SetPasswords,
+            ]
+        );
+
+        let subcommands = helper_calls(&system)
+            .into_iter()
+            .map(|args| args[0].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            subcommands,
+            ["unmount", "partition", "write-file", "write-file"]
+        );
+        let input = system
+            .calls()
+            .into_iter()
+            .find_map(|call| match call {
+                Call::Helper { args, input } if args[0] == "partition" => input,
+                _ => None,
+            })
+            .unwrap();
+        let request = serde_json::from_slice::<Envelope<PartitionRequest>>(&input)
+            .unwrap()
+            .into_body()
+            .unwrap();
+        assert!(matches!(request.schema, PartitionSchema::FullDisk(disk) if disk == "/dev/sda"));
+        assert_eq!(request.esp, "/boot/efi");
+
+        let files = system.written_files();
+        assert_eq!(files[0].0, "etc/nixos/configuration.nix");
+        assert!(files[0]
+            .1
+            .contains("  boot.loader.systemd-boot.enable = true;"));
+        assert!(files[0]
+            .1
+            .contains("  boot.loader.efi.canTouchEfiVariables = true;"));
+        assert!(files[0].1.contains("  networking.hostName = \"icicle\";"));
+        assert_eq!(
+            files[1],
+            (
+                "etc/nixos/flake.nix".to_string(),
+                "nixosConfigurations.icicle\n".to_string()
+            )
+        );
+
+        let commands = commands(&system);
+        let programs = commands
+            .iter()
+            .map(|cmd| cmd.program.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            programs,
+            [
+                "rm",
+                "nixos-generate-config",
+                "uname",
+                "nixos-version",
+                "lsblk",
+                "nixos-install",
+                "nixos-enter",
+                "nixos-enter",
+            ]
+        );
+        assert!(commands[5]
+            .args
+            .contains(&"/tmp/icicle/etc/nixos#icicle".to_string()));
+        assert_eq!(commands[6].stdin.as_deref(), Some(&b"alice:hunter2"[..]));
+        assert_eq!(commands[7].stdin.as_deref(), Some(&b"root:toor"[..]));
+    }
+
+    #[test]
+    fn fails_when_passwords_are_not_set() {
+        let system = Arc::new(
+            fake_system(true).with_failure("nixos-enter", "chpasswd: cannot lock /etc/passwd"),
+        );
+        let installer = installer(
+            &system,
+            answers(Some(PartitionSchema::FullDisk("/dev/sda".to_string()))),
+        );
+        let error = installer.run(|_| {}).unwrap_err();
+        assert_eq!(error.to_string(), "Failed to set user password");
+        assert!(format!("{:#}", error).contains("cannot lock /etc/passwd"));
+    }
+
+    #[test]
+    fn installs_to_image() {
+        let system = Arc::new(fake_system(true).with_helper_response(
+            "partition",
+            Ok(Response::Attached {
+                device: "/dev/loop0".to_string(),
+            }),
+        ));
+        let installer = installer(&system, answers(None)).with_image(image());
+        installer.run(|_| {}).unwrap();
+
+        let calls = helper_calls(&system);
+        assert_eq!(
+            calls[1],
+            [
+                "partition",
+                "--image",
+                "/home/alice/nixos.qcow2",
+                "--format",
+                "qcow2",
+                "--size",
+                "8589934592",
+            ]
+        );
+        assert_eq!(
+            calls.last().unwrap(),
+            &[
+                "detach",
+                "--image",
+                "/home/alice/nixos.qcow2",
+                "--format",
+                "qcow2",
+            ]
+        );
+        let configuration = &system.written_files()[0].1;
+        assert!(configuration.contains("  boot.loader.efi.canTouchEfiVariables = false;"));
+        assert!(!configuration.contains("/dev/loop0"));
+    }
+
+    #[test]
+    fn detaches_image_after_failure() {
+        let system = Arc::new(fake_system(true).with_helper_response(
+            "partition",
+            Err(HelperError::new(
+                ErrorCode::PartitionTable,
+                "mklabel failed",
+            )),
+        ));
+        let installer = installer(&system, answers(None)).with_image(image());
+        assert!(installer.run(|_| {}).is_err());
+        let subcommands = helper_calls(&system)
+            .into_iter()
+            .map(|args| args[0].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(subcommands, ["unmount", "partition", "detach"]);
+    }
+
+    #[test]
+    fn refuses_image_on_bios() {
+        let system = Arc::new(fake_system(false));
+        let installer = installer(&system, answers(None)).with_image(image());
+        assert!(installer.prepare(|_| {}).is_err());
+        assert!(helper_calls(&system).is_empty());
+    }
+}
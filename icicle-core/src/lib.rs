@@ -0,0 +1,55 @@
+//! UI independent parts of the Icicle installer: configuration loading, answer
+//! resolution, template rendering and the install pipeline.
+
+pub mod answers;
+pub mod bootloader;
+pub mod choices;
+pub mod conditions;
+pub mod config;
+pub mod facts;
+pub mod form;
+pub mod helper;
+pub mod install;
+pub mod migrate;
+pub mod mounts;
+pub mod nvidia;
+pub mod quirks;
+pub mod render;
+pub mod schema;
+pub mod system;
+pub mod text;
+
+pub use icicle_protocol as protocol;
+
+use std::path::{Path, PathBuf};
+use system::System;
+
+/// Locations Icicle reads its configuration from and runs its helper from.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    pub sysconfdir: PathBuf,
+    pub libexecdir: PathBuf,
+}
+
+impl Paths {
+    pub fn new(sysconfdir: impl Into<PathBuf>, libexecdir: impl Into<PathBuf>) -> Self {
+        Paths {
+            sysconfdir: sysconfdir.into(),
+            libexecdir: libexecdir.into(),
+        }
+    }
+
+    /// Directory holding `config.yml`, the installation configs and their templates.
+    pub fn config_dir(&self) -> PathBuf {
+        self.sysconfdir.join("icicle")
+    }
+
+    pub fn helper(&self) -> PathBuf {
+        self.libexecdir.join("icicle-helper")
+    }
+}
+
+/// Whether the running system was booted with EFI.
+pub fn is_efi(system: &dyn System) -> bool {
+    system.exists(Path::new("/sys/firmware/efi"))
+}
@@ -0,0 +1,101 @@
+//! Migrations of configs written for older layouts.
+//!
+//! Every config file has a `version`, files without one are version 0. When a change
+//! to the config types would break existing configs, [`CONFIG_VERSION`] is increased
+//! and a migration is added that rewrites the previous layout to the new one, warning
+//! about what the config author should change.
+
+use crate::config::CONFIG_VERSION;
+use anyhow::{bail, Context, Result};
+use log::warn;
+use serde_yaml::Value;
+use std::path::Path;
+
+/// Migration from version `i` to `i + 1` at index `i`.
+const MIGRATIONS: &[fn(&mut Value, &Path)] = &[v0_to_v1];
+
+/// Rewrites `value`, read from `path`, to the layout of [`CONFIG_VERSION`].
+pub fn migrate(value: &mut Value, path: &Path) -> Result<()> {
+    let version = match value.get("version") {
+        Some(version) => version
+            .as_u64()
+            .with_context(|| format!("Invalid version in {}", path.display()))?
+            as u32,
+        None => 0,
+    };
+    if version > CONFIG_VERSION {
+        bail!(
+            "{} has config version {}, but only versions up to {} are supported",
+            path.display(),
+            version,
+            CONFIG_VERSION
+        );
+    }
+    if version < CONFIG_VERSION {
+        warn!(
+            "{} has config version {}, migrating it to version {}",
+            path.display(),
+            version,
+            CONFIG_VERSION
+        );
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(value, path);
+    }
+    if let Value::Mapping(mapping) = value {
+        mapping.insert("version".into(), CONFIG_VERSION.into());
+    }
+    Ok(())
+}
+
+/// Version 1 introduced the `version` key and dropped the unused `distribution_logo`.
+fn v0_to_v1(value: &mut Value, path: &Path) {
+    warn!(
+        "{} has no version, add `version: {}` to it",
+        path.display(),
+        CONFIG_VERSION
+    );
+    if let Value::Mapping(mapping) = value {
+        if mapping.remove("distribution_logo").is_some() {
+            warn!(
+                "{}: `distribution_logo` is deprecated and ignored, remove it",
+                path.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn migrates_unversioned_configs() {
+        let mut value = yaml("{distribution_name: SnowflakeOS, distribution_logo: snowflakeos}");
+        migrate(&mut value, Path::new("branding.yml")).unwrap();
+        assert_eq!(value, yaml("{distribution_name: SnowflakeOS, version: 1}"));
+    }
+
+    #[test]
+    fn keeps_current_configs() {
+        let mut value = yaml("{version: 1, distribution_logo: snowflakeos}");
+        migrate(&mut value, Path::new("branding.yml")).unwrap();
+        assert_eq!(value, yaml("{version: 1, distribution_logo: snowflakeos}"));
+    }
+
+    #[test]
+    fn rejects_newer_and_invalid_versions() {
+        let path = Path::new("branding.yml");
+        let error = migrate(&mut yaml("version: 2"), path).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "branding.yml has config version 2, but only versions up to 1 are supported"
+        );
+        let error = migrate(&mut yaml("version: one"), path).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid version in branding.yml");
+    }
+}
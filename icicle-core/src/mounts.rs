@@ -0,0 +1,187 @@
+//! Checks on targets the user partitioned and mounted by hand.
+
+use crate::{
+    protocol::TARGET_ROOT,
+    system::{Cmd, System},
+};
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// A filesystem mounted under the target root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub device: String,
+    /// Mountpoint relative to the target root, e.g. `/` or `/boot/efi`
+    pub mountpoint: String,
+    pub fstype: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountProblem {
+    /// Nothing is mounted at the target root
+    NoRoot,
+    /// Booted with EFI but no ESP is mounted at the given mountpoint
+    NoEsp(String),
+    /// The filesystem of the ESP is not FAT
+    EspNotFat(String),
+}
+
+/// Filesystems currently mounted under the target root, shortest mountpoint first.
+pub fn target_mounts(system: &dyn System) -> Result<Vec<Mount>> {
+    let table = system
+        .read_to_string(Path::new("/proc/self/mounts"))
+        .context("Failed to read mount table")?;
+    let mut mounts = vec![];
+    for line in table.lines() {
+        let mut fields = line.split_whitespace();
+        if let (Some(device), Some(target), Some(fstype)) =
+            (fields.next(), fields.next(), fields.next())
+        {
+            let target = unescape(target);
+            let mountpoint = if target == TARGET_ROOT {
+                "/".to_string()
+            } else if let Some(path) = target.strip_prefix(&format!("{}/", TARGET_ROOT)) {
+                format!("/{}", path)
+            } else {
+                continue;
+            };
+            // Later entries shadow earlier ones mounted on the same path
+            mounts.retain(|m: &Mount| m.mountpoint != mountpoint);
+            mounts.push(Mount {
+                device: unescape(device),
+                mountpoint,
+                fstype: fstype.to_string(),
+            });
+        }
+    }
+    mounts.sort_by_key(|m| m.mountpoint.len());
+    Ok(mounts)
+}
+
+/// Everything that would keep `mounts` from being installed to. `esp` is the
+/// mountpoint of the EFI system partition, `None` on BIOS machines.
+pub fn check_mounts(mounts: &[Mount], esp: Option<&str>) -> Vec<MountProblem> {
+    let mut problems = vec![];
+    if !mounts.iter().any(|m| m.mountpoint == "/") {
+        problems.push(MountProblem::NoRoot);
+    }
+    if let Some(esp) = esp {
+        match mounts.iter().find(|m| m.mountpoint == esp) {
+            Some(mount) if mount.fstype != "vfat" => {
+                problems.push(MountProblem::EspNotFat(mount.fstype.to_string()))
+            }
+            Some(_) => {}
+            None => problems.push(MountProblem::NoEsp(esp.to_string())),
+        }
+    }
+    problems
+}
+
+/// Disk holding `device`, e.g. `/dev/sda` for `/dev/sda2`.
+pub fn parent_disk(system: &dyn System, device: &str) -> Option<String> {
+    let output = system
+        .run(&Cmd::new("lsblk").args(["--noheadings", "--output", "PKNAME", device]))
+        .ok()?;
+    if !output.success() {
+        return None;
+    }
+    output
+        .stdout_str()
+        .lines()
+        .map(|x| x.trim())
+        .find(|x| !x.is_empty())
+        .map(|x| format!("/dev/{}", x))
+}
+
+/// Decodes the octal escapes `/proc/self/mounts` uses for whitespace.
+fn unescape(field: &str) -> String {
+    let mut out = String::new();
+    let mut chars = field.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let code = chars.clone().take(3).collect::<String>();
+            if let Ok(byte) = u8::from_str_radix(&code, 8) {
+                out.push(byte as char);
+                chars.nth(2);
+                continue;
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::{Call, FakeSystem};
+
+    fn mount(device: &str, mountpoint: &str, fstype: &str) -> Mount {
+        Mount {
+            device: device.to_string(),
+            mountpoint: mountpoint.to_string(),
+            fstype: fstype.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_target_mounts() {
+        let system = FakeSystem::new().with_file(
+            "/proc/self/mounts",
+            "/dev/sda2 / ext4 rw 0 0\n\
+             /dev/sda1 /tmp/icicle/boot/efi vfat rw 0 0\n\
+             /dev/sdb1 /tmp/icicle/home ext4 rw 0 0\n\
+             /dev/sdb2 /tmp/icicle/home btrfs rw 0 0\n\
+             /dev/sda3 /tmp/icicle ext4 rw 0 0\n\
+             /dev/sdc1 /tmp/icicle/mnt/my\\040files ntfs rw 0 0\n\
+             /dev/sdc2 /tmp/icicle2 ext4 rw 0 0\n",
+        );
+        assert_eq!(
+            target_mounts(&system).unwrap(),
+            [
+                mount("/dev/sda3", "/", "ext4"),
+                mount("/dev/sdb2", "/home", "btrfs"),
+                mount("/dev/sda1", "/boot/efi", "vfat"),
+                mount("/dev/sdc1", "/mnt/my files", "ntfs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_root_and_esp() {
+        let root = mount("/dev/sda2", "/", "ext4");
+        let esp = mount("/dev/sda1", "/boot", "vfat");
+        assert!(check_mounts(&[root.clone(), esp.clone()], Some("/boot")).is_empty());
+        assert!(check_mounts(&[root.clone(), esp.clone()], None).is_empty());
+        assert_eq!(
+            check_mounts(&[esp], Some("/boot/efi")),
+            [
+                MountProblem::NoRoot,
+                MountProblem::NoEsp("/boot/efi".to_string())
+            ]
+        );
+        let ext4 = mount("/dev/sda1", "/boot", "ext4");
+        assert_eq!(
+            check_mounts(&[root, ext4], Some("/boot")),
+            [MountProblem::EspNotFat("ext4".to_string())]
+        );
+    }
+
+    #[test]
+    fn finds_parent_disk() {
+        let system = FakeSystem::new().with_output("lsblk", "nvme0n1\n");
+        assert_eq!(
+            parent_disk(&system, "/dev/nvme0n1p2").as_deref(),
+            Some("/dev/nvme0n1")
+        );
+        match &system.calls()[..] {
+            [Call::Run(cmd)] => assert_eq!(
+                cmd.args,
+                ["--noheadings", "--output", "PKNAME", "/dev/nvme0n1p2"]
+            ),
+            calls => panic!("Unexpected calls {:?}", calls),
+        }
+        let system = FakeSystem::new().with_failure("lsblk", "not a block device");
+        assert_eq!(parent_disk(&system, "/tmp/icicle.img"), None);
+    }
+}
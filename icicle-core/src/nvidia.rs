@@ -0,0 +1,188 @@
+//! NVIDIA PRIME on machines with an integrated GPU and an NVIDIA GPU.
+
+use crate::facts::{GpuVendor, PciDevice};
+
+/// The two GPUs of a hybrid graphics machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prime {
+    /// Vendor of the integrated GPU, Intel or AMD
+    pub integrated: GpuVendor,
+    /// Bus ID of the integrated GPU in NixOS format, e.g. `PCI:0:2:0`
+    pub integrated_bus_id: String,
+    /// Bus ID of the NVIDIA GPU in NixOS format, e.g. `PCI:1:0:0`
+    pub nvidia_bus_id: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NvidiaDriver {
+    /// Open kernel modules, Turing and newer GPUs only
+    Open,
+    #[default]
+    Proprietary,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrimeMode {
+    /// The NVIDIA GPU renders only applications started with `nvidia-offload`
+    #[default]
+    Offload,
+    /// The NVIDIA GPU renders everything
+    Sync,
+}
+
+/// How the NVIDIA GPU of a hybrid graphics machine is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NvidiaConfig {
+    pub prime: Prime,
+    pub driver: NvidiaDriver,
+    pub mode: PrimeMode,
+}
+
+impl Prime {
+    /// Finds an NVIDIA GPU next to an Intel or AMD GPU.
+    pub fn detect(devices: &[PciDevice]) -> Option<Self> {
+        let gpus = devices.iter().filter(|dev| dev.is_gpu());
+        let nvidia = gpus.clone().find(|dev| dev.vendor == 0x10de)?;
+        let (integrated, device) = gpus.clone().find_map(|dev| match dev.vendor {
+            0x8086 => Some((GpuVendor::Intel, dev)),
+            0x1002 => Some((GpuVendor::Amd, dev)),
+            _ => None,
+        })?;
+        Some(Prime {
+            integrated,
+            integrated_bus_id: bus_id(&device.slot)?,
+            nvidia_bus_id: bus_id(&nvidia.slot)?,
+        })
+    }
+}
+
+impl NvidiaConfig {
+    /// Configuration substituted for `@NVIDIAOFFLOAD@`.
+    pub fn to_nix(&self) -> String {
+        let open = self.driver == NvidiaDriver::Open;
+        let mode = match self.mode {
+            PrimeMode::Offload => {
+                r#"      offload = {
+        enable = true;
+        enableOffloadCmd = true;
+      };"#
+            }
+            PrimeMode::Sync => "      sync.enable = true;",
+        };
+        let integrated = match self.prime.integrated {
+            GpuVendor::Amd => "amdgpuBusId",
+            _ => "intelBusId",
+        };
+        format!(
+            r#"  # Use the NVIDIA GPU with PRIME.
+  services.xserver.videoDrivers = [ "nvidia" ];
+  hardware.graphics.enable = true;
+  hardware.nvidia = {{
+    modesetting.enable = true;
+    open = {};
+    prime = {{
+{}
+      {} = "{}";
+      nvidiaBusId = "{}";
+    }};
+  }};"#,
+            open, mode, integrated, self.prime.integrated_bus_id, self.prime.nvidia_bus_id
+        )
+    }
+}
+
+/// Converts a PCI slot like `0000:01:00.0` to the decimal `PCI:1:0:0` NixOS expects.
+/// Devices outside of domain 0 get the domain after the bus, e.g. `PCI:1@1:0:0`.
+pub fn bus_id(slot: &str) -> Option<String> {
+    let (domain, rest) = slot.split_once(':')?;
+    let (bus, rest) = rest.split_once(':')?;
+    let (device, function) = rest.split_once('.')?;
+    let [domain, bus, device, function] =
+        [domain, bus, device, function].map(|part| u32::from_str_radix(part, 16).ok());
+    let bus = match domain? {
+        0 => bus?.to_string(),
+        domain => format!("{}@{}", bus?, domain),
+    };
+    Some(format!("PCI:{}:{}:{}", bus, device?, function?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpu(slot: &str, vendor: u16) -> PciDevice {
+        PciDevice {
+            slot: slot.to_string(),
+            vendor,
+            device: 0,
+            class: 0x030000,
+        }
+    }
+
+    #[test]
+    fn converts_slots_to_decimal_bus_ids() {
+        assert_eq!(bus_id("0000:00:02.0").as_deref(), Some("PCI:0:2:0"));
+        assert_eq!(bus_id("0000:0a:1f.7").as_deref(), Some("PCI:10:31:7"));
+        assert_eq!(bus_id("0001:01:00.0").as_deref(), Some("PCI:1@1:0:0"));
+        assert_eq!(bus_id("01:00.0"), None);
+        assert_eq!(bus_id("0000:0g:00.0"), None);
+    }
+
+    #[test]
+    fn detects_hybrid_graphics() {
+        let audio = PciDevice {
+            class: 0x040300,
+            ..gpu("0000:00:1f.3", 0x8086)
+        };
+        let devices = [
+            audio,
+            gpu("0000:01:00.0", 0x10de),
+            gpu("0000:05:00.0", 0x1002),
+        ];
+        assert_eq!(
+            Prime::detect(&devices),
+            Some(Prime {
+                integrated: GpuVendor::Amd,
+                integrated_bus_id: "PCI:5:0:0".to_string(),
+                nvidia_bus_id: "PCI:1:0:0".to_string(),
+            })
+        );
+        assert_eq!(Prime::detect(&devices[..2]), None);
+    }
+
+    #[test]
+    fn configures_prime() {
+        let config = NvidiaConfig {
+            prime: Prime {
+                integrated: GpuVendor::Intel,
+                integrated_bus_id: "PCI:0:2:0".to_string(),
+                nvidia_bus_id: "PCI:1:0:0".to_string(),
+            },
+            driver: NvidiaDriver::Open,
+            mode: PrimeMode::Sync,
+        };
+        assert_eq!(
+            config.to_nix(),
+            r#"  # Use the NVIDIA GPU with PRIME.
+  services.xserver.videoDrivers = [ "nvidia" ];
+  hardware.graphics.enable = true;
+  hardware.nvidia = {
+    modesetting.enable = true;
+    open = true;
+    prime = {
+      sync.enable = true;
+      intelBusId = "PCI:0:2:0";
+      nvidiaBusId = "PCI:1:0:0";
+    };
+  };"#
+        );
+        let offload = NvidiaConfig {
+            driver: NvidiaDriver::Proprietary,
+            mode: PrimeMode::Offload,
+            ..config
+        }
+        .to_nix();
+        assert!(offload.contains("    open = false;\n"));
+        assert!(offload.contains("      offload = {\n        enable = true;\n"));
+    }
+}
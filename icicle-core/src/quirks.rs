@@ -0,0 +1,234 @@
+//! Configuration some hardware needs, from `quirks.yml`: quirks and nixos-hardware modules.
+
+use crate::{
+    facts::{CpuVendor, Facts},
+    text::Text,
+};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Configuration added on machines with matching hardware.
+///
+/// ```yml
+/// - id: broadcom-sta
+///   name: "Broadcom Wi-Fi driver"
+///   match:
+///     pci: [ "14e4:4311", "14e4:43b1" ]
+///   config: |-
+///     boot.kernelModules = [ "wl" ];
+///     boot.extraModulePackages = [ config.boot.kernelPackages.broadcom_sta ];
+/// ```
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+pub struct Quirk {
+    pub id: String,
+    /// Shown on the summary page
+    pub name: Text,
+    pub description: Option<Text>,
+    /// Hardware the quirk applies to, every machine when not given
+    #[serde(rename = "match")]
+    pub matches: Option<HardwareMatch>,
+    /// Substituted for `@QUIRKS@` when the quirk applies
+    pub config: String,
+}
+
+/// A module of [nixos-hardware](https://github.com/NixOS/nixos-hardware) for a machine.
+///
+/// ```yml
+/// - module: lenovo-thinkpad-t480
+///   name: "Lenovo ThinkPad T480"
+///   match:
+///     dmi:
+///       sys_vendor: "LENOVO"
+///       product_version: "ThinkPad T480"
+/// ```
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+pub struct HardwareModule {
+    /// Name of the module in the `nixosModules` of nixos-hardware
+    pub module: String,
+    /// Name of the machine, shown on the summary page
+    pub name: Text,
+    #[serde(rename = "match")]
+    pub matches: HardwareMatch,
+}
+
+/// Holds if any of the given criteria holds.
+///
+/// ```yml
+/// match:
+///   pci: [ "80ee:cafe" ]
+///   dmi:
+///     product_name: "VirtualBox"
+/// ```
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HardwareMatch {
+    /// PCI `vendor:device` ids in hex, e.g. `14e4:43b1`, or `14e4:*` for any device
+    /// of a vendor
+    pub pci: Option<Vec<String>>,
+    /// USB `vendor:product` ids, like `pci`
+    pub usb: Option<Vec<String>>,
+    /// Regular expressions matching the whole DMI strings, by field name. All of them
+    /// must match
+    pub dmi: Option<BTreeMap<String, String>>,
+    pub cpu: Option<CpuVendor>,
+}
+
+impl HardwareMatch {
+    pub fn holds(&self, facts: &Facts) -> bool {
+        self.pci.as_ref().map_or(false, |ids| {
+            facts
+                .pci_devices
+                .iter()
+                .any(|dev| ids.iter().any(|id| id_matches(id, dev.vendor, dev.device)))
+        }) || self.usb.as_ref().map_or(false, |ids| {
+            facts
+                .usb_devices
+                .iter()
+                .any(|dev| ids.iter().any(|id| id_matches(id, dev.vendor, dev.product)))
+        }) || self.dmi.as_ref().map_or(false, |dmi| {
+            dmi.iter().all(|(field, pattern)| {
+                facts.dmi.get(field).map_or(false, |value| {
+                    Regex::new(&format!("^(?:{})$", pattern)).map_or(false, |re| re.is_match(value))
+                })
+            })
+        }) || self.cpu.map_or(false, |cpu| facts.cpu == Some(cpu))
+    }
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
+pub struct QuirksConfig {
+    /// Layout version of the config, older layouts are migrated when loaded
+    #[serde(default)]
+    pub version: u32,
+    pub quirks: Vec<Quirk>,
+    /// nixos-hardware modules, the first matching one is used
+    #[serde(default)]
+    pub nixos_hardware: Vec<HardwareModule>,
+}
+
+/// The quirks that apply to the machine, in the order they are listed.
+pub fn matching_quirks(quirks: &[Quirk], facts: &Facts) -> Vec<Quirk> {
+    quirks
+        .iter()
+        .filter(|quirk| quirk.matches.as_ref().map_or(true, |m| m.holds(facts)))
+        .cloned()
+        .collect()
+}
+
+/// The first nixos-hardware module for the machine.
+pub fn matching_module(modules: &[HardwareModule], facts: &Facts) -> Option<HardwareModule> {
+    modules
+        .iter()
+        .find(|module| module.matches.holds(facts))
+        .cloned()
+}
+
+/// Whether a `vendor:device` id from the quirks matches a device.
+fn id_matches(id: &str, vendor: u16, device: u16) -> bool {
+    let part_matches =
+        |pattern: &str, value: u16| pattern == "*" || u16::from_str_radix(pattern, 16) == Ok(value);
+    match id.split_once(':') {
+        Some((v, d)) => part_matches(v, vendor) && part_matches(d, device),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::FakeSystem;
+
+    /// A Lenovo ThinkPad with an AMD CPU, Broadcom Wi-Fi and a Realtek USB Wi-Fi dongle.
+    fn facts() -> Facts {
+        let wifi = "/sys/bus/pci/devices/0000:03:00.0";
+        let dongle = "/sys/bus/usb/devices/1-2";
+        let system = FakeSystem::new()
+            .with_file(
+                "/proc/cpuinfo",
+                "processor\t: 0\nvendor_id\t: AuthenticAMD\n",
+            )
+            .with_file(format!("{}/vendor", wifi), "0x14e4\n")
+            .with_file(format!("{}/device", wifi), "0x43b1\n")
+            .with_file(format!("{}/class", wifi), "0x028000\n")
+            .with_file(format!("{}/idVendor", dongle), "0bda\n")
+            .with_file(format!("{}/idProduct", dongle), "8179\n")
+            .with_file("/sys/class/dmi/id/sys_vendor", "LENOVO\n")
+            .with_file("/sys/class/dmi/id/product_version", "ThinkPad T480\n");
+        Facts::detect(&system)
+    }
+
+    fn matches(yaml: &str) -> HardwareMatch {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn matches_device_ids() {
+        let facts = facts();
+        assert!(matches("pci: [\"8086:1234\", \"14e4:43b1\"]").holds(&facts));
+        assert!(matches("pci: [\"14e4:*\"]").holds(&facts));
+        assert!(!matches("pci: [\"14e4:4311\", \"14e4\"]").holds(&facts));
+        assert!(matches("usb: [\"0bda:8179\"]").holds(&facts));
+        assert!(!matches("usb: [\"14e4:43b1\"]").holds(&facts));
+        assert!(matches("cpu: amd").holds(&facts));
+        assert!(!matches("cpu: intel").holds(&facts));
+        assert!(!HardwareMatch::default().holds(&facts));
+    }
+
+    #[test]
+    fn matches_whole_dmi_strings() {
+        let facts = facts();
+        assert!(matches("dmi: {sys_vendor: LENOVO, product_version: ThinkPad T4.0}").holds(&facts));
+        assert!(!matches("dmi: {sys_vendor: LENOVO, product_version: ThinkPad}").holds(&facts));
+        assert!(!matches("dmi: {product_name: .*}").holds(&facts));
+        assert!(!matches("dmi: {sys_vendor: \"(\"}").holds(&facts));
+    }
+
+    #[test]
+    fn selects_matching_quirks_in_order() {
+        let quirks: Vec<Quirk> = serde_yaml::from_str(
+            r#"
+- id: always
+  name: Always
+  config: "services.fwupd.enable = true;"
+- id: virtualbox
+  name: VirtualBox
+  match: {pci: ["80ee:cafe"]}
+  config: "virtualisation.virtualbox.guest.enable = true;"
+- id: broadcom-sta
+  name: Broadcom
+  match: {pci: ["14e4:43b1"]}
+  config: "boot.kernelModules = [ \"wl\" ];"
+"#,
+        )
+        .unwrap();
+        let ids = matching_quirks(&quirks, &facts())
+            .into_iter()
+            .map(|quirk| quirk.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["always", "broadcom-sta"]);
+    }
+
+    #[test]
+    fn selects_first_matching_module() {
+        let modules: Vec<HardwareModule> = serde_yaml::from_str(
+            r#"
+- module: lenovo-thinkpad-x1-6th-gen
+  name: Lenovo ThinkPad X1 Carbon 6th Gen
+  match: {dmi: {sys_vendor: LENOVO, product_version: ThinkPad X1 Carbon 6th}}
+- module: lenovo-thinkpad-t480
+  name: Lenovo ThinkPad T480
+  match: {dmi: {sys_vendor: LENOVO, product_version: ThinkPad T480}}
+- module: lenovo-thinkpad
+  name: Lenovo ThinkPad
+  match: {dmi: {product_version: ThinkPad.*}}
+"#,
+        )
+        .unwrap();
+        let facts = facts();
+        let module = matching_module(&modules, &facts).map(|module| module.module);
+        assert_eq!(module.as_deref(), Some("lenovo-thinkpad-t480"));
+        assert_eq!(matching_module(&modules[..1], &facts), None);
+    }
+}
@@ -0,0 +1,831 @@
+use crate::{
+    answers::Answers,
+    bootloader::Bootloader,
+    config::{Choice, FlakeInput, ListChoices},
+    is_efi,
+    protocol::{DiskLayout, LUKS_NAME},
+    system::{Cmd, System},
+};
+use anyhow::{bail, Context, Result};
+use regex::{Captures, Regex};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+/* Configuration keys:
+    @NVIDIAOFFLOAD@ - NVIDIA PRIME on hybrid graphics machines
+    @BOOTLOADER@ - Bootloader and EFI system partition, ZFS root and encrypted disk support
+    @NETWORK@ - Network configuration
+    @TIMEZONE@ - Timezone, and the hardware clock in local time next to Windows
+    @LOCALE@ - Localization
+    @KEYBOARD@ - Keyboard layout
+    @DESKTOP@ - Desktop environment
+    @AUTOLOGIN@ - Autologin config
+    @PACKAGES@ - Packages to install
+    @STATEVERSION@ - NixOS State version
+    @IMPORTS@ - Module files of the selected choices
+    @FLAKEINPUTS@ - Flake inputs of the selected choices, nixos-hardware and lanzaboote
+    @FLAKEMODULES@ - Flake modules of the selected choices, nixos-hardware and lanzaboote
+    @QUIRKS@ - Configuration of the hardware quirks that apply
+*/
+
+/* Value keys:
+    @HOSTNAME@ - Hostname
+    @USERNAME@ - Username
+    @FULLNAME@ - Full name
+    @FIELD_ID@ - Value of a form field, as a Nix expression
+    @LIST_ID.Choice@ - Whether a choice of a list is selected, `true` or `false`
+*/
+
+/// Flake input of the nixos-hardware modules.
+const NIXOS_HARDWARE_URL: &str = "github:NixOS/nixos-hardware";
+
+/// Flake input of lanzaboote, which signs the boot files for Secure Boot.
+const LANZABOOTE_URL: &str = "github:nix-community/lanzaboote";
+
+/// Placeholders inside lists or attribute sets, whose targets are added as entries.
+const ENTRY_TARGETS: [&str; 4] = ["IMPORTS", "FLAKEINPUTS", "FLAKEMODULES", "PACKAGES"];
+
+/// Facts about the machine being installed on that end up in the configuration.
+#[derive(Debug, Clone)]
+pub struct RenderContext {
+    pub efi: bool,
+    /// Nix system double, e.g. `x86_64-linux`
+    pub system: String,
+    pub state_version: String,
+    /// Random `networking.hostId`, which ZFS requires
+    pub host_id: String,
+    /// UUID of the LUKS container of the LVM on LUKS layout, once it is open
+    pub luks_uuid: Option<String>,
+    /// Choices of every `!list` step of the installation config, whose flags are
+    /// substituted
+    pub lists: ListChoices,
+}
+
+impl RenderContext {
+    pub fn detect(system: &dyn System) -> Result<Self> {
+        let arch = system
+            .run(&Cmd::new("uname").arg("-m"))
+            .context("Failed to get architecture")?
+            .stdout_str()
+            .trim()
+            .to_string();
+        let state_version = system
+            .run(&Cmd::new("nixos-version"))
+            .context("Failed to get nixos version")?
+            .stdout_str()
+            .get(0..5)
+            .context("Failed to get nixos version")?
+            .to_string();
+        Ok(RenderContext {
+            efi: is_efi(system),
+            system: format!("{}-linux", arch),
+            state_version,
+            host_id: host_id(system)?,
+            luks_uuid: luks_uuid(system),
+            lists: ListChoices::new(),
+        })
+    }
+}
+
+/// Random `networking.hostId`, 8 hex digits.
+fn host_id(system: &dyn System) -> Result<String> {
+    let bytes = system
+        .read_bytes(Path::new("/dev/urandom"), 4)
+        .context("Failed to read /dev/urandom")?;
+    if bytes.len() != 4 {
+        bail!("Failed to generate a host ID");
+    }
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// UUID of the partition holding the open `LUKS_NAME` container.
+fn luks_uuid(system: &dyn System) -> Option<String> {
+    let output = system
+        .run(&Cmd::new("lsblk").args([
+            "--noheadings",
+            "--inverse",
+            "--list",
+            "--output",
+            "FSTYPE,UUID",
+            &format!("/dev/mapper/{}", LUKS_NAME),
+        ]))
+        .ok()?;
+    output.stdout_str().lines().find_map(|line| {
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["crypto_LUKS", uuid] => Some(uuid.to_string()),
+            _ => None,
+        }
+    })
+}
+
+/// Renders every `.nix` template in `template_dirs` and the module files of the selected
+/// choices, returning file names and contents. Templates in later directories replace
+/// templates with the same name in earlier ones.
+pub fn render_dir(
+    system: &dyn System,
+    template_dirs: &[PathBuf],
+    answers: &Answers,
+    ctx: &RenderContext,
+) -> Result<Vec<(String, String)>> {
+    let mut templates = BTreeMap::new();
+    for template_dir in template_dirs {
+        for path in system.read_dir(template_dir)? {
+            let filename = match path.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if filename.ends_with(".nix") {
+                templates.insert(filename, path);
+            }
+        }
+    }
+    for choice in selected_choices(answers) {
+        for module in choice.modules.iter().flatten() {
+            let path = template_dirs
+                .iter()
+                .rev()
+                .map(|dir| dir.join("modules").join(module))
+                .find(|path| system.exists(path))
+                .with_context(|| format!("Module file {} not found", module))?;
+            templates.insert(module.to_string(), path);
+        }
+    }
+    let mut files = vec![];
+    for (filename, path) in templates {
+        let template = system.read_to_string(&path)?;
+        files.push((filename, render(&template, answers, ctx)?));
+    }
+    Ok(files)
+}
+
+/// Substitutes all `@KEY@` placeholders in `template`.
+pub fn render(template: &str, answers: &Answers, ctx: &RenderContext) -> Result<String> {
+    // Snippets of the selected choices for other placeholders, filled in along with them
+    let targets = choice_targets(answers);
+    let mut config = template.to_string();
+    for (key, snippets) in &targets {
+        if !ENTRY_TARGETS.contains(key) {
+            let placeholder = format!("@{}@", key);
+            let snippets = snippets.iter().map(|s| indent(s)).collect::<Vec<_>>();
+            config = config.replace(
+                &placeholder,
+                &format!("{}\n{}", placeholder, snippets.join("\n")),
+            );
+        }
+    }
+    config = replace_flags(&config, answers, &ctx.lists);
+
+    config = replace_block(
+        &config,
+        "@NVIDIAOFFLOAD@",
+        &answers
+            .nvidia
+            .as_ref()
+            .map(|nvidia| nvidia.to_nix())
+            .unwrap_or_default(),
+    );
+    config = config.replace("@ARCH@", &ctx.system);
+
+    let bios_disk = if ctx.efi {
+        None
+    } else {
+        Some(
+            answers
+                .bootdisk()
+                .context("Failed to get bootloader disk")?,
+        )
+    };
+    let mut bootloader = answers.bootloader.clone();
+    if let Some(windows) = answers.windows {
+        bootloader.os_prober = Some(windows.boot_entry);
+    }
+    let mut boot = vec![bootloader.to_nix(bios_disk.as_deref(), answers.portable)];
+    if answers.zfs() {
+        boot.push(String::new());
+        boot.push("  # Root on ZFS, pools are tied to the host ID.".to_string());
+        boot.push("  boot.supportedFilesystems = [ \"zfs\" ];".to_string());
+        if bios_disk.is_some() || bootloader.loader == Bootloader::Grub {
+            boot.push("  boot.loader.grub.zfsSupport = true;".to_string());
+        }
+        boot.push(format!("  networking.hostId = \"{}\";", ctx.host_id));
+    }
+    if let DiskLayout::LvmOnLuks { .. } = answers.layout {
+        let uuid = ctx
+            .luks_uuid
+            .as_ref()
+            .context("Failed to find the encrypted partition")?;
+        boot.push(String::new());
+        boot.push("  # Unlock the encrypted disk and activate its volume group.".to_string());
+        boot.push(format!("  boot.initrd.luks.devices.{} = {{", LUKS_NAME));
+        boot.push(format!("    device = \"/dev/disk/by-uuid/{}\";", uuid));
+        boot.push("    preLVM = true;".to_string());
+        boot.push("  };".to_string());
+        boot.push("  boot.initrd.services.lvm.enable = true;".to_string());
+    }
+    config = config.replace("@BOOTLOADER@", &boot.join("\n"));
+
+    let quirks = answers
+        .quirks
+        .iter()
+        .map(|quirk| indent(&quirk.config))
+        .collect::<Vec<_>>();
+    config = replace_block(&config, "@QUIRKS@", &quirks.join("\n"));
+
+    config = config.replace(
+        "@NETWORK@",
+        &format!(
+            r#"  # Define your hostname.
+  networking.hostName = "{}";
+
+  # Enable networking
+  networking.networkmanager.enable = true;"#,
+            answers.hostname().unwrap_or("nixos")
+        ),
+    );
+
+    let mut time = vec![];
+    if let Some(tz) = &answers.timezone {
+        time.push(format!(
+            r#"  # Set your time zone.
+  time.timeZone = "{}";"#,
+            tz
+        ));
+    }
+    if answers.windows.map_or(false, |windows| windows.local_time) {
+        time.push(
+            r#"  # Keep the hardware clock in local time, like Windows does.
+  time.hardwareClockInLocalTime = true;"#
+                .to_string(),
+        );
+    }
+    if !time.is_empty() {
+        config = config.replace("@TIMEZONE@", &time.join("\n\n"));
+    }
+
+    if let Some(locale) = &answers.language {
+        config = config.replace(
+            "@LOCALE@",
+            &format!(
+                r#"  # Select internationalisation properties.
+  i18n.defaultLocale = "{}";"#,
+                locale
+            ),
+        );
+    }
+
+    let desktop = answers.desktop.as_ref();
+
+    if let Some(keymap) = &answers.keyboard {
+        let (layout, variant) = keymap.split_once('+').unwrap_or((keymap, ""));
+        let keyboard = match desktop.and_then(|d| d.keyboard.as_ref()) {
+            Some(keyboard) => indent(keyboard),
+            None if variant.is_empty() => format!(
+                r#"  # Set the keyboard layout.
+  services.xserver.layout = "{}";
+  console.useXkbConfig = true;"#,
+                layout
+            ),
+            None => format!(
+                r#"  # Set the keyboard layout.
+  services.xserver = {{
+    layout = "{}";
+    xkbVariant = "{}";
+  }};
+  console.useXkbConfig = true;"#,
+                layout, variant
+            ),
+        };
+        config = config.replace(
+            "@KEYBOARD@",
+            &keyboard
+                .replace("@LAYOUT@", layout)
+                .replace("@VARIANT@", variant),
+        );
+    }
+
+    config = config.replace(
+        "@DESKTOP@",
+        &desktop.map(|d| indent(&d.config)).unwrap_or_default(),
+    );
+
+    if let Some(user) = &answers.user {
+        let mut autocfg = String::new();
+        if user.autologin {
+            match desktop.and_then(|d| d.autologin.as_ref()) {
+                Some(autologin) => {
+                    autocfg.push_str(&indent(autologin).replace("@USERNAME@", &user.username));
+                    autocfg.push('\n');
+                }
+                None => autocfg.push_str(&format!(
+                    r#"  # Enable automatic login for the user.
+  services.xserver.displayManager.autoLogin.enable = true;
+  services.xserver.displayManager.autoLogin.user = "{}";
+"#,
+                    user.username
+                )),
+            }
+        }
+        config = config.replace("@AUTOLOGIN@", &autocfg);
+    }
+
+    // List configuration options
+    let mut extrapkgs = vec![];
+    for (id, choices) in answers.lists.iter() {
+        let mut listcfg = String::new();
+        for (_key, choice) in choices.iter() {
+            if let Some(pkgs) = &choice.packages {
+                for pkg in pkgs {
+                    extrapkgs.push(pkg.to_string());
+                }
+            }
+            if let Some(cfg) = &choice.config {
+                cfg.lines()
+                    .for_each(|x| listcfg.push_str(&format!("  {}\n", x)));
+            }
+        }
+        config = config.replace(&format!("@{}@", id), &listcfg);
+    }
+    for snippet in targets.get("PACKAGES").into_iter().flatten() {
+        extrapkgs.extend(
+            snippet
+                .lines()
+                .map(str::trim)
+                .filter(|pkg| !pkg.is_empty())
+                .map(String::from),
+        );
+    }
+
+    // The keys lanzaboote signs with are managed with sbctl
+    let lanzaboote = answers.bootloader.uses_lanzaboote(ctx.efi);
+    if lanzaboote {
+        extrapkgs.push("sbctl".to_string());
+    }
+
+    // Module files and flake additions of the selected choices, nixos-hardware and lanzaboote
+    let nixos_hardware = (
+        "nixos-hardware".to_string(),
+        FlakeInput {
+            url: NIXOS_HARDWARE_URL.to_string(),
+            follows: vec![],
+        },
+    );
+    let lanzaboote_input = (
+        "lanzaboote".to_string(),
+        FlakeInput {
+            url: LANZABOOTE_URL.to_string(),
+            follows: vec!["nixpkgs".to_string()],
+        },
+    );
+    let mut imports = vec![];
+    let mut flakeinputs = BTreeMap::new();
+    let mut flakemodules = vec![];
+    if let Some(module) = &answers.nixos_hardware {
+        flakeinputs.insert(&nixos_hardware.0, &nixos_hardware.1);
+        flakemodules.push(format!(
+            "          inputs.nixos-hardware.nixosModules.{}",
+            module
+        ));
+    }
+    if lanzaboote {
+        flakeinputs.insert(&lanzaboote_input.0, &lanzaboote_input.1);
+        flakemodules.push("          inputs.lanzaboote.nixosModules.lanzaboote".to_string());
+    }
+    for choice in selected_choices(answers) {
+        for module in choice.modules.iter().flatten() {
+            let import = format!("      ./{}", module);
+            if !imports.contains(&import) {
+                imports.push(import);
+            }
+        }
+        if let Some(inputs) = &choice.flake_inputs {
+            flakeinputs.extend(inputs.iter());
+        }
+        for module in choice.flake_modules.iter().flatten() {
+            let module = format!("          {}", module);
+            if !flakemodules.contains(&module) {
+                flakemodules.push(module);
+            }
+        }
+    }
+    imports.extend(target_entries(&targets, "IMPORTS", 6));
+    let mut flakeinputs = flake_inputs(&flakeinputs);
+    flakeinputs.extend(target_entries(&targets, "FLAKEINPUTS", 4));
+    flakemodules.extend(target_entries(&targets, "FLAKEMODULES", 10));
+    config = replace_block(&config, "@IMPORTS@", &imports.join("\n"));
+    config = replace_block(&config, "@FLAKEINPUTS@", &flakeinputs.join("\n"));
+    config = replace_block(&config, "@FLAKEMODULES@", &flakemodules.join("\n"));
+
+    config = config.replace(
+        "@PACKAGES@",
+        &if extrapkgs.is_empty() {
+            r#"  # List packages installed in system profile.
+  environment.systemPackages = with pkgs; [
+    firefox
+  ];"#
+            .to_string()
+        } else {
+            format!(
+                r#"  # List packages installed in system profile.
+  environment.systemPackages = with pkgs; [
+    firefox
+    {}
+  ];"#,
+                extrapkgs.join("\n    ")
+            )
+        },
+    );
+
+    config = config.replace(
+        "@STATEVERSION@",
+        &format!(
+            r#"  system.stateVersion = "{}"; # Did you read the comment?"#,
+            ctx.state_version
+        ),
+    );
+
+    // Placeholders only used as targets
+    for key in targets.keys() {
+        config = replace_block(&config, &format!("@{}@", key), "");
+    }
+
+    Ok(replace_values(&config, answers))
+}
+
+/// Substitutes the values the user entered, the user's names and form fields. This comes
+/// last so that every snippet can use them, and in a single pass so that placeholders
+/// within the values are never substituted themselves.
+fn replace_values(config: &str, answers: &Answers) -> String {
+    let mut values = answers
+        .forms
+        .iter()
+        .map(|(id, value)| (id.to_string(), value.to_nix()))
+        .collect::<HashMap<_, _>>();
+    if let Some(user) = &answers.user {
+        values.insert("USERNAME".to_string(), user.username.to_string());
+        values.insert("FULLNAME".to_string(), user.name.to_string());
+        values.insert("HOSTNAME".to_string(), user.hostname.to_string());
+    }
+    if values.is_empty() {
+        return config.to_string();
+    }
+    let keys = values
+        .keys()
+        .map(|key| regex::escape(key))
+        .collect::<Vec<_>>();
+    let re = Regex::new(&format!("@({})@", keys.join("|"))).unwrap();
+    re.replace_all(config, |caps: &Captures| values[&caps[1]].to_string())
+        .to_string()
+}
+
+/// Selected choices of every list, ordered by list id and choice name.
+fn selected_choices(answers: &Answers) -> Vec<&Choice> {
+    let mut lists = answers.lists.iter().collect::<Vec<_>>();
+    lists.sort_by_key(|(id, _)| *id);
+    lists
+        .into_iter()
+        .flat_map(|(_, choices)| {
+            let mut choices = choices.iter().collect::<Vec<_>>();
+            choices.sort_by_key(|(key, _)| *key);
+            choices.into_iter().map(|(_, choice)| choice)
+        })
+        .collect()
+}
+
+/// Snippets of the selected choices by the placeholder they target.
+fn choice_targets(answers: &Answers) -> BTreeMap<&str, Vec<&str>> {
+    let mut targets = BTreeMap::<_, Vec<_>>::new();
+    for choice in selected_choices(answers) {
+        for (key, snippet) in choice.targets.iter().flatten() {
+            targets
+                .entry(key.as_str())
+                .or_default()
+                .push(snippet.as_str());
+        }
+    }
+    targets
+}
+
+/// Snippets targeting `key`, indented by `spaces` as entries of a list or attribute set.
+fn target_entries(targets: &BTreeMap<&str, Vec<&str>>, key: &str, spaces: usize) -> Vec<String> {
+    targets
+        .get(key)
+        .into_iter()
+        .flatten()
+        .map(|snippet| indent_by(snippet, spaces))
+        .collect()
+}
+
+/// Substitutes `@LIST_ID.Choice@` placeholders of the choices in `lists` with whether the
+/// choice is selected. Other text between `@`s, e.g. e-mail addresses, is left alone.
+fn replace_flags(config: &str, answers: &Answers, lists: &ListChoices) -> String {
+    let mut config = config.to_string();
+    for (list, choices) in lists {
+        for choice in choices {
+            let selected = answers
+                .lists
+                .get(list)
+                .map_or(false, |selected| selected.contains_key(choice));
+            config = config.replace(&format!("@{}.{}@", list, choice), &selected.to_string());
+        }
+    }
+    config
+}
+
+/// Flake inputs as entries of the `inputs` attribute set.
+fn flake_inputs(inputs: &BTreeMap<&String, &FlakeInput>) -> Vec<String> {
+    let mut lines = vec![];
+    for (name, input) in inputs {
+        lines.push(format!(r#"    {}.url = "{}";"#, name, input.url));
+        for follows in &input.follows {
+            lines.push(format!(
+                r#"    {}.inputs.{}.follows = "{}";"#,
+                name, follows, follows
+            ));
+        }
+    }
+    lines
+}
+
+/// Substitutes a placeholder on a line of its own, dropping the line when `block` is empty.
+fn replace_block(config: &str, key: &str, block: &str) -> String {
+    if block.is_empty() {
+        config.replace(&format!("{}\n", key), "").replace(key, "")
+    } else {
+        config.replace(key, block)
+    }
+}
+
+/// Indents a configuration snippet from the YAML configs into the attribute set.
+fn indent(snippet: &str) -> String {
+    indent_by(snippet, 2)
+}
+
+/// Indents every non-empty line of `snippet` by `spaces`.
+fn indent_by(snippet: &str, spaces: usize) -> String {
+    snippet
+        .lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", " ".repeat(spaces), line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        answers::UserConfig, form::FieldValue, protocol::PartitionSchema, system::FakeSystem,
+    };
+    use std::collections::BTreeSet;
+
+    fn answers() -> Answers {
+        Answers {
+            partitions: Some(PartitionSchema::FullDisk("/dev/sda".to_string())),
+            user: Some(UserConfig {
+                name: "Alice".to_string(),
+                username: "alice".to_string(),
+                password: "hunter2".to_string(),
+                hostname: "icicle".to_string(),
+                rootpassword: None,
+                autologin: false,
+            }),
+            ..Answers::default()
+        }
+    }
+
+    fn ctx(efi: bool) -> RenderContext {
+        RenderContext {
+            efi,
+            system: "x86_64-linux".to_string(),
+            state_version: "23.05".to_string(),
+            host_id: "61626364".to_string(),
+            luks_uuid: None,
+            lists: ListChoices::new(),
+        }
+    }
+
+    #[test]
+    fn detects_context() {
+        let system = FakeSystem::new()
+            .with_file("/sys/firmware/efi/fw_platform_size", "64\n")
+            .with_file("/dev/urandom", "abcdefgh")
+            .with_output("uname", "x86_64\n")
+            .with_output("nixos-version", "23.05.1234.abcdef (Stoat)\n")
+            .with_output("lsblk", "crypt\ncrypto_LUKS 0a1b2c3d-uuid\n");
+        let ctx = RenderContext::detect(&system).unwrap();
+        assert!(ctx.efi);
+        assert_eq!(ctx.system, "x86_64-linux");
+        assert_eq!(ctx.state_version, "23.05");
+        assert_eq!(ctx.host_id, "61626364");
+        assert_eq!(ctx.luks_uuid.as_deref(), Some("0a1b2c3d-uuid"));
+    }
+
+    #[test]
+    fn renders_template_dirs_and_modules() {
+        let system = FakeSystem::new()
+            .with_file(
+                "/etc/icicle/base/configuration.nix",
+                "{\n  imports = [\n@IMPORTS@\n  ];\n\n@NETWORK@\n\n@STATEVERSION@\n}\n",
+            )
+            .with_file("/etc/icicle/base/flake.nix", "base\n")
+            .with_file("/etc/icicle/base/README.md", "Not a template\n")
+            .with_file(
+                "/etc/icicle/base/modules/gaming.nix",
+                "{ user = \"@USERNAME@\"; }\n",
+            )
+            .with_file("/etc/icicle/gnome/flake.nix", "system = \"@ARCH@\";\n");
+        let mut answers = answers();
+        let gaming: Choice = serde_yaml::from_str("modules: [gaming.nix]").unwrap();
+        answers.lists.insert(
+            "EXTRAS".to_string(),
+            HashMap::from([("Gaming".to_string(), gaming)]),
+        );
+        let dirs = [
+            PathBuf::from("/etc/icicle/base"),
+            PathBuf::from("/etc/icicle/gnome"),
+        ];
+        let files = render_dir(&system, &dirs, &answers, &ctx(true)).unwrap();
+        let names = files
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["configuration.nix", "flake.nix", "gaming.nix"]);
+        assert!(files[0]
+            .1
+            .contains("  imports = [\n      ./gaming.nix\n  ];"));
+        assert!(files[0].1.contains("  networking.hostName = \"icicle\";"));
+        assert!(files[0].1.contains("  system.stateVersion = \"23.05\";"));
+        assert_eq!(files[1].1, "system = \"x86_64-linux\";\n");
+        assert_eq!(files[2].1, "{ user = \"alice\"; }\n");
+    }
+
+    #[test]
+    fn missing_module_is_an_error() {
+        let system = FakeSystem::new().with_file("/etc/icicle/base/configuration.nix", "{ }\n");
+        let mut answers = answers();
+        let gaming: Choice = serde_yaml::from_str("modules: [gaming.nix]").unwrap();
+        answers.lists.insert(
+            "EXTRAS".to_string(),
+            HashMap::from([("Gaming".to_string(), gaming)]),
+        );
+        let dirs = [PathBuf::from("/etc/icicle/base")];
+        assert!(render_dir(&system, &dirs, &answers, &ctx(true)).is_err());
+    }
+
+    #[test]
+    fn renders_bootloader_for_firmware() {
+        let mut answers = answers();
+        let bios = render("@BOOTLOADER@", &answers, &ctx(false)).unwrap();
+        assert!(bios.contains("  boot.loader.grub.device = \"/dev/sda\";"));
+        assert!(!bios.contains("canTouchEfiVariables"));
+
+        answers.bootloader.loader = Bootloader::Limine;
+        answers.portable = true;
+        let efi = render("@BOOTLOADER@", &answers, &ctx(true)).unwrap();
+        assert!(efi.contains("  boot.loader.limine.enable = true;"));
+        assert!(efi.contains("  boot.loader.limine.efiInstallAsRemovable = true;"));
+        assert!(efi.contains("  boot.loader.efi.canTouchEfiVariables = false;"));
+        assert!(!efi.contains("/dev/sda"));
+    }
+
+    #[test]
+    fn unlocks_lvm_on_luks_by_uuid() {
+        let mut answers = answers();
+        answers.layout = DiskLayout::LvmOnLuks {
+            passphrase: "secret".to_string(),
+        };
+        let mut ctx = ctx(true);
+        assert!(render("@BOOTLOADER@", &answers, &ctx).is_err());
+        ctx.luks_uuid = Some("0a1b2c3d-uuid".to_string());
+        let config = render("@BOOTLOADER@", &answers, &ctx).unwrap();
+        assert!(config.contains("    device = \"/dev/disk/by-uuid/0a1b2c3d-uuid\";"));
+        assert!(!config.contains("secret"));
+    }
+
+    #[test]
+    fn adds_choice_snippets_to_several_targets() {
+        let mut answers = answers();
+        let steam: Choice = serde_yaml::from_str(
+            r#"
+targets:
+  SERVICES: "programs.steam.enable = true;"
+  PACKAGES: "mangohud"
+  FLAKEINPUTS: "nix-gaming.url = \"github:fufexan/nix-gaming\";"
+  FLAKEMODULES: "inputs.nix-gaming.nixosModules.pipewireLowLatency"
+"#,
+        )
+        .unwrap();
+        let syncthing: Choice = serde_yaml::from_str(
+            r#"
+targets:
+  SERVICES: "services.syncthing.enable = true;"
+  IMPORTS: "./syncthing.nix"
+"#,
+        )
+        .unwrap();
+        answers.lists.insert(
+            "EXTRAS".to_string(),
+            HashMap::from([
+                ("Steam".to_string(), steam),
+                ("Syncthing".to_string(), syncthing),
+            ]),
+        );
+        let template = r#"imports = [
+@IMPORTS@
+];
+inputs = {
+@FLAKEINPUTS@
+};
+modules = [
+@FLAKEMODULES@
+];
+@SERVICES@
+@PACKAGES@
+"#;
+        assert_eq!(
+            render(template, &answers, &ctx(true)).unwrap(),
+            r#"imports = [
+      ./syncthing.nix
+];
+inputs = {
+    nix-gaming.url = "github:fufexan/nix-gaming";
+};
+modules = [
+          inputs.nix-gaming.nixosModules.pipewireLowLatency
+];
+  programs.steam.enable = true;
+  services.syncthing.enable = true;
+  # List packages installed in system profile.
+  environment.systemPackages = with pkgs; [
+    firefox
+    mangohud
+  ];
+"#
+        );
+    }
+
+    #[test]
+    fn replaces_flags_of_configured_choices_only() {
+        let mut answers = answers();
+        let zen: Choice = serde_yaml::from_str("{}").unwrap();
+        answers.lists.insert(
+            "KERNEL".to_string(),
+            HashMap::from([("Zen".to_string(), zen)]),
+        );
+        let mut ctx = ctx(true);
+        ctx.lists = ListChoices::from([(
+            "KERNEL".to_string(),
+            BTreeSet::from(["Zen".to_string(), "Latest".to_string()]),
+        )]);
+        let template = r#"zen = @KERNEL.Zen@;
+latest = @KERNEL.Latest@;
+unknown = @KERNEL.Lts@ @DESKTOP.GNOME@;
+maintainers = [ "alice@example.org" "bob@example.org" ];
+"#;
+        assert_eq!(
+            render(template, &answers, &ctx).unwrap(),
+            r#"zen = true;
+latest = false;
+unknown = @KERNEL.Lts@ @DESKTOP.GNOME@;
+maintainers = [ "alice@example.org" "bob@example.org" ];
+"#
+        );
+    }
+
+    #[test]
+    fn substitutes_user_values_last_and_once() {
+        let mut answers = answers();
+        if let Some(user) = &mut answers.user {
+            user.name = "Alice @STATEVERSION@".to_string();
+        }
+        answers.forms.insert(
+            "MOTD".to_string(),
+            FieldValue::Text("@PACKAGES@ @KERNEL.Zen@ @USERNAME@".to_string()),
+        );
+        let tools: Choice =
+            serde_yaml::from_str("config: 'users.users.@USERNAME@.shell = pkgs.zsh;'").unwrap();
+        answers.lists.insert(
+            "TOOLS".to_string(),
+            HashMap::from([("Zsh".to_string(), tools)]),
+        );
+        let mut ctx = ctx(true);
+        ctx.lists =
+            ListChoices::from([("KERNEL".to_string(), BTreeSet::from(["Zen".to_string()]))]);
+        let template = "@TOOLS@description = \"@FULLNAME@\";\nmotd = @MOTD@;\n@STATEVERSION@\n";
+        assert_eq!(
+            render(template, &answers, &ctx).unwrap(),
+            r#"  users.users.alice.shell = pkgs.zsh;
+description = "Alice @STATEVERSION@";
+motd = "@PACKAGES@ @KERNEL.Zen@ @USERNAME@";
+  system.stateVersion = "23.05"; # Did you read the comment?
+"#
+        );
+    }
+}
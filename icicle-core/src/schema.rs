@@ -0,0 +1,84 @@
+//! JSON Schemas of the config files, for validation in editors.
+
+use crate::{
+    config::{DesktopsConfig, IcicleConfig, InstallationConfig},
+    quirks::QuirksConfig,
+};
+use schemars::{
+    gen::SchemaSettings,
+    schema::{InstanceType, RootSchema, Schema, SchemaObject, SubschemaValidation},
+};
+
+/// Schemas of `config.yml`, installation configs, `desktops.yml` and `quirks.yml`,
+/// by file name.
+pub fn schemas() -> Vec<(&'static str, RootSchema)> {
+    let gen = || SchemaSettings::draft07().into_generator();
+    vec![
+        (
+            "config.schema.json",
+            gen().into_root_schema_for::<IcicleConfig>(),
+        ),
+        (
+            "installation.schema.json",
+            gen().into_root_schema_for::<InstallationConfig>(),
+        ),
+        (
+            "desktops.schema.json",
+            gen().into_root_schema_for::<DesktopsConfig>(),
+        ),
+        (
+            "quirks.schema.json",
+            gen().into_root_schema_for::<QuirksConfig>(),
+        ),
+    ]
+}
+
+/// Splits the schema of an externally tagged enum into the schemas of its variants'
+/// contents. serde_yaml reads these enums from YAML tags like `!list`, which JSON Schema
+/// has no notion of. Variants without required fields may also be left empty.
+pub(crate) fn untagged_variants(schema: Schema) -> Vec<Schema> {
+    let variants = match schema {
+        Schema::Object(SchemaObject {
+            subschemas: Some(subschemas),
+            ..
+        }) => subschemas.one_of.unwrap_or_default(),
+        schema => return vec![schema],
+    };
+    variants
+        .into_iter()
+        .map(|variant| match variant {
+            Schema::Object(SchemaObject {
+                object: Some(object),
+                ..
+            }) if object.properties.len() == 1 => {
+                let content = object
+                    .properties
+                    .into_values()
+                    .next()
+                    .unwrap_or(Schema::Bool(true));
+                match &content {
+                    Schema::Object(SchemaObject {
+                        object: Some(fields),
+                        ..
+                    }) if fields.required.is_empty() => SchemaObject {
+                        subschemas: Some(Box::new(SubschemaValidation {
+                            any_of: Some(vec![
+                                content.clone(),
+                                SchemaObject {
+                                    instance_type: Some(InstanceType::Null.into()),
+                                    ..Default::default()
+                                }
+                                .into(),
+                            ]),
+                            ..Default::default()
+                        })),
+                        ..Default::default()
+                    }
+                    .into(),
+                    _ => content,
+                }
+            }
+            variant => variant,
+        })
+        .collect()
+}
@@ -0,0 +1,376 @@
+//! Every side effect Icicle has on the host goes through [`System`], so the whole
+//! install flow can be exercised against [`FakeSystem`] without root or NixOS.
+
+use crate::{
+    helper::run_helper,
+    protocol::{HelperError, Response},
+};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs, io,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
+    thread,
+};
+
+/// A command to run on the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cmd {
+    pub program: String,
+    pub args: Vec<String>,
+    pub stdin: Option<Vec<u8>>,
+    /// Run through pkexec
+    pub privileged: bool,
+}
+
+impl Cmd {
+    pub fn new(program: &str) -> Self {
+        Cmd {
+            program: program.to_string(),
+            args: vec![],
+            stdin: None,
+            privileged: false,
+        }
+    }
+
+    pub fn privileged(program: &str) -> Self {
+        Cmd {
+            privileged: true,
+            ..Cmd::new(program)
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<str>) -> Self {
+        self.args.push(arg.as_ref().to_string());
+        self
+    }
+
+    pub fn args<S: AsRef<str>>(mut self, args: impl IntoIterator<Item = S>) -> Self {
+        self.args
+            .extend(args.into_iter().map(|a| a.as_ref().to_string()));
+        self
+    }
+
+    pub fn stdin(mut self, stdin: impl Into<Vec<u8>>) -> Self {
+        self.stdin = Some(stdin.into());
+        self
+    }
+
+    /// Full command line, including pkexec for privileged commands.
+    pub fn command_line(&self) -> Vec<String> {
+        let mut line = vec!["/usr/bin/env".to_string()];
+        if self.privileged {
+            line.push("pkexec".to_string());
+        }
+        line.push(self.program.to_string());
+        line.extend(self.args.iter().cloned());
+        line
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CmdOutput {
+    pub status: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl CmdOutput {
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+
+    pub fn stdout_str(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).to_string()
+    }
+}
+
+pub trait System: Send + Sync {
+    /// Runs `cmd` to completion.
+    fn run(&self, cmd: &Cmd) -> Result<CmdOutput>;
+    /// Starts `cmd` without waiting for it to finish.
+    fn spawn(&self, cmd: &Cmd) -> Result<()>;
+    /// Runs `icicle-helper` with `args` and `input` on stdin.
+    fn helper(&self, args: &[&str], input: Option<&[u8]>) -> Result<Response, HelperError>;
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+    /// Up to `len` bytes from the start of the file at `path`, e.g. `/dev/urandom`.
+    fn read_bytes(&self, path: &Path, len: usize) -> io::Result<Vec<u8>>;
+    /// Paths of the entries of the directory at `path`.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+    fn exists(&self, path: &Path) -> bool;
+}
+
+/// The machine Icicle is running on.
+pub struct RealSystem {
+    helper: PathBuf,
+}
+
+impl RealSystem {
+    pub fn new(helper: impl Into<PathBuf>) -> Self {
+        RealSystem {
+            helper: helper.into(),
+        }
+    }
+
+    fn command(cmd: &Cmd) -> Command {
+        let mut command = if cmd.privileged {
+            let mut command = Command::new("pkexec");
+            command.arg(&cmd.program);
+            command
+        } else {
+            Command::new(&cmd.program)
+        };
+        command.args(&cmd.args);
+        command
+    }
+}
+
+impl System for RealSystem {
+    fn run(&self, cmd: &Cmd) -> Result<CmdOutput> {
+        let mut command = Self::command(cmd);
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        if cmd.stdin.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to run {}", cmd.program))?;
+        // Input is written from a thread, so a child that fills its stdout or stderr
+        // before reading all of it does not block both sides
+        let writer = match &cmd.stdin {
+            Some(input) => {
+                let mut stdin = child.stdin.take().context("Failed to get stdin")?;
+                let input = input.clone();
+                Some(thread::spawn(move || stdin.write_all(&input)))
+            }
+            None => None,
+        };
+        let output = child.wait_with_output()?;
+        if let Some(writer) = writer {
+            writer
+                .join()
+                .map_err(|_| anyhow!("Failed to write to {}", cmd.program))?
+                .with_context(|| format!("Failed to write to {}", cmd.program))?;
+        }
+        Ok(CmdOutput {
+            status: output.status.code(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    fn spawn(&self, cmd: &Cmd) -> Result<()> {
+        Self::command(cmd)
+            .spawn()
+            .with_context(|| format!("Failed to run {}", cmd.program))?;
+        Ok(())
+    }
+
+    fn helper(&self, args: &[&str], input: Option<&[u8]>) -> Result<Response, HelperError> {
+        run_helper(&self.helper, args, input)
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn read_bytes(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        fs::File::open(path)?
+            .take(len as u64)
+            .read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect()
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    Run(Cmd),
+    Spawn(Cmd),
+    Helper {
+        args: Vec<String>,
+        input: Option<Vec<u8>>,
+    },
+}
+
+/// Records every call instead of touching the host. Command outputs, files and
+/// helper responses are canned; anything not configured succeeds with no output.
+#[derive(Default)]
+pub struct FakeSystem {
+    calls: Mutex<Vec<Call>>,
+    outputs: HashMap<String, CmdOutput>,
+    files: BTreeMap<PathBuf, String>,
+    helper_responses: HashMap<String, Result<Response, HelperError>>,
+}
+
+impl FakeSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `program` succeed with `stdout`.
+    pub fn with_output(mut self, program: &str, stdout: &str) -> Self {
+        self.outputs.insert(
+            program.to_string(),
+            CmdOutput {
+                status: Some(0),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: vec![],
+            },
+        );
+        self
+    }
+
+    /// Makes `program` fail with `stderr`.
+    pub fn with_failure(mut self, program: &str, stderr: &str) -> Self {
+        self.outputs.insert(
+            program.to_string(),
+            CmdOutput {
+                status: Some(1),
+                stdout: vec![],
+                stderr: stderr.as_bytes().to_vec(),
+            },
+        );
+        self
+    }
+
+    pub fn with_file(mut self, path: impl Into<PathBuf>, contents: &str) -> Self {
+        self.files.insert(path.into(), contents.to_string());
+        self
+    }
+
+    /// Sets the response of the helper subcommand `subcommand`.
+    pub fn with_helper_response(
+        mut self,
+        subcommand: &str,
+        response: Result<Response, HelperError>,
+    ) -> Self {
+        self.helper_responses
+            .insert(subcommand.to_string(), response);
+        self
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    /// Files written into the target root through `icicle-helper write-file`.
+    pub fn written_files(&self) -> Vec<(String, String)> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::Helper { args, input }
+                    if args.first().map(|a| a.as_str()) == Some("write-file") =>
+                {
+                    let path = args
+                        .iter()
+                        .position(|a| a == "--path")
+                        .and_then(|i| args.get(i + 1))?
+                        .to_string();
+                    Some((
+                        path,
+                        String::from_utf8_lossy(&input.unwrap_or_default()).to_string(),
+                    ))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn record(&self, call: Call) {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.push(call);
+        }
+    }
+}
+
+impl System for FakeSystem {
+    fn run(&self, cmd: &Cmd) -> Result<CmdOutput> {
+        self.record(Call::Run(cmd.clone()));
+        Ok(self
+            .outputs
+            .get(&cmd.program)
+            .cloned()
+            .unwrap_or(CmdOutput {
+                status: Some(0),
+                ..CmdOutput::default()
+            }))
+    }
+
+    fn spawn(&self, cmd: &Cmd) -> Result<()> {
+        self.record(Call::Spawn(cmd.clone()));
+        Ok(())
+    }
+
+    fn helper(&self, args: &[&str], input: Option<&[u8]>) -> Result<Response, HelperError> {
+        self.record(Call::Helper {
+            args: args.iter().map(|a| a.to_string()).collect(),
+            input: input.map(|i| i.to_vec()),
+        });
+        args.first()
+            .and_then(|subcommand| self.helper_responses.get(*subcommand))
+            .cloned()
+            .unwrap_or(Ok(Response::Done))
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.display().to_string()))
+    }
+
+    fn read_bytes(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        let contents = self.read_to_string(path)?;
+        Ok(contents.bytes().take(len).collect())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let entries = self
+            .files
+            .keys()
+            .filter_map(|p| p.strip_prefix(path).ok()?.components().next())
+            .map(|name| path.join(name))
+            .collect::<BTreeSet<_>>();
+        if entries.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                path.display().to_string(),
+            ))
+        } else {
+            Ok(entries.into_iter().collect())
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.keys().any(|p| p.starts_with(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_input_larger_than_a_pipe() {
+        let input = vec![b'x'; 1 << 20];
+        let output = RealSystem::new("icicle-helper")
+            .run(&Cmd::new("cat").stdin(input.clone()))
+            .unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, input);
+    }
+}
@@ -0,0 +1,55 @@
+//! Translatable strings in the configs.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A string shown to the user, translated either through gettext or inline.
+///
+/// ```yml
+/// title: "Kernel"
+/// title:
+///   en: "Kernel"
+///   de: "Kernel"
+///   pt_BR: "Núcleo"
+/// ```
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Text {
+    /// Translated through the config's gettext domain
+    Gettext(String),
+    /// Translations by language, `en` is used for languages without one
+    Inline(BTreeMap<String, String>),
+}
+
+impl Text {
+    /// The inline translation for `language`, e.g. `pt_BR.UTF-8` tries `pt_BR`, `pt`,
+    /// then `en`, then any translation. Gettext strings are returned as is.
+    pub fn pick(&self, language: &str) -> &str {
+        match self {
+            Text::Gettext(msgid) => msgid,
+            Text::Inline(translations) => {
+                let language = language.split(['.', '@']).next().unwrap_or_default();
+                let short = language.split('_').next().unwrap_or_default();
+                [language, short, "en"]
+                    .iter()
+                    .find_map(|lang| translations.get(*lang))
+                    .or_else(|| translations.values().next())
+                    .map(|text| text.as_str())
+                    .unwrap_or_default()
+            }
+        }
+    }
+}
+
+impl Default for Text {
+    fn default() -> Self {
+        Text::Gettext(String::new())
+    }
+}
+
+impl From<&str> for Text {
+    fn from(text: &str) -> Self {
+        Text::Gettext(text.to_string())
+    }
+}
@@ -1,54 +1,82 @@
-use anyhow::{anyhow, Context, Result};
 use clap::{self, FromArgMatches, Subcommand};
 use disk_types::{BlockDeviceExt, FileSystem, PartitionTable, PartitionType, Sector, SectorExt};
 use distinst_disk_ops::FormatPartitions;
 use distinst_disks::{DiskExt, PartitionBuilder, PartitionFlag};
-use serde::{Deserialize, Serialize};
+use icicle_core::protocol::{
+    encode, CustomPartition, Disk, DiskLayout, Envelope, ErrorCode, HelperError, ImageFormat,
+    Output, Partition, PartitionRequest, PartitionSchema, Progress, Response, RootFilesystem,
+    WithCode, LUKS_NAME, TARGET_ROOT, VOLUME_GROUP, ZFS_POOL,
+};
+use rustix::{
+    fs::{
+        cwd, fchown, fstat, fsync, major, minor, mkdirat, openat, renameat, statat, unlinkat,
+        AtFlags, FileType, Mode, OFlags,
+    },
+    io::Errno,
+    process::{Gid, Uid},
+};
 use std::{
     collections::HashMap,
+    ffi::{OsStr, OsString},
     fs::{self, File},
     io::{self, Read, Write},
-    process::Command,
+    os::unix::{
+        fs::{DirBuilderExt, PermissionsExt},
+        io::OwnedFd,
+    },
+    path::{Component, Path, PathBuf},
+    process::{Command, Output as ProcessOutput, Stdio},
 };
 
-#[derive(Serialize)]
-struct Disk {
-    name: String,
-    size: u64,
-    partitions: Vec<Partition>,
-}
+type Result<T> = std::result::Result<T, HelperError>;
 
-#[derive(Serialize)]
-struct Partition {
-    name: String,
-    format: String,
-    size: u64,
-}
+/// Datasets of a ZFS root and where they are mounted.
+const ZFS_DATASETS: [(&str, &str); 3] = [("root", "/"), ("nix", "/nix"), ("home", "/home")];
 
-#[derive(Deserialize, Debug, Clone)]
-pub enum PartitionSchema {
-    FullDisk(String),
-    Custom(HashMap<String, CustomPartition>),
-}
+/// Largest swap logical volume of the LVM on LUKS layout, smaller machines get as much
+/// swap as they have memory.
+const SWAP_MAX_SIZE: u64 = 8 * 1024 * 1024 * 1024;
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct CustomPartition {
-    pub format: Option<String>,
-    pub mountpoint: Option<String>,
-    pub device: String,
-}
+/// Largest disk an MBR partition table can address, larger disks get GPT on BIOS machines.
+const MBR_MAX_SIZE: u64 = 2 * 1024 * 1024 * 1024 * 1024;
 
 #[derive(Subcommand, Debug)]
 enum SubCommands {
     GetPartitions {},
-    Partition {},
+    /// Partition and mount disks as described by the `PartitionRequest` on stdin
+    Partition {
+        /// Install to this disk image instead of a physical disk, attached as a loop device.
+        /// Either a new file in a directory the user running pkexec can write to, or a
+        /// regular file of theirs
+        #[clap(long)]
+        image: Option<PathBuf>,
+        /// Size of the image in bytes, required if it does not exist yet
+        #[clap(long, requires = "image")]
+        size: Option<u64>,
+        /// Format of the image, `raw` or `qcow2`
+        #[clap(long, requires = "image", default_value = "raw")]
+        format: ImageFormat,
+    },
+    /// Write the contents of stdin to a file inside the target root
     WriteFile {
+        /// Path of the file, relative to the target root
         #[clap(short, long)]
         path: String,
+        /// Octal file mode of permission bits only, defaults to 644
+        #[clap(short, long)]
+        mode: Option<String>,
+        /// Owner of the file as `user[:group]`, names or numeric ids
         #[clap(short, long)]
-        contents: String,
+        owner: Option<String>,
     },
     Unmount {},
+    /// Unmount the target root and detach a disk image attached by `partition --image`
+    Detach {
+        #[clap(long)]
+        image: PathBuf,
+        #[clap(long, default_value = "raw")]
+        format: ImageFormat,
+    },
 }
 
 fn main() {
@@ -59,284 +87,1539 @@ fn main() {
         .map_err(|err| err.exit())
         .unwrap();
 
-    if users::get_effective_uid() != 0 {
-        eprintln!("icicle-helper must be run as root");
-        std::process::exit(1);
+    let result = if users::get_effective_uid() != 0 {
+        Err(HelperError::new(
+            ErrorCode::NotRoot,
+            "icicle-helper must be run as root",
+        ))
+    } else {
+        match derived_subcommands {
+            SubCommands::GetPartitions {} => get_partitions(),
+            SubCommands::Partition {
+                image,
+                size,
+                format,
+            } => match image {
+                Some(image) => partition_image(&image, size, format),
+                None => partition(),
+            },
+            SubCommands::WriteFile { path, mode, owner } => {
+                write_file(&path, mode.as_deref(), owner.as_deref())
+            }
+            SubCommands::Unmount {} => unmount(),
+            SubCommands::Detach { image, format } => detach(&image, format),
+        }
+    };
+
+    match result {
+        Ok(response) => println!("{}", encode(Output::Response(response))),
+        Err(e) => {
+            println!("{}", encode(Output::Error(e)));
+            std::process::exit(1);
+        }
     }
+}
+
+fn progress(progress: Progress) {
+    println!("{}", encode(Output::Progress(progress)));
+}
+
+fn log(message: String) {
+    println!("{}", encode(Output::Log(message)));
+}
+
+/// Reads and unwraps a versioned request from stdin.
+fn read_request<T: serde::de::DeserializeOwned>() -> Result<T> {
+    let mut buf = String::new();
+    io::stdin()
+        .lock()
+        .read_to_string(&mut buf)
+        .code(ErrorCode::InvalidRequest, "Failed to read request")?;
+    serde_json::from_str::<Envelope<T>>(&buf)
+        .code(ErrorCode::InvalidRequest, "Failed to parse request")?
+        .into_body()
+}
 
-    match derived_subcommands {
-        SubCommands::GetPartitions {} => {
-            let mut outdisks = vec![];
+fn get_partitions() -> Result<Response> {
+    let mut outdisks = vec![];
+    let systems = os_prober();
 
-            let mut devicevec = vec![];
-            let devices = libparted::Device::devices(true);
-            for device in devices {
-                devicevec.push(device);
+    let mut devicevec = vec![];
+    let devices = libparted::Device::devices(true);
+    for device in devices {
+        devicevec.push(device);
+    }
+    devicevec.sort_by(|a, b| a.path().to_str().cmp(&b.path().to_str()));
+    for mut device in devicevec {
+        // Logical volumes and LUKS containers are device mapper devices, volume groups
+        // are listed below
+        let path = device.path().to_string_lossy().to_string();
+        if path.starts_with("/dev/mapper/") || path.starts_with("/dev/dm-") {
+            continue;
+        }
+        let sectorsize = device.sector_size();
+        let mut disk = Disk {
+            name: device.path().to_string_lossy().to_string(),
+            size: device.length() * sectorsize,
+            partitions: vec![],
+            table: None,
+            gap: device.length() * sectorsize,
+            removable: removable(device.path()),
+            volume_group: false,
+        };
+        if let Ok(partdisk) = libparted::Disk::new(&mut device) {
+            disk.table = partdisk
+                .get_disk_type_name()
+                .map(|name| String::from_utf8_lossy(name).to_string());
+            let mut partvec = vec![];
+            for part in partdisk.parts() {
+                if part.get_path().is_none() {
+                    continue;
+                }
+                partvec.push(part);
             }
-            devicevec.sort_by(|a, b| a.path().to_str().cmp(&b.path().to_str()));
-            for mut device in devicevec {
-                let sectorsize = device.sector_size();
-                let mut disk = Disk {
-                    name: device.path().to_str().unwrap().to_string(),
-                    size: device.length() * sectorsize,
-                    partitions: vec![],
-                };
-                if let Ok(partdisk) = libparted::Disk::new(&mut device) {
-                    let mut partvec = vec![];
-                    for part in partdisk.parts() {
-                        if part.get_path().is_none() {
-                            continue;
-                        }
-                        partvec.push(part);
-                    }
-                    partvec.sort_by(|a, b| a.get_path().cmp(&b.get_path()));
-                    for part in partvec {
-                        disk.partitions.push(Partition {
-                            name: part.get_path().unwrap().to_string_lossy().to_string(),
-                            format: part.fs_type_name().unwrap_or("unknown").to_string(),
-                            size: (part.geom_length() as u64) * sectorsize,
-                        });
-                    }   
+            partvec.sort_by(|a, b| a.get_path().cmp(&b.get_path()));
+            for part in partvec {
+                if let Some(path) = part.get_path() {
+                    let name = path.to_string_lossy().to_string();
+                    let os = match &systems {
+                        Some(systems) => systems.get(&name).cloned(),
+                        None if part.get_flag(PartitionFlag::PED_PARTITION_ESP) => scan_esp(path),
+                        None => None,
+                    };
+                    disk.gap = disk.gap.min((part.geom_start() as u64) * sectorsize);
+                    disk.partitions.push(Partition {
+                        name,
+                        format: part.fs_type_name().unwrap_or("unknown").to_string(),
+                        size: (part.geom_length() as u64) * sectorsize,
+                        bios_boot: part.get_flag(PartitionFlag::PED_PARTITION_BIOS_GRUB),
+                        os,
+                    });
                 }
-                outdisks.push(disk);
             }
-            println!("{}", serde_json::to_string(&outdisks).unwrap());
         }
-        SubCommands::Partition {} => {
-            partition().unwrap();
+        outdisks.push(disk);
+    }
+    outdisks.extend(volume_groups(&systems));
+    Ok(Response::Disks(outdisks))
+}
+
+/// Every LVM volume group as a disk, with its logical volumes as partitions. Empty if
+/// LVM is not available.
+fn volume_groups(systems: &Option<HashMap<String, String>>) -> Vec<Disk> {
+    // Logical volumes only have a device to format and mount once they are active
+    let _ = Command::new("vgchange").arg("-ay").output();
+    let report = |program: &str, fields: &str| {
+        run_command(
+            program,
+            [
+                "--noheadings",
+                "--nosuffix",
+                "--units",
+                "b",
+                "--separator",
+                ":",
+                "-o",
+                fields,
+            ],
+            ErrorCode::Lvm,
+        )
+        .unwrap_or_default()
+    };
+    let mut disks = report("vgs", "vg_name,vg_size")
+        .lines()
+        .filter_map(|line| {
+            let (name, size) = line.trim().split_once(':')?;
+            Some(Disk {
+                name: format!("/dev/{}", name),
+                size: size.parse().ok()?,
+                partitions: vec![],
+                table: None,
+                gap: 0,
+                removable: false,
+                volume_group: true,
+            })
+        })
+        .collect::<Vec<_>>();
+    for line in report("lvs", "vg_name,lv_path,lv_size").lines() {
+        let mut fields = line.trim().split(':');
+        if let (Some(group), Some(path), Some(size)) = (fields.next(), fields.next(), fields.next())
+        {
+            let disk = disks
+                .iter_mut()
+                .find(|disk| disk.name == format!("/dev/{}", group));
+            if let (Some(disk), Ok(size)) = (disk, size.parse()) {
+                disk.partitions.push(Partition {
+                    name: path.to_string(),
+                    format: run_command(
+                        "blkid",
+                        ["-o", "value", "-s", "TYPE", path],
+                        ErrorCode::Lvm,
+                    )
+                    .unwrap_or_else(|_| "unknown".to_string()),
+                    size,
+                    bios_boot: false,
+                    os: systems
+                        .as_ref()
+                        .and_then(|systems| systems.get(path).cloned()),
+                });
+            }
         }
-        SubCommands::WriteFile { path, contents } => {
-            let mut file = File::create(path).unwrap();
-            file.write_all(contents.as_bytes()).unwrap();
+    }
+    for disk in &mut disks {
+        disk.partitions.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    disks
+}
+
+/// Whether the kernel reports the disk as removable, or it is attached over USB. USB
+/// disks often do not report themselves as removable.
+fn removable(device: &Path) -> bool {
+    let block = match device.file_name() {
+        Some(name) => Path::new("/sys/block").join(name),
+        None => return false,
+    };
+    let flagged = fs::read_to_string(block.join("removable"))
+        .map_or(false, |removable| removable.trim() == "1");
+    let usb = fs::canonicalize(&block).map_or(false, |path| {
+        path.components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with("usb"))
+    });
+    flagged || usb
+}
+
+/// Operating systems found by os-prober, by the partition they boot from.
+/// `None` if os-prober is not available.
+fn os_prober() -> Option<HashMap<String, String>> {
+    let output = Command::new("os-prober").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Lines look like `/dev/sda1@/efi/Microsoft/Boot/bootmgfw.efi:Windows Boot Manager:Windows:efi`
+    let systems = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let device = fields.next()?.split('@').next()?;
+            let name = fields.next()?;
+            Some((device.to_string(), name.to_string()))
+        })
+        .collect();
+    Some(systems)
+}
+
+/// Looks for the Windows Boot Manager on an EFI system partition, for systems
+/// without os-prober.
+fn scan_esp(device: &Path) -> Option<String> {
+    // A fresh directory only root can reach, rather than a predictable path in /tmp
+    let dir = mkdtemp("/run/icicle-esp-").ok()?;
+    let mounted = Command::new("mount")
+        .args(["-o", "ro"])
+        .arg(device)
+        .arg(&dir)
+        .status()
+        .map_or(false, |status| status.success());
+    let windows = mounted && dir.join("EFI/Microsoft/Boot/bootmgfw.efi").exists();
+    if mounted {
+        let _ = Command::new("umount").arg(&dir).status();
+    }
+    let _ = fs::remove_dir(&dir);
+    windows.then(|| "Windows Boot Manager".to_string())
+}
+
+/// Creates a new directory named `prefix` followed by random characters, readable only
+/// by root, like mkdtemp(3). Never reuses an existing directory.
+fn mkdtemp(prefix: &str) -> io::Result<PathBuf> {
+    let mut random = [0; 8];
+    File::open("/dev/urandom")?.read_exact(&mut random)?;
+    let suffix = random
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    let dir = PathBuf::from(format!("{}{}", prefix, suffix));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    Ok(dir)
+}
+
+fn unmount() -> Result<Response> {
+    let output = Command::new("umount")
+        .arg("-R")
+        .arg("-f")
+        .arg(TARGET_ROOT)
+        .output()
+        .code(ErrorCode::Unmount, "Failed to run umount")?;
+    if !output.status.success() {
+        log(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    // Exported pools are imported by the installed system without forcing it
+    let _ = Command::new("zpool").args(["export", ZFS_POOL]).output();
+    // Close the LVM on LUKS layout, so that a disk image can be detached
+    let _ = Command::new("swapoff")
+        .arg(format!("/dev/{}/swap", VOLUME_GROUP))
+        .output();
+    let _ = Command::new("vgchange")
+        .args(["-an", VOLUME_GROUP])
+        .output();
+    let _ = Command::new("cryptsetup")
+        .args(["close", LUKS_NAME])
+        .output();
+    Ok(Response::Done)
+}
+
+/// Runs `program` and returns its stdout, failing with `code` if it does not succeed.
+fn run_command<S: AsRef<OsStr>>(
+    program: &str,
+    args: impl IntoIterator<Item = S>,
+    code: ErrorCode,
+) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .code(code, format!("Failed to run {}", program))?;
+    command_stdout(program, output, code)
+}
+
+/// Runs `program` with `input` on its stdin, for secrets that must not show up in the
+/// process list, and returns its stdout.
+fn run_command_input<S: AsRef<OsStr>>(
+    program: &str,
+    args: impl IntoIterator<Item = S>,
+    input: &str,
+    code: ErrorCode,
+) -> Result<String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .code(code, format!("Failed to run {}", program))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input.as_bytes())
+            .code(code, format!("Failed to write to {}", program))?;
+    }
+    let output = child
+        .wait_with_output()
+        .code(code, format!("Failed to run {}", program))?;
+    command_stdout(program, output, code)
+}
+
+/// Stdout of a finished `program`, failing with `code` if it did not succeed.
+fn command_stdout(program: &str, output: ProcessOutput, code: ErrorCode) -> Result<String> {
+    if !output.status.success() {
+        return Err(HelperError::new(
+            code,
+            format!(
+                "{} failed: {}",
+                program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// File the installation is written to, qcow2 images are staged as raw next to the target.
+fn staging_path(image: &Path, format: ImageFormat) -> PathBuf {
+    match format {
+        ImageFormat::Raw => image.to_path_buf(),
+        ImageFormat::Qcow2 => {
+            let mut path = image.as_os_str().to_owned();
+            path.push(".raw");
+            PathBuf::from(path)
         }
-        SubCommands::Unmount {} => {
-            if let Err(e) = Command::new("umount")
-                .arg("-R")
-                .arg("-f")
-                .arg("/tmp/icicle")
-                .output()
-            {
-                eprintln!("{}", e);
-                std::process::exit(1);
+    }
+}
+
+fn partition_image(image: &Path, size: Option<u64>, format: ImageFormat) -> Result<Response> {
+    let request: PartitionRequest = read_request()?;
+    if !matches!(request.schema, PartitionSchema::FullDisk(_)) {
+        return Err(HelperError::new(
+            ErrorCode::InvalidRequest,
+            "Disk images can only be partitioned as a full disk",
+        ));
+    }
+
+    let staging = staging_path(image, format);
+    progress(Progress::AttachingImage {
+        image: image.to_string_lossy().to_string(),
+    });
+    let file = open_image(&staging, true)?;
+    let len = file
+        .metadata()
+        .code(ErrorCode::Image, "Failed to read image size")?
+        .len();
+    match size {
+        Some(size) => file
+            .set_len(size)
+            .code(ErrorCode::Image, "Failed to resize image")?,
+        None if len == 0 => {
+            return Err(HelperError::new(
+                ErrorCode::InvalidRequest,
+                "A size is required to create a new image",
+            ))
+        }
+        None => {}
+    }
+
+    let device = run_command(
+        "losetup",
+        [
+            OsStr::new("--find"),
+            OsStr::new("--show"),
+            OsStr::new("--partscan"),
+            staging.as_os_str(),
+        ],
+        ErrorCode::Image,
+    )?;
+    log(format!("Attached {} to {}", staging.display(), device));
+    // losetup opens the image by its path again, which may have been swapped since
+    if let Err(e) = check_backing_file(&device, &file) {
+        let _ = run_command("losetup", ["--detach", &device], ErrorCode::Image);
+        return Err(e);
+    }
+    drop(file);
+
+    if let Err(e) = partition_full_disk(&device, &request.esp, request.filesystem, &request.layout)
+    {
+        let _ = unmount();
+        let _ = detach_loop(&staging);
+        return Err(e);
+    }
+    Ok(Response::Attached { device })
+}
+
+/// Opens a disk image, creating it for the user who ran pkexec if `create` is set and it
+/// does not exist. Refuses symlinks, anything but regular files and images of other
+/// users, so the helper can not be pointed at a system file.
+fn open_image(path: &Path, create: bool) -> Result<File> {
+    let (uid, gid) = invoking_user();
+    let (dir, name) = open_image_dir(path)?;
+    let flags = OFlags::RDWR | OFlags::NOFOLLOW | OFlags::NONBLOCK | OFlags::CLOEXEC;
+    let created = if create {
+        openat(
+            &dir,
+            &name,
+            flags | OFlags::CREATE | OFlags::EXCL,
+            Mode::from_raw_mode(0o644),
+        )
+    } else {
+        Err(Errno::EXIST)
+    };
+    let (fd, created) = match created {
+        Ok(fd) => (fd, true),
+        Err(Errno::EXIST) => match openat(&dir, &name, flags, Mode::empty()) {
+            Ok(fd) => (fd, false),
+            Err(Errno::LOOP) => {
+                return Err(HelperError::new(
+                    ErrorCode::Image,
+                    format!("Refusing to follow symlink {}", path.display()),
+                ))
+            }
+            Err(e) => {
+                return Err(e).code(
+                    ErrorCode::Image,
+                    format!("Failed to open image {}", path.display()),
+                )
             }
+        },
+        Err(e) => {
+            return Err(e).code(
+                ErrorCode::Image,
+                format!("Failed to create image {}", path.display()),
+            )
         }
+    };
+    let stat = fstat(&fd).code(ErrorCode::Image, "Failed to read image")?;
+    if FileType::from_raw_mode(stat.st_mode) != FileType::RegularFile {
+        return Err(HelperError::new(
+            ErrorCode::Image,
+            format!("{} is not a regular file", path.display()),
+        ));
     }
+    if created {
+        // SAFETY: the ids belong to the user who ran pkexec
+        let (uid, gid) = unsafe { (Uid::from_raw(uid), Gid::from_raw(gid)) };
+        fchown(&fd, Some(uid), Some(gid)).code(ErrorCode::Image, "Failed to hand over image")?;
+    } else if uid != 0 && stat.st_uid != uid {
+        return Err(HelperError::new(
+            ErrorCode::Image,
+            format!("{} belongs to another user", path.display()),
+        ));
+    }
+    Ok(File::from(fd))
 }
 
-fn partition() -> Result<()> {
-    let stdin = io::stdin();
-    let mut buf = String::new();
-    stdin.lock().read_to_string(&mut buf)?;
+/// Opens the directory holding the disk image `path` without following a symlink and
+/// returns it with the file name. The user who ran pkexec must be able to create files
+/// in it, so the helper never creates or removes a file there on behalf of a user who
+/// could not do so themselves.
+fn open_image_dir(path: &Path) -> Result<(OwnedFd, OsString)> {
+    let (parent, name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if path.is_absolute() => (parent, name),
+        _ => {
+            return Err(HelperError::new(
+                ErrorCode::InvalidRequest,
+                format!("{} is not an absolute path to a file", path.display()),
+            ))
+        }
+    };
+    let dir = openat(
+        cwd(),
+        parent,
+        OFlags::RDONLY | OFlags::DIRECTORY | OFlags::NOFOLLOW | OFlags::CLOEXEC,
+        Mode::empty(),
+    )
+    .code(
+        ErrorCode::Image,
+        format!("Failed to open directory {}", parent.display()),
+    )?;
+    let stat = fstat(&dir).code(ErrorCode::Image, "Failed to read image directory")?;
+    let (uid, gid) = invoking_user();
+    let groups = users::get_user_by_uid(uid)
+        .and_then(|user| users::get_user_groups(user.name(), gid))
+        .unwrap_or_default();
+    // Write and search permission of the owner, the group or everyone else
+    let needed = if stat.st_uid == uid {
+        0o300
+    } else if groups.iter().any(|group| group.gid() == stat.st_gid) {
+        0o030
+    } else {
+        0o003
+    };
+    if uid != 0 && stat.st_mode & needed != needed {
+        return Err(HelperError::new(
+            ErrorCode::Image,
+            format!("{} is not writable by the invoking user", parent.display()),
+        ));
+    }
+    Ok((dir, name.to_os_string()))
+}
 
-    let schema: PartitionSchema = serde_json::from_str(&buf)?;
+/// User and primary group of the user who ran the helper through pkexec, root when it
+/// was run directly.
+fn invoking_user() -> (u32, u32) {
+    let uid = std::env::var("PKEXEC_UID")
+        .ok()
+        .and_then(|uid| uid.parse().ok())
+        .unwrap_or(0);
+    let gid = users::get_user_by_uid(uid).map_or(0, |user| user.primary_group_id());
+    (uid, gid)
+}
 
-    match schema {
+/// Fails unless the loop `device` is backed by the already opened `file`.
+fn check_backing_file(device: &str, file: &File) -> Result<()> {
+    let backing = run_command(
+        "losetup",
+        [
+            "--list",
+            "--noheadings",
+            "--output",
+            "BACK-INO,BACK-MAJ:MIN",
+            device,
+        ],
+        ErrorCode::Image,
+    )?;
+    let stat = fstat(file).code(ErrorCode::Image, "Failed to read image")?;
+    let expected = [
+        stat.st_ino.to_string(),
+        format!("{}:{}", major(stat.st_dev), minor(stat.st_dev)),
+    ];
+    if !backing
+        .split_whitespace()
+        .eq(expected.iter().map(|x| x.as_str()))
+    {
+        return Err(HelperError::new(
+            ErrorCode::Image,
+            format!("{} is not backed by the opened image", device),
+        ));
+    }
+    Ok(())
+}
+
+/// Detaches every loop device backed by `file`.
+fn detach_loop(file: &Path) -> Result<()> {
+    let devices = run_command(
+        "losetup",
+        [
+            OsStr::new("--list"),
+            OsStr::new("--noheadings"),
+            OsStr::new("--output"),
+            OsStr::new("NAME"),
+            OsStr::new("--associated"),
+            file.as_os_str(),
+        ],
+        ErrorCode::Image,
+    )?;
+    for device in devices.lines().map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        log(format!("Detaching {}", device));
+        run_command("losetup", ["--detach", device], ErrorCode::Image)?;
+    }
+    Ok(())
+}
+
+fn detach(image: &Path, format: ImageFormat) -> Result<Response> {
+    let staging = staging_path(image, format);
+    unmount()?;
+    progress(Progress::DetachingImage {
+        image: image.to_string_lossy().to_string(),
+    });
+    detach_loop(&staging)?;
+    if format == ImageFormat::Qcow2 {
+        progress(Progress::ConvertingImage {
+            image: image.to_string_lossy().to_string(),
+            format,
+        });
+        let source = open_image(&staging, false)?;
+        let target = open_image(image, true)?;
+        target
+            .set_len(0)
+            .code(ErrorCode::Image, "Failed to truncate image")?;
+        // qemu-img reads and writes through the checked files on its stdin and stdout
+        // instead of opening the paths again
+        let output = Command::new("qemu-img")
+            .args([
+                "convert",
+                "-f",
+                "raw",
+                "-O",
+                "qcow2",
+                "/dev/stdin",
+                "/dev/stdout",
+            ])
+            .stdin(source)
+            .stdout(target)
+            .output()
+            .code(ErrorCode::Image, "Failed to run qemu-img")?;
+        command_stdout("qemu-img", output, ErrorCode::Image)?;
+        let (dir, name) = open_image_dir(&staging)?;
+        unlinkat(&dir, &name, AtFlags::empty())
+            .code(ErrorCode::Image, "Failed to remove staging image")?;
+    }
+    Ok(Response::Done)
+}
+
+fn mount(device: &Path, target: &str) -> Result<()> {
+    let mountpoint = format!("{}{}", TARGET_ROOT, target);
+    progress(Progress::Mounting {
+        device: device.to_string_lossy().to_string(),
+        target: target.to_string(),
+    });
+    fs::create_dir_all(&mountpoint).code(ErrorCode::Mount, "Failed to create mountpoint")?;
+    let output = Command::new("mount")
+        .arg(device)
+        .arg(&mountpoint)
+        .output()
+        .code(ErrorCode::Mount, "Failed to mount partition")?;
+    if !output.status.success() {
+        return Err(HelperError::new(
+            ErrorCode::Mount,
+            format!(
+                "Failed to mount {}: {}",
+                device.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn partition() -> Result<Response> {
+    let request: PartitionRequest = read_request()?;
+
+    match request.schema {
         PartitionSchema::FullDisk(diskpath) => {
-            let start_sector = Sector::Start;
-            let end_sector = Sector::End;
-            let boot_sector = Sector::Unit(2_097_152);
+            partition_full_disk(&diskpath, &request.esp, request.filesystem, &request.layout)?
+        }
+        PartitionSchema::Custom(partitions) => partition_custom(partitions, &request.esp)?,
+        PartitionSchema::Premounted { .. } => {
+            return Err(HelperError::new(
+                ErrorCode::InvalidRequest,
+                "Premounted targets are not partitioned",
+            ))
+        }
+    }
+    Ok(Response::Done)
+}
 
-            println!("Partition: Finding disk");
-            let mut dev = distinst_disks::Disk::from_name(&diskpath)
-                .ok()
-                .ok_or_else(|| anyhow!("Failed to find disk"))?;
-            let efi = distinst_disks::Bootloader::detect() == distinst_disks::Bootloader::Efi;
-
-            if efi {
-                println!("Partition: Creating GPT partition table");
-                dev.mklabel(PartitionTable::Gpt)
-                    .ok()
-                    .ok_or_else(|| anyhow!("Failed to create GPT partition table"))?;
-
-                println!("Partition: Creating EFI partition");
-                // Add /boot/efi partition
-                dev.add_partition(
-                    PartitionBuilder::new(
-                        dev.get_sector(start_sector),
-                        dev.get_sector(boot_sector),
-                        FileSystem::Fat32,
-                    )
-                    .partition_type(PartitionType::Primary)
-                    .flag(PartitionFlag::PED_PARTITION_ESP)
-                    .mount("/boot/efi".into()),
-                )
-                .ok()
-                .ok_or_else(|| anyhow!("Failed to create EFI partition"))?;
-            } else {
-                println!("Partition: Creating MBR partition table");
-                dev.mklabel(PartitionTable::Msdos)
-                    .ok()
-                    .ok_or_else(|| anyhow!("Failed to create MBR partition table"))?;
-            }
+fn partition_full_disk(
+    diskpath: &str,
+    esp: &str,
+    filesystem: RootFilesystem,
+    layout: &DiskLayout,
+) -> Result<()> {
+    let start_sector = Sector::Start;
+    let end_sector = Sector::End;
+    let boot_sector = Sector::Unit(2_097_152);
+    let bios_boot_sector = Sector::Unit(4096);
+    let boot_size = 2_097_152;
 
-            println!("Partition: Creating root partition");
-            // Add root partition
-            dev.add_partition(
-                PartitionBuilder::new(
-                    dev.get_sector(if efi { boot_sector } else { start_sector }),
-                    dev.get_sector(end_sector),
-                    FileSystem::Ext4,
-                )
-                .partition_type(PartitionType::Primary)
-                .mount("/".into()),
+    if filesystem == RootFilesystem::Zfs && *layout != DiskLayout::Plain {
+        return Err(HelperError::new(
+            ErrorCode::InvalidRequest,
+            "ZFS pools can not be created inside a LUKS container",
+        ));
+    }
+
+    progress(Progress::FindingDisk {
+        disk: diskpath.to_string(),
+    });
+    let mut dev = distinst_disks::Disk::from_name(diskpath).ok().code(
+        ErrorCode::DiskNotFound,
+        format!("Failed to find disk {}", diskpath),
+    )?;
+    let released = release_disk(diskpath)?;
+    if let DiskLayout::LvmOnLuks { .. } = layout {
+        check_lvm_on_luks_names(&released)?;
+    }
+    let efi = distinst_disks::Bootloader::detect() == distinst_disks::Bootloader::Efi;
+    // MBR partition tables can not address disks larger than 2 TiB
+    let gpt = efi || dev.get_sectors() * dev.get_sector_size() > MBR_MAX_SIZE;
+
+    if efi {
+        progress(Progress::CreatingPartitionTable {
+            table: "gpt".to_string(),
+        });
+        dev.mklabel(PartitionTable::Gpt).ok().code(
+            ErrorCode::PartitionTable,
+            "Failed to create GPT partition table",
+        )?;
+
+        progress(Progress::CreatingPartition {
+            mountpoint: esp.to_string(),
+        });
+        // Add EFI system partition
+        dev.add_partition(
+            PartitionBuilder::new(
+                dev.get_sector(start_sector),
+                dev.get_sector(boot_sector),
+                FileSystem::Fat32,
             )
-            .ok()
-            .ok_or_else(|| anyhow!("Failed to create root partition"))?;
+            .partition_type(PartitionType::Primary)
+            .flag(PartitionFlag::PED_PARTITION_ESP)
+            .mount(esp.into()),
+        )
+        .ok()
+        .code(ErrorCode::CreatePartition, "Failed to create EFI partition")?;
+    } else if gpt {
+        progress(Progress::CreatingPartitionTable {
+            table: "gpt".to_string(),
+        });
+        dev.mklabel(PartitionTable::Gpt).ok().code(
+            ErrorCode::PartitionTable,
+            "Failed to create GPT partition table",
+        )?;
 
-            println!("Partition: Committing changes");
-            let partitions = dev
-                .commit()
-                .ok()
-                .ok_or_else(|| anyhow!("Failed to commit changes"))?
-                .context("Failed to get partitions")?;
+        log(format!("Creating BIOS boot partition on {}", diskpath));
+        // GRUB embeds itself in the BIOS boot partition on GPT disks
+        dev.add_partition(
+            PartitionBuilder::new(
+                dev.get_sector(start_sector),
+                dev.get_sector(bios_boot_sector),
+                None,
+            )
+            .partition_type(PartitionType::Primary)
+            .flag(PartitionFlag::PED_PARTITION_BIOS_GRUB),
+        )
+        .ok()
+        .code(
+            ErrorCode::CreatePartition,
+            "Failed to create BIOS boot partition",
+        )?;
+    } else {
+        progress(Progress::CreatingPartitionTable {
+            table: "msdos".to_string(),
+        });
+        dev.mklabel(PartitionTable::Msdos).ok().code(
+            ErrorCode::PartitionTable,
+            "Failed to create MBR partition table",
+        )?;
+    }
 
-            println!("Partition: Formatting partitions");
-            let formatparts = FormatPartitions(partitions.0);
-            formatparts
-                .format()
-                .ok()
-                .ok_or_else(|| anyhow!("Failed to format partitions"))?;
+    let passphrase = match layout {
+        DiskLayout::Plain => None,
+        DiskLayout::LvmOnLuks { passphrase } => Some(passphrase),
+    };
+    let mut root_start = dev.get_sector(if efi {
+        boot_sector
+    } else if gpt {
+        bios_boot_sector
+    } else {
+        start_sector
+    });
+    // The kernels can not be read from the encrypted volume group
+    if passphrase.is_some() && !(efi && esp == "/boot") {
+        progress(Progress::CreatingPartition {
+            mountpoint: "/boot".to_string(),
+        });
+        dev.add_partition(
+            PartitionBuilder::new(root_start, root_start + boot_size, FileSystem::Ext4)
+                .partition_type(PartitionType::Primary)
+                .mount("/boot".into()),
+        )
+        .ok()
+        .code(
+            ErrorCode::CreatePartition,
+            "Failed to create boot partition",
+        )?;
+        root_start += boot_size;
+    }
 
-            println!("Partition: Reloading disk");
-            dev.reload()
-                .ok()
-                .ok_or_else(|| anyhow!("Failed to reload disk"))?;
+    progress(Progress::CreatingPartition {
+        mountpoint: "/".to_string(),
+    });
+    // Add root partition, or the LUKS container holding it
+    let mut root = PartitionBuilder::new(
+        root_start,
+        dev.get_sector(end_sector),
+        // The pool or the LUKS container is created on the partition after committing
+        match filesystem {
+            RootFilesystem::Ext4 if passphrase.is_none() => Some(FileSystem::Ext4),
+            _ => None,
+        },
+    )
+    .partition_type(PartitionType::Primary);
+    if passphrase.is_none() {
+        root = root.mount("/".into());
+    }
+    dev.add_partition(root).ok().code(
+        ErrorCode::CreatePartition,
+        "Failed to create root partition",
+    )?;
+
+    progress(Progress::Committing {
+        disk: diskpath.to_string(),
+    });
+    let partitions = dev
+        .commit()
+        .ok()
+        .code(ErrorCode::Commit, "Failed to commit changes")?
+        .code(ErrorCode::Commit, "Failed to get partitions")?;
+
+    progress(Progress::Formatting {
+        disk: diskpath.to_string(),
+    });
+    let formatparts = FormatPartitions(partitions.0);
+    formatparts
+        .format()
+        .ok()
+        .code(ErrorCode::Format, "Failed to format partitions")?;
 
-            println!("Partition: Sorting partitions");
-            let mut partvec = dev.get_partitions().to_vec();
-            // Sort by shortest target first
-            partvec.sort_by(|a, b| {
-                a.target
+    dev.reload()
+        .ok()
+        .code(ErrorCode::DiskNotFound, "Failed to reload disk")?;
+
+    let mut partvec = dev.get_partitions().to_vec();
+    // Sort by shortest target first
+    partvec.sort_by(|a, b| {
+        a.target
+            .as_ref()
+            .map(|x| x.to_string_lossy().len())
+            .unwrap_or(0)
+            .cmp(
+                &b.target
                     .as_ref()
                     .map(|x| x.to_string_lossy().len())
-                    .unwrap_or(0)
-                    .cmp(
-                        &b.target
-                            .as_ref()
-                            .map(|x| x.to_string_lossy().len())
-                            .unwrap_or(0),
-                    )
-            });
+                    .unwrap_or(0),
+            )
+    });
 
-            println!("Partition: Mounting partitions");
-            for part in partvec {
-                if let Some(target) = &part.target.as_ref().and_then(|x| x.to_str()) {
-                    println!(" -- Target: {}", target);
-                    println!(" -- Device: {}", part.get_device_path().to_string_lossy());
-                    println!(
-                        " -- Filesystem: {:?}",
-                        part.filesystem.unwrap().to_string().as_str()
-                    );
-                    fs::create_dir_all(format!("/tmp/icicle{}", target))
-                        .context("Failed to create mountpoint")?;
-                    let output = Command::new("mount")
-                        .arg(part.get_device_path())
-                        .arg(format!("/tmp/icicle{}", target))
-                        .output()
-                        .context("Failed to mount partition")?;
-                    if !output.status.success() {
-                        return Err(anyhow!(
-                            "Failed to mount partition: {}",
-                            String::from_utf8_lossy(&output.stderr)
-                        ));
-                    }
-                }
+    if let Some(passphrase) = passphrase {
+        // The root partition is the last one
+        let container = partvec.iter().max_by_key(|part| part.number).code(
+            ErrorCode::PartitionNotFound,
+            "Failed to find root partition",
+        )?;
+        create_lvm_on_luks(&container.get_device_path(), passphrase, filesystem)?;
+    }
+
+    for part in partvec {
+        if let Some(target) = &part.target.as_ref().and_then(|x| x.to_str()) {
+            if *target == "/" && filesystem == RootFilesystem::Zfs {
+                create_zpool(&part.get_device_path(), &[])?;
+            } else {
+                mount(&part.get_device_path(), target)?;
             }
         }
-        PartitionSchema::Custom(partitions) => {
-            let mut devices = HashMap::new();
-            for (path, custom) in &partitions {
-                if !devices.contains_key(&custom.device) {
-                    let dev = distinst_disks::Disk::from_name(&custom.device)
-                        .ok()
-                        .ok_or_else(|| anyhow!("Failed to find disk {}", custom.device))?;
-                    devices.insert(custom.device.to_string(), (dev, vec![]));
-                }
-                let partvec = &mut devices.get_mut(&custom.device).unwrap().1;
-                partvec.push((path, custom));
-                partvec.sort_by(|a, b| a.0.cmp(b.0));
-            }
+    }
+    Ok(())
+}
 
-            // Loop through each modified disk
-            for (device, (mut dev, partitions)) in devices {
-                println!("Partitions: Partitioning disk {}", device);
-                for (part, custom) in partitions {
-                    let partition = dev
-                        .partitions
-                        .iter()
-                        .find(|x| x.get_device_path().to_str() == Some(part))
-                        .ok_or_else(|| anyhow!("Failed to find partition {}", part))?;
-                    let num = &partition.number;
-                    if let Some(format) = &custom.format.as_ref().and_then(|x| match x.as_str() {
-                        "btrfs" => Some(FileSystem::Btrfs),
-                        "ext4" => Some(FileSystem::Ext4),
-                        "ext3" => Some(FileSystem::Ext3),
-                        "fat32" => Some(FileSystem::Fat32),
-                        "ntfs" => Some(FileSystem::Ntfs),
-                        "xfs" => Some(FileSystem::Xfs),
-                        "swap" => Some(FileSystem::Swap),
-                        _ => None,
-                    }) {
-                        dev.format_partition(*num, *format)
-                            .ok()
-                            .ok_or_else(|| anyhow!("Failed to format partition {}", part))?;
-                        if let Some(mountpoint) = &custom.mountpoint {
-                            if mountpoint == "/boot/efi" {
-                                let partition = dev
-                                    .partitions
-                                    .iter_mut()
-                                    .find(|x| x.get_device_path().to_str() == Some(part))
-                                    .ok_or_else(|| anyhow!("Failed to find partition {}", part))?;
-                                partition.flags.push(PartitionFlag::PED_PARTITION_ESP);
-                            }
-                        }
+fn partition_custom(partitions: HashMap<String, CustomPartition>, esp: &str) -> Result<()> {
+    let groups = volume_groups(&None)
+        .into_iter()
+        .map(|group| group.name)
+        .collect::<Vec<_>>();
+    let mut devices = HashMap::new();
+    for (path, custom) in &partitions {
+        if groups.contains(&custom.device) {
+            // Logical volumes are formatted below
+            continue;
+        }
+        if !devices.contains_key(&custom.device) {
+            let dev = distinst_disks::Disk::from_name(&custom.device).ok().code(
+                ErrorCode::DiskNotFound,
+                format!("Failed to find disk {}", custom.device),
+            )?;
+            devices.insert(custom.device.to_string(), (dev, vec![]));
+        }
+        if let Some((_, partvec)) = devices.get_mut(&custom.device) {
+            partvec.push((path, custom));
+            partvec.sort_by(|a, b| a.0.cmp(b.0));
+        }
+    }
+
+    // Loop through each modified disk
+    for (device, (mut dev, partitions)) in devices {
+        for (part, custom) in partitions {
+            let partition = dev
+                .partitions
+                .iter()
+                .find(|x| x.get_device_path().to_str() == Some(part))
+                .code(
+                    ErrorCode::PartitionNotFound,
+                    format!("Failed to find partition {}", part),
+                )?;
+            let num = &partition.number;
+            if let Some(format) = &custom.format.as_ref().and_then(|x| match x.as_str() {
+                "btrfs" => Some(FileSystem::Btrfs),
+                "ext4" => Some(FileSystem::Ext4),
+                "ext3" => Some(FileSystem::Ext3),
+                "fat32" => Some(FileSystem::Fat32),
+                "ntfs" => Some(FileSystem::Ntfs),
+                "xfs" => Some(FileSystem::Xfs),
+                "swap" => Some(FileSystem::Swap),
+                // Pools are created when mounting
+                _ => None,
+            }) {
+                dev.format_partition(*num, *format).ok().code(
+                    ErrorCode::Format,
+                    format!("Failed to format partition {}", part),
+                )?;
+                if let Some(mountpoint) = &custom.mountpoint {
+                    if mountpoint == esp {
+                        let partition = dev
+                            .partitions
+                            .iter_mut()
+                            .find(|x| x.get_device_path().to_str() == Some(part))
+                            .code(
+                                ErrorCode::PartitionNotFound,
+                                format!("Failed to find partition {}", part),
+                            )?;
+                        partition.flags.push(PartitionFlag::PED_PARTITION_ESP);
                     }
                 }
-
-                println!("Partitions: Committing changes");
-                let parts = dev
-                    .commit()
-                    .ok()
-                    .ok_or_else(|| anyhow!("Failed to commit changes to disk {}", device))?
-                    .context("Failed to commit")?;
-
-                println!("Partitions: Formatting partitions");
-                let formatparts = FormatPartitions(parts.0);
-                formatparts
-                    .format()
-                    .context("Failed to format partitions")?;
-                dev.reload()
-                    .ok()
-                    .ok_or_else(|| anyhow!("Failed to reload disk {}", device))?;
             }
+        }
 
-            println!("Partitions: Mounting partitions");
-            let mut mountvec = partitions.into_iter().collect::<Vec<_>>();
-            mountvec.sort_by(|a, b| {
-                // Sort by mountpoint length, shortest first
-                let a = a.1.mountpoint.as_ref().map(|x| x.len()).unwrap_or(0);
-                let b = b.1.mountpoint.as_ref().map(|x| x.len()).unwrap_or(0);
-                a.cmp(&b)
+        progress(Progress::Committing {
+            disk: device.to_string(),
+        });
+        let parts = dev
+            .commit()
+            .ok()
+            .code(
+                ErrorCode::Commit,
+                format!("Failed to commit changes to disk {}", device),
+            )?
+            .code(ErrorCode::Commit, "Failed to commit")?;
+
+        progress(Progress::Formatting {
+            disk: device.to_string(),
+        });
+        let formatparts = FormatPartitions(parts.0);
+        formatparts
+            .format()
+            .code(ErrorCode::Format, "Failed to format partitions")?;
+        dev.reload().ok().code(
+            ErrorCode::DiskNotFound,
+            format!("Failed to reload disk {}", device),
+        )?;
+    }
+
+    for (path, custom) in &partitions {
+        if let (true, Some(format)) = (groups.contains(&custom.device), &custom.format) {
+            format_volume(path, format)?;
+        }
+    }
+
+    let mountpoints = partitions
+        .values()
+        .filter(|custom| custom.format.as_deref() != Some("zfs"))
+        .filter_map(|custom| custom.mountpoint.clone())
+        .collect::<Vec<_>>();
+    let mut mountvec = partitions.into_iter().collect::<Vec<_>>();
+    mountvec.sort_by(|a, b| {
+        // Sort by mountpoint length, shortest first
+        let a = a.1.mountpoint.as_ref().map(|x| x.len()).unwrap_or(0);
+        let b = b.1.mountpoint.as_ref().map(|x| x.len()).unwrap_or(0);
+        a.cmp(&b)
+    });
+    for (part, custom) in mountvec {
+        if custom.format == Some("swap".to_string()) {
+            progress(Progress::EnablingSwap {
+                device: part.to_string(),
             });
-            for (part, custom) in mountvec {
-                if custom.format == Some("swap".to_string()) {
-                    let _output = Command::new("swapon")
-                        .arg(&part)
-                        .output()
-                        .context("Failed to enable swap")?;
-                    continue;
-                }
-                if let Some(target) = custom.mountpoint {
-                    fs::create_dir_all(format!("/tmp/icicle{}", target))
-                        .context("Failed to create mountpoint")?;
-                    let _output = Command::new("mount")
-                        .arg(&part)
-                        .arg(format!("/tmp/icicle{}", target))
-                        .output()
-                        .context("Failed to mount partition")?;
-                }
+            let _output = Command::new("swapon")
+                .arg(&part)
+                .output()
+                .code(ErrorCode::Swap, "Failed to enable swap")?;
+            continue;
+        }
+        if custom.format.as_deref() == Some("zfs") {
+            if custom.mountpoint.as_deref() != Some("/") {
+                return Err(HelperError::new(
+                    ErrorCode::InvalidRequest,
+                    format!("ZFS partition {} must be mounted at /", part),
+                ));
             }
+            create_zpool(Path::new(&part), &mountpoints)?;
+            continue;
+        }
+        if let Some(target) = custom.mountpoint {
+            mount(Path::new(&part), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Deactivates the volume groups and closes the LUKS containers on a disk, which
+/// listing the disks activates, so that its partition table can be replaced. Returns
+/// the deactivated volume groups.
+fn release_disk(diskpath: &str) -> Result<Vec<String>> {
+    // The disk, its partitions and the devices stacked on them, like `/dev/sda1 part`
+    let devices = run_command(
+        "lsblk",
+        ["-nlpo", "NAME,TYPE", diskpath],
+        ErrorCode::DiskNotFound,
+    )?;
+    let devices = devices
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, kind)| (name.trim(), kind.trim()))
+        .collect::<Vec<_>>();
+    let physical_volumes = run_command(
+        "pvs",
+        ["--noheadings", "--separator", ":", "-o", "pv_name,vg_name"],
+        ErrorCode::Lvm,
+    )
+    .unwrap_or_default();
+    let mut groups = physical_volumes
+        .lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .filter(|(pv, group)| !group.is_empty() && devices.iter().any(|(name, _)| name == pv))
+        .map(|(_, group)| group.to_string())
+        .collect::<Vec<_>>();
+    groups.sort();
+    groups.dedup();
+    for group in &groups {
+        log(format!(
+            "Deactivating volume group {} on {}",
+            group, diskpath
+        ));
+        run_command("vgchange", ["-an", group.as_str()], ErrorCode::Lvm)?;
+    }
+    // Containers stacked on each other are listed after the one holding them
+    for (name, _) in devices.iter().rev().filter(|(_, kind)| *kind == "crypt") {
+        log(format!("Closing LUKS container {} on {}", name, diskpath));
+        run_command("cryptsetup", ["close", *name], ErrorCode::Encrypt)?;
+    }
+    Ok(groups)
+}
+
+/// Fails if the volume group or the LUKS container of the layout would clash with
+/// ones on other disks, before anything on the disk is touched. A volume group on the
+/// disk itself, like one left by an earlier attempt, is replaced.
+fn check_lvm_on_luks_names(released: &[String]) -> Result<()> {
+    let taken = !released.iter().any(|group| group == VOLUME_GROUP)
+        && Command::new("vgs")
+            .arg(VOLUME_GROUP)
+            .output()
+            .map_or(false, |output| output.status.success());
+    if taken {
+        return Err(HelperError::new(
+            ErrorCode::Lvm,
+            format!("A volume group named {} already exists", VOLUME_GROUP),
+        ));
+    }
+    if Path::new("/dev/mapper").join(LUKS_NAME).exists() {
+        return Err(HelperError::new(
+            ErrorCode::Encrypt,
+            format!("A LUKS container named {} is already open", LUKS_NAME),
+        ));
+    }
+    Ok(())
+}
+
+/// Encrypts `device` with LUKS and creates a volume group in it, with a swap and a root
+/// logical volume. The root volume is mounted at the target root.
+fn create_lvm_on_luks(device: &Path, passphrase: &str, filesystem: RootFilesystem) -> Result<()> {
+    progress(Progress::Encrypting {
+        device: device.to_string_lossy().to_string(),
+    });
+    // The passphrase is read from stdin as is, without a trailing newline
+    run_command_input(
+        "cryptsetup",
+        [
+            OsStr::new("luksFormat"),
+            OsStr::new("--type"),
+            OsStr::new("luks2"),
+            OsStr::new("--batch-mode"),
+            OsStr::new("--key-file"),
+            OsStr::new("-"),
+            device.as_os_str(),
+        ],
+        passphrase,
+        ErrorCode::Encrypt,
+    )?;
+    run_command_input(
+        "cryptsetup",
+        [
+            OsStr::new("open"),
+            OsStr::new("--key-file"),
+            OsStr::new("-"),
+            device.as_os_str(),
+            OsStr::new(LUKS_NAME),
+        ],
+        passphrase,
+        ErrorCode::Encrypt,
+    )?;
+
+    let container = format!("/dev/mapper/{}", LUKS_NAME);
+    run_command("pvcreate", ["-ff", "-y", &container], ErrorCode::Lvm)?;
+    run_command("vgcreate", [VOLUME_GROUP, &container], ErrorCode::Lvm)?;
+    progress(Progress::CreatingVolume {
+        name: "swap".to_string(),
+    });
+    run_command(
+        "lvcreate",
+        [
+            "-y",
+            "-L",
+            &format!("{}b", swap_size()),
+            "-n",
+            "swap",
+            VOLUME_GROUP,
+        ],
+        ErrorCode::Lvm,
+    )?;
+    progress(Progress::CreatingVolume {
+        name: "root".to_string(),
+    });
+    run_command(
+        "lvcreate",
+        ["-y", "-l", "100%FREE", "-n", "root", VOLUME_GROUP],
+        ErrorCode::Lvm,
+    )?;
+
+    let swap = format!("/dev/{}/swap", VOLUME_GROUP);
+    run_command("mkswap", [&swap], ErrorCode::Swap)?;
+    progress(Progress::EnablingSwap {
+        device: swap.to_string(),
+    });
+    run_command("swapon", [&swap], ErrorCode::Swap)?;
+
+    let root = format!("/dev/{}/root", VOLUME_GROUP);
+    match filesystem {
+        RootFilesystem::Ext4 => {
+            progress(Progress::Formatting {
+                disk: root.to_string(),
+            });
+            run_command("mkfs.ext4", ["-F", &root], ErrorCode::Format)?;
+            mount(Path::new(&root), "/")
         }
+        RootFilesystem::Zfs => create_zpool(Path::new(&root), &[]),
     }
+}
+
+/// Size of the swap logical volume in bytes, as much as the machine has memory up to
+/// `SWAP_MAX_SIZE`.
+fn swap_size() -> u64 {
+    let memory = fs::read_to_string("/proc/meminfo")
+        .ok()
+        .and_then(|meminfo| {
+            meminfo
+                .lines()
+                .find(|line| line.starts_with("MemTotal:"))?
+                .split_whitespace()
+                .nth(1)?
+                .parse::<u64>()
+                .ok()
+        });
+    memory.map_or(SWAP_MAX_SIZE, |kib| (kib * 1024).min(SWAP_MAX_SIZE))
+}
+
+/// Formats a logical volume, which distinst does not know about.
+fn format_volume(path: &str, format: &str) -> Result<()> {
+    let (program, args): (&str, &[&str]) = match format {
+        "btrfs" => ("mkfs.btrfs", &["-f"]),
+        "ext4" => ("mkfs.ext4", &["-F"]),
+        "ext3" => ("mkfs.ext3", &["-F"]),
+        "fat32" => ("mkfs.fat", &["-F", "32"]),
+        "ntfs" => ("mkfs.ntfs", &["--fast"]),
+        "xfs" => ("mkfs.xfs", &["-f"]),
+        "swap" => ("mkswap", &[]),
+        // Pools are created when mounting
+        _ => return Ok(()),
+    };
+    progress(Progress::Formatting {
+        disk: path.to_string(),
+    });
+    run_command(
+        program,
+        args.iter().copied().chain([path]),
+        ErrorCode::Format,
+    )?;
     Ok(())
 }
+
+/// Creates the ZFS pool on `device` and mounts its datasets, except for the ones whose
+/// mountpoint is in `taken` by other partitions.
+fn create_zpool(device: &Path, taken: &[String]) -> Result<()> {
+    // A pool left imported by an earlier attempt would keep the name taken
+    let _ = Command::new("zpool").args(["export", ZFS_POOL]).output();
+    log(format!(
+        "Creating ZFS pool {} on {}",
+        ZFS_POOL,
+        device.display()
+    ));
+    // GRUB reads the kernels from the pool unless they are kept on the ESP, so only
+    // features it supports are enabled
+    run_command(
+        "zpool",
+        [
+            OsStr::new("create"),
+            OsStr::new("-f"),
+            OsStr::new("-o"),
+            OsStr::new("ashift=12"),
+            OsStr::new("-o"),
+            OsStr::new("compatibility=grub2"),
+            OsStr::new("-O"),
+            OsStr::new("compression=lz4"),
+            OsStr::new("-O"),
+            OsStr::new("acltype=posixacl"),
+            OsStr::new("-O"),
+            OsStr::new("xattr=sa"),
+            OsStr::new("-O"),
+            OsStr::new("mountpoint=none"),
+            OsStr::new(ZFS_POOL),
+            device.as_os_str(),
+        ],
+        ErrorCode::Zfs,
+    )?;
+    for (name, target) in ZFS_DATASETS {
+        if taken.iter().any(|mountpoint| mountpoint == target) {
+            continue;
+        }
+        let dataset = format!("{}/{}", ZFS_POOL, name);
+        // Legacy mountpoints end up in hardware-configuration.nix like other filesystems
+        run_command(
+            "zfs",
+            ["create", "-o", "mountpoint=legacy", &dataset],
+            ErrorCode::Zfs,
+        )?;
+        progress(Progress::Mounting {
+            device: dataset.to_string(),
+            target: target.to_string(),
+        });
+        let mountpoint = format!("{}{}", TARGET_ROOT, target);
+        fs::create_dir_all(&mountpoint).code(ErrorCode::Mount, "Failed to create mountpoint")?;
+        run_command(
+            "mount",
+            ["-t", "zfs", &dataset, &mountpoint],
+            ErrorCode::Mount,
+        )?;
+    }
+    Ok(())
+}
+
+fn write_file(path: &str, mode: Option<&str>, owner: Option<&str>) -> Result<Response> {
+    let mode = mode.map(parse_mode).transpose()?.unwrap_or(0o644);
+    let owner = owner.map(parse_owner).transpose()?;
+    let mut contents = vec![];
+    io::stdin()
+        .lock()
+        .read_to_end(&mut contents)
+        .code(ErrorCode::InvalidRequest, "Failed to read file contents")?;
+    write_target_file(Path::new(TARGET_ROOT), path, &contents, mode, owner)?;
+    Ok(Response::Done)
+}
+
+/// Parses an octal file mode. Only permission bits are accepted, so a file written into
+/// the target can never be setuid or setgid.
+fn parse_mode(mode: &str) -> Result<u32> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode & !0o777 == 0 => Ok(mode),
+        _ => Err(HelperError::new(
+            ErrorCode::InvalidRequest,
+            format!("Invalid file mode {}", mode),
+        )),
+    }
+}
+
+/// Writes `contents` to `path` relative to `root`, replacing the file atomically.
+fn write_target_file(
+    root: &Path,
+    path: &str,
+    contents: &[u8],
+    mode: u32,
+    owner: Option<(u32, u32)>,
+) -> Result<()> {
+    let (dir, filename) = open_target_dir(root, path)?;
+    let target = root.join(path.trim_start_matches('/'));
+    match statat(&dir, &filename, AtFlags::SYMLINK_NOFOLLOW) {
+        Ok(stat) => match FileType::from_raw_mode(stat.st_mode) {
+            FileType::Symlink => {
+                return Err(HelperError::new(
+                    ErrorCode::PathOutsideTarget,
+                    format!("Refusing to overwrite symlink {}", target.display()),
+                ))
+            }
+            FileType::Directory => {
+                return Err(HelperError::new(
+                    ErrorCode::Io,
+                    format!("{} is a directory", target.display()),
+                ))
+            }
+            _ => {}
+        },
+        Err(Errno::NOENT) => {}
+        Err(e) => return Err(e).code(ErrorCode::Io, target.display()),
+    }
+
+    // Write to a temporary file in the same directory and rename it over the
+    // target, so a failed write never leaves a truncated file behind
+    let mut tmpname = OsString::from(".");
+    tmpname.push(&filename);
+    tmpname.push(".icicle-tmp");
+    match unlinkat(&dir, &tmpname, AtFlags::empty()) {
+        Ok(()) | Err(Errno::NOENT) => {}
+        Err(e) => return Err(e).code(ErrorCode::Io, "Failed to remove stale temporary file"),
+    }
+    let mut tmpfile = File::from(
+        openat(
+            &dir,
+            &tmpname,
+            OFlags::WRONLY | OFlags::CREATE | OFlags::EXCL | OFlags::NOFOLLOW | OFlags::CLOEXEC,
+            Mode::from_raw_mode(mode),
+        )
+        .code(ErrorCode::Io, "Failed to create temporary file")?,
+    );
+    let written = (|| -> io::Result<()> {
+        tmpfile.write_all(contents)?;
+        // Explicitly set permissions since the mode passed to open is masked by the umask
+        tmpfile.set_permissions(fs::Permissions::from_mode(mode))?;
+        if let Some((uid, gid)) = owner {
+            // SAFETY: the ids may only exist in the target system, which the kernel accepts
+            let (uid, gid) = unsafe { (Uid::from_raw(uid), Gid::from_raw(gid)) };
+            fchown(&tmpfile, Some(uid), Some(gid))?;
+        }
+        tmpfile.sync_all()?;
+        renameat(&dir, &tmpname, &dir, &filename)?;
+        Ok(())
+    })();
+    if let Err(e) = written {
+        let _ = unlinkat(&dir, &tmpname, AtFlags::empty());
+        return Err(e).code(
+            ErrorCode::Io,
+            format!("Failed to write {}", target.display()),
+        );
+    }
+    fsync(&dir).code(ErrorCode::Io, "Failed to sync directory")
+}
+
+/// Opens the directory holding `path` relative to `root`, creating missing
+/// parent directories, and returns it with the file name. Rejects `..` components, and
+/// every directory is opened relative to the one before it without following symlinks,
+/// so a symlink swapped in along the way can never lead outside the target root.
+fn open_target_dir(root: &Path, path: &str) -> Result<(OwnedFd, OsString)> {
+    let mut components = vec![];
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(c) => components.push(c),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(HelperError::new(
+                    ErrorCode::PathOutsideTarget,
+                    format!("Path traversal is not allowed: {}", path),
+                ));
+            }
+        }
+    }
+    let filename = components
+        .pop()
+        .code(ErrorCode::InvalidRequest, "No file name given")?;
+
+    let flags = OFlags::RDONLY | OFlags::DIRECTORY | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+    let mut dir = openat(cwd(), root, flags, Mode::empty()).code(
+        ErrorCode::Io,
+        format!("Target root {} is not a directory", root.display()),
+    )?;
+    let mut current = root.to_path_buf();
+    for component in components {
+        current.push(component);
+        let opened = match openat(&dir, component, flags, Mode::empty()) {
+            Err(Errno::NOENT) => match mkdirat(&dir, component, Mode::from_raw_mode(0o755)) {
+                Ok(()) | Err(Errno::EXIST) => openat(&dir, component, flags, Mode::empty()),
+                Err(e) => Err(e),
+            },
+            opened => opened,
+        };
+        dir = match opened {
+            Ok(opened) => opened,
+            // Opening a symlink as a directory fails with ENOTDIR rather than ELOOP
+            Err(Errno::LOOP | Errno::NOTDIR)
+                if statat(&dir, component, AtFlags::SYMLINK_NOFOLLOW).map_or(false, |stat| {
+                    FileType::from_raw_mode(stat.st_mode) == FileType::Symlink
+                }) =>
+            {
+                return Err(HelperError::new(
+                    ErrorCode::PathOutsideTarget,
+                    format!("Refusing to follow symlink {}", current.display()),
+                ));
+            }
+            Err(Errno::NOTDIR) => {
+                return Err(HelperError::new(
+                    ErrorCode::Io,
+                    format!("{} is not a directory", current.display()),
+                ));
+            }
+            Err(e) => {
+                return Err(e).code(
+                    ErrorCode::Io,
+                    format!("Failed to open {}", current.display()),
+                )
+            }
+        };
+    }
+    Ok((dir, filename.to_os_string()))
+}
+
+/// Parses `user[:group]`, where both parts may be names or numeric ids. Numeric ids
+/// are used as is since they may only exist in the target system. Without a group
+/// the primary group of the user is used.
+fn parse_owner(owner: &str) -> Result<(u32, u32)> {
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+    let (uid, primarygid) = match user.parse::<u32>() {
+        Ok(uid) => (
+            uid,
+            users::get_user_by_uid(uid)
+                .map(|u| u.primary_group_id())
+                .unwrap_or(uid),
+        ),
+        Err(_) => {
+            let user = users::get_user_by_name(user)
+                .code(ErrorCode::InvalidRequest, format!("Unknown user {}", user))?;
+            (user.uid(), user.primary_group_id())
+        }
+    };
+    let gid = match group {
+        Some(group) => match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => users::get_group_by_name(group)
+                .code(
+                    ErrorCode::InvalidRequest,
+                    format!("Unknown group {}", group),
+                )?
+                .gid(),
+        },
+        None => primarygid,
+    };
+    Ok((uid, gid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// A fresh directory standing in for the target root, next to one outside of it.
+    fn target_root(name: &str) -> (PathBuf, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("icicle-helper-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("root")).unwrap();
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        (dir.join("root"), dir.join("outside"))
+    }
+
+    fn error_code<T>(result: Result<T>) -> Option<ErrorCode> {
+        result.err().map(|e| e.code)
+    }
+
+    #[test]
+    fn parses_permission_bits_only() {
+        assert_eq!(parse_mode("644").unwrap(), 0o644);
+        assert_eq!(parse_mode("0600").unwrap(), 0o600);
+        assert_eq!(parse_mode("777").unwrap(), 0o777);
+        for mode in ["4755", "2755", "1777", "10644", "888", "rw-r--r--", ""] {
+            assert_eq!(
+                error_code(parse_mode(mode)),
+                Some(ErrorCode::InvalidRequest),
+                "{}",
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn writes_below_the_target_root() {
+        let (root, _) = target_root("write");
+        write_target_file(&root, "/etc/nixos/configuration.nix", b"{ }\n", 0o600, None).unwrap();
+        let path = root.join("etc/nixos/configuration.nix");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{ }\n");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o7777,
+            0o600
+        );
+        write_target_file(
+            &root,
+            "etc/nixos/configuration.nix",
+            b"{ }\n\n",
+            0o644,
+            None,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{ }\n\n");
+    }
+
+    #[test]
+    fn rejects_parent_components() {
+        let (root, outside) = target_root("parent");
+        for path in ["../outside/file", "etc/../../outside/file", "/.."] {
+            assert_eq!(
+                error_code(write_target_file(&root, path, b"", 0o644, None)),
+                Some(ErrorCode::PathOutsideTarget),
+                "{}",
+                path
+            );
+        }
+        assert!(!outside.join("file").exists());
+    }
+
+    #[test]
+    fn refuses_to_follow_symlinks() {
+        let (root, outside) = target_root("symlink");
+        symlink(&outside, root.join("etc")).unwrap();
+        assert_eq!(
+            error_code(write_target_file(&root, "etc/passwd", b"", 0o644, None)),
+            Some(ErrorCode::PathOutsideTarget)
+        );
+        assert!(!outside.join("passwd").exists());
+
+        fs::write(outside.join("shadow"), "secret").unwrap();
+        symlink(outside.join("shadow"), root.join("shadow")).unwrap();
+        assert_eq!(
+            error_code(write_target_file(&root, "shadow", b"", 0o644, None)),
+            Some(ErrorCode::PathOutsideTarget)
+        );
+        assert_eq!(
+            fs::read_to_string(outside.join("shadow")).unwrap(),
+            "secret"
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! Messages exchanged between Icicle and `icicle-helper`.
+//!
+//! Requests are written as JSON to the helper's stdin. The helper answers with
+//! newline delimited JSON on stdout, any number of [`Output::Progress`] and
+//! [`Output::Log`] events followed by exactly one [`Output::Response`] or
+//! [`Output::Error`]. Every message is wrapped in an [`Envelope`] carrying the
+//! protocol version.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+/// Version of the protocol, bump whenever a message changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Root of the system being installed.
+pub const TARGET_ROOT: &str = "/tmp/icicle";
+
+/// Name of the pool a ZFS root is created in.
+pub const ZFS_POOL: &str = "rpool";
+
+/// Volume group created by the LVM on LUKS layout.
+pub const VOLUME_GROUP: &str = "nixos";
+
+/// Device mapper name of the opened LUKS container of the LVM on LUKS layout.
+pub const LUKS_NAME: &str = "cryptroot";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Envelope<T> {
+    pub version: u32,
+    pub body: T,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(body: T) -> Self {
+        Envelope {
+            version: PROTOCOL_VERSION,
+            body,
+        }
+    }
+
+    /// Unwraps the message, failing if it was sent by a different protocol version.
+    pub fn into_body(self) -> Result<T, HelperError> {
+        if self.version == PROTOCOL_VERSION {
+            Ok(self.body)
+        } else {
+            Err(HelperError::new(
+                ErrorCode::UnsupportedVersion,
+                format!(
+                    "Expected protocol version {}, got {}",
+                    PROTOCOL_VERSION, self.version
+                ),
+            ))
+        }
+    }
+}
+
+/// Request body for `icicle-helper partition`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartitionRequest {
+    pub schema: PartitionSchema,
+    /// Mountpoint of the EFI system partition, flagged as ESP when partitioning
+    #[serde(default = "default_esp")]
+    pub esp: String,
+    /// Filesystem of the root partition of a full disk layout
+    #[serde(default)]
+    pub filesystem: RootFilesystem,
+    /// How a full disk is laid out
+    #[serde(default)]
+    pub layout: DiskLayout,
+}
+
+fn default_esp() -> String {
+    "/boot/efi".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum PartitionSchema {
+    FullDisk(String),
+    Custom(HashMap<String, CustomPartition>),
+    /// The user already partitioned and mounted the target root, nothing is partitioned
+    Premounted {
+        bootdisk: Option<String>,
+    },
+}
+
+/// Filesystem of the root partition of a full disk layout. A ZFS root is a pool with
+/// datasets for `/`, `/nix` and `/home`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RootFilesystem {
+    #[default]
+    Ext4,
+    Zfs,
+}
+
+/// How a full disk is laid out.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiskLayout {
+    /// The root filesystem on a partition of its own
+    #[default]
+    Plain,
+    /// A LUKS container holding a volume group with root and swap logical volumes. An
+    /// unencrypted `/boot` partition is created unless the ESP is mounted there
+    LvmOnLuks { passphrase: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CustomPartition {
+    pub format: Option<String>,
+    pub mountpoint: Option<String>,
+    pub device: String,
+}
+
+/// Format of a disk image installed to with `icicle-helper partition --image`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageFormat {
+    #[default]
+    Raw,
+    /// Installed to a raw staging image and converted with `qemu-img` when detached
+    Qcow2,
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageFormat::Raw => write!(f, "raw"),
+            ImageFormat::Qcow2 => write!(f, "qcow2"),
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = HelperError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(ImageFormat::Raw),
+            "qcow2" => Ok(ImageFormat::Qcow2),
+            _ => Err(HelperError::new(
+                ErrorCode::InvalidRequest,
+                format!("Unknown image format {}", s),
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Disk {
+    pub name: String,
+    pub size: u64,
+    pub partitions: Vec<Partition>,
+    /// Partition table, e.g. `gpt` or `msdos`, `None` on disks without one
+    #[serde(default)]
+    pub table: Option<String>,
+    /// Bytes before the first partition, where GRUB embeds itself on `msdos` disks
+    #[serde(default)]
+    pub gap: u64,
+    /// Removable media or attached over USB, e.g. a USB stick
+    #[serde(default)]
+    pub removable: bool,
+    /// An LVM volume group named after its directory, e.g. `/dev/vg`, whose logical
+    /// volumes are listed as partitions. It can not be partitioned as a whole
+    #[serde(default)]
+    pub volume_group: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Partition {
+    pub name: String,
+    pub format: String,
+    pub size: u64,
+    /// Flagged as BIOS boot partition, where GRUB embeds itself on `gpt` disks
+    #[serde(default)]
+    pub bios_boot: bool,
+    /// Operating system booted from the partition, e.g. `Windows Boot Manager`
+    #[serde(default)]
+    pub os: Option<String>,
+}
+
+impl Partition {
+    /// Whether Windows is booted from the partition.
+    pub fn has_windows(&self) -> bool {
+        self.os
+            .as_deref()
+            .map_or(false, |os| os.contains("Windows"))
+    }
+}
+
+/// A single line of helper output.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "kebab-case")]
+pub enum Output {
+    Progress(Progress),
+    Log(String),
+    Response(Response),
+    Error(HelperError),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "kebab-case")]
+pub enum Response {
+    Disks(Vec<Disk>),
+    /// A disk image was partitioned through the loop device `device`
+    Attached {
+        device: String,
+    },
+    Done,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "step", rename_all = "kebab-case")]
+pub enum Progress {
+    FindingDisk { disk: String },
+    CreatingPartitionTable { table: String },
+    CreatingPartition { mountpoint: String },
+    Committing { disk: String },
+    Formatting { disk: String },
+    Mounting { device: String, target: String },
+    EnablingSwap { device: String },
+    Encrypting { device: String },
+    CreatingVolume { name: String },
+    AttachingImage { image: String },
+    DetachingImage { image: String },
+    ConvertingImage { image: String, format: ImageFormat },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    /// The helper was not run as root
+    NotRoot,
+    /// pkexec authentication was cancelled or refused
+    NotAuthorized,
+    /// The request could not be parsed
+    InvalidRequest,
+    /// The request was sent with a different protocol version
+    UnsupportedVersion,
+    DiskNotFound,
+    PartitionNotFound,
+    PartitionTable,
+    CreatePartition,
+    Commit,
+    Format,
+    Mount,
+    Unmount,
+    Swap,
+    /// The ZFS pool or its datasets could not be created
+    Zfs,
+    /// The LUKS container could not be created or opened
+    Encrypt,
+    /// The volume group or its logical volumes could not be created
+    Lvm,
+    /// A path would escape the target root
+    PathOutsideTarget,
+    /// A disk image could not be created, attached, detached or converted
+    Image,
+    Io,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HelperError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl HelperError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        HelperError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for HelperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for HelperError {}
+
+/// Attaches an [`ErrorCode`] to a failed operation.
+pub trait WithCode<T> {
+    fn code(self, code: ErrorCode, message: impl fmt::Display) -> Result<T, HelperError>;
+}
+
+impl<T, E: fmt::Display> WithCode<T> for Result<T, E> {
+    fn code(self, code: ErrorCode, message: impl fmt::Display) -> Result<T, HelperError> {
+        self.map_err(|e| HelperError::new(code, format!("{}: {}", message, e)))
+    }
+}
+
+impl<T> WithCode<T> for Option<T> {
+    fn code(self, code: ErrorCode, message: impl fmt::Display) -> Result<T, HelperError> {
+        self.ok_or_else(|| HelperError::new(code, message.to_string()))
+    }
+}
+
+/// Serializes `output` as a single protocol line.
+pub fn encode(output: Output) -> String {
+    serde_json::to_string(&Envelope::new(output)).unwrap_or_else(|e| {
+        format!(
+            r#"{{"version":{},"body":{{"type":"error","data":{{"code":"io","message":"{}"}}}}}}"#,
+            PROTOCOL_VERSION,
+            e.to_string().replace('"', "'")
+        )
+    })
+}
+
+/// Parses a single protocol line.
+pub fn decode(line: &str) -> Result<Output, HelperError> {
+    serde_json::from_str::<Envelope<Output>>(line)
+        .code(ErrorCode::InvalidRequest, "Failed to parse helper output")?
+        .into_body()
+}
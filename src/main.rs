@@ -5,10 +5,11 @@ use icicle::{
     config::{GETTEXT_PACKAGE, LOCALEDIR, RESOURCES_FILE},
     ui::window::AppModel,
 };
+use icicle_core::install::ImageTarget;
 use log::{error, info};
 use relm4::*;
 use simplelog::*;
-use std::fs::File;
+use std::{env, fs::File, path::PathBuf, str::FromStr};
 
 fn main() {
     CombinedLogger::init(vec![
@@ -25,6 +26,7 @@ fn main() {
         ),
     ])
     .unwrap();
+    let image = image_target();
     gtk::init().unwrap();
     setup_gettext();
     glib::set_application_name(&gettext("Icicle Installer"));
@@ -38,7 +40,7 @@ fn main() {
     let app = adw::Application::new(Some(icicle::config::APP_ID), gio::ApplicationFlags::empty());
     app.set_resource_base_path(Some("/org/snowflakeos/Icicle"));
     let app = RelmApp::with_app(app);
-    app.run::<AppModel>(());
+    app.run::<AppModel>(image);
 }
 
 fn setup_gettext() {
@@ -49,3 +51,34 @@ fn setup_gettext() {
         .expect("Unable to bind the text domain codeset to UTF-8");
     gettextrs::textdomain(GETTEXT_PACKAGE).expect("Unable to switch to the text domain");
 }
+
+/// Disk image to install to instead of a disk chosen on the partitioning page, given as
+/// `--image PATH [--image-size BYTES] [--image-format raw|qcow2]`. The size is required
+/// if the image does not exist yet.
+fn image_target() -> Option<ImageTarget> {
+    let mut args = env::args().skip(1);
+    let mut path = None;
+    let mut size = None;
+    let mut format = Default::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--image" => path = Some(parse_arg::<PathBuf>(&arg, args.next())),
+            "--image-size" => size = Some(parse_arg(&arg, args.next())),
+            "--image-format" => format = parse_arg(&arg, args.next()),
+            _ => {}
+        }
+    }
+    // The helper does not run in the current directory
+    let path = path.map(|path| env::current_dir().map_or(path.clone(), |dir| dir.join(path)))?;
+    Some(ImageTarget { path, size, format })
+}
+
+fn parse_arg<T: FromStr>(name: &str, value: Option<String>) -> T {
+    match value.and_then(|value| value.parse().ok()) {
+        Some(value) => value,
+        None => {
+            error!("Invalid or missing value for {}", name);
+            std::process::exit(1);
+        }
+    }
+}
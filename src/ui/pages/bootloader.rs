@@ -0,0 +1,239 @@
+use crate::ui::window::AppMsg;
+use adw::prelude::*;
+use gettextrs::gettext;
+use icicle_core::{
+    bootloader::{Bootloader, BootloaderConfig, Esp},
+    facts::SecureBoot,
+};
+use log::trace;
+use relm4::*;
+
+#[tracker::track]
+pub struct BootloaderModel {
+    config: BootloaderConfig,
+    secure_boot: SecureBoot,
+}
+
+#[derive(Debug)]
+pub enum BootloaderMsg {
+    CheckSelected,
+    /// Defaults of the installation config
+    SetConfig(BootloaderConfig),
+    SetSecureBoot(SecureBoot),
+    SetLoader(Bootloader),
+    SetEsp(Esp),
+    SetLanzaboote(bool),
+}
+
+#[relm4::component(pub)]
+impl SimpleComponent for BootloaderModel {
+    type Init = ();
+    type Input = BootloaderMsg;
+    type Output = AppMsg;
+
+    view! {
+        gtk::ScrolledWindow {
+            adw::Clamp {
+                gtk::Box {
+                    set_hexpand: true,
+                    set_vexpand: true,
+                    set_valign: gtk::Align::Center,
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 20,
+                    set_margin_all: 20,
+                    gtk::Label {
+                        add_css_class: "title-1",
+                        #[watch]
+                        set_label: &gettext("Bootloader"),
+                    },
+                    gtk::Label {
+                        set_wrap: true,
+                        set_justify: gtk::Justification::Center,
+                        #[watch]
+                        set_label: &gettext("Choose the bootloader that starts the installed system, and where the EFI system partition is mounted."),
+                    },
+                    adw::PreferencesGroup {
+                        #[watch]
+                        set_title: &gettext("Bootloader"),
+                        adw::ActionRow {
+                            set_title: "systemd-boot",
+                            #[watch]
+                            set_subtitle: &gettext("A simple menu listing the installed generations"),
+                            set_activatable: true,
+                            connect_activated[systemdbootbtn] => move |_| {
+                                systemdbootbtn.activate();
+                            },
+                            #[name(systemdbootbtn)]
+                            add_prefix = &gtk::CheckButton {
+                                #[watch]
+                                set_active: model.config.loader == Bootloader::SystemdBoot,
+                                connect_toggled[sender] => move |btn| {
+                                    if btn.is_active() {
+                                        sender.input(BootloaderMsg::SetLoader(Bootloader::SystemdBoot));
+                                    }
+                                },
+                            },
+                        },
+                        adw::ActionRow {
+                            set_title: "GRUB",
+                            #[watch]
+                            set_subtitle: &gettext("Also lists other operating systems installed on this computer"),
+                            set_activatable: true,
+                            connect_activated[grubbtn] => move |_| {
+                                grubbtn.activate();
+                            },
+                            #[name(grubbtn)]
+                            add_prefix = &gtk::CheckButton {
+                                set_group: Some(&systemdbootbtn),
+                                #[watch]
+                                set_active: model.config.loader == Bootloader::Grub,
+                                connect_toggled[sender] => move |btn| {
+                                    if btn.is_active() {
+                                        sender.input(BootloaderMsg::SetLoader(Bootloader::Grub));
+                                    }
+                                },
+                            },
+                        },
+                        adw::ActionRow {
+                            set_title: "Limine",
+                            #[watch]
+                            set_subtitle: &gettext("A fast and lightweight menu listing the installed generations"),
+                            set_activatable: true,
+                            connect_activated[liminebtn] => move |_| {
+                                liminebtn.activate();
+                            },
+                            #[name(liminebtn)]
+                            add_prefix = &gtk::CheckButton {
+                                set_group: Some(&systemdbootbtn),
+                                #[watch]
+                                set_active: model.config.loader == Bootloader::Limine,
+                                connect_toggled[sender] => move |btn| {
+                                    if btn.is_active() {
+                                        sender.input(BootloaderMsg::SetLoader(Bootloader::Limine));
+                                    }
+                                },
+                            },
+                        },
+                    },
+                    adw::PreferencesGroup {
+                        #[watch]
+                        set_title: &gettext("Secure Boot"),
+                        #[watch]
+                        set_visible: model.config.loader == Bootloader::SystemdBoot,
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Sign for Secure Boot"),
+                            #[watch]
+                            set_subtitle: &match model.secure_boot {
+                                SecureBoot::Enabled => gettext("Secure Boot is enabled on this computer, the installed system only boots once its keys are enrolled. Replaces systemd-boot with lanzaboote, keys are enrolled after the installation"),
+                                SecureBoot::SetupMode => gettext("Secure Boot is in setup mode on this computer. Replaces systemd-boot with lanzaboote, keys are enrolled after the installation"),
+                                SecureBoot::Disabled => gettext("Replaces systemd-boot with lanzaboote, keys are enrolled after the installation"),
+                            },
+                            set_activatable_widget: Some(&lanzabooteswitch),
+                            #[name(lanzabooteswitch)]
+                            add_suffix = &gtk::Switch {
+                                set_valign: gtk::Align::Center,
+                                #[watch]
+                                set_active: model.config.lanzaboote == Some(true),
+                                connect_active_notify[sender] => move |switch| {
+                                    sender.input(BootloaderMsg::SetLanzaboote(switch.is_active()));
+                                },
+                            },
+                        },
+                    },
+                    adw::PreferencesGroup {
+                        #[watch]
+                        set_title: &gettext("EFI System Partition"),
+                        adw::ActionRow {
+                            set_title: "/boot/efi",
+                            #[watch]
+                            set_subtitle: &gettext("Kernels are kept on the root partition, a small EFI system partition is enough"),
+                            set_activatable: true,
+                            connect_activated[bootefibtn] => move |_| {
+                                bootefibtn.activate();
+                            },
+                            #[name(bootefibtn)]
+                            add_prefix = &gtk::CheckButton {
+                                #[watch]
+                                set_active: model.config.esp == Esp::BootEfi,
+                                connect_toggled[sender] => move |btn| {
+                                    if btn.is_active() {
+                                        sender.input(BootloaderMsg::SetEsp(Esp::BootEfi));
+                                    }
+                                },
+                            },
+                        },
+                        adw::ActionRow {
+                            set_title: "/boot",
+                            #[watch]
+                            set_subtitle: &gettext("Kernels are kept on the EFI system partition, which needs room for several of them"),
+                            set_activatable: true,
+                            connect_activated[bootbtn] => move |_| {
+                                bootbtn.activate();
+                            },
+                            #[name(bootbtn)]
+                            add_prefix = &gtk::CheckButton {
+                                set_group: Some(&bootefibtn),
+                                #[watch]
+                                set_active: model.config.esp == Esp::Boot,
+                                connect_toggled[sender] => move |btn| {
+                                    if btn.is_active() {
+                                        sender.input(BootloaderMsg::SetEsp(Esp::Boot));
+                                    }
+                                },
+                            },
+                        },
+                    },
+                }
+            }
+        }
+    }
+
+    fn init(
+        _init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = BootloaderModel {
+            config: BootloaderConfig::default(),
+            secure_boot: SecureBoot::default(),
+            tracker: 0,
+        };
+
+        let widgets = view_output!();
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
+        self.reset();
+        match msg {
+            BootloaderMsg::CheckSelected => {
+                let _ = sender.output(AppMsg::SetCanGoForward(true));
+            }
+            BootloaderMsg::SetConfig(config) => {
+                self.set_config(config);
+            }
+            BootloaderMsg::SetSecureBoot(secure_boot) => {
+                self.set_secure_boot(secure_boot);
+            }
+            BootloaderMsg::SetLoader(loader) => {
+                trace!("BootloaderMsg::SetLoader {:?}", loader);
+                self.get_mut_config().loader = loader;
+                let _ = sender.output(AppMsg::SetBootloaderConfig(self.config.clone()));
+            }
+            BootloaderMsg::SetEsp(esp) => {
+                trace!("BootloaderMsg::SetEsp {:?}", esp);
+                self.get_mut_config().esp = esp;
+                let _ = sender.output(AppMsg::SetBootloaderConfig(self.config.clone()));
+            }
+            BootloaderMsg::SetLanzaboote(lanzaboote) => {
+                trace!("BootloaderMsg::SetLanzaboote {}", lanzaboote);
+                if self.config.lanzaboote != Some(lanzaboote) {
+                    self.get_mut_config().lanzaboote = Some(lanzaboote);
+                    let _ = sender.output(AppMsg::SetBootloaderConfig(self.config.clone()));
+                }
+            }
+        }
+    }
+}
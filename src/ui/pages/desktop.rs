@@ -0,0 +1,132 @@
+use crate::ui::window::AppMsg;
+use adw::prelude::*;
+use crate::utils::i18n::text_i18n;
+use gettextrs::gettext;
+use icicle_core::config::Desktop;
+use log::trace;
+use relm4::*;
+
+#[tracker::track]
+pub struct DesktopModel {
+    #[tracker::no_eq]
+    group: adw::PreferencesGroup,
+    /// Every desktop with its row
+    #[tracker::no_eq]
+    rows: Vec<(Desktop, adw::ActionRow)>,
+    selected: Option<String>,
+    locale: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum DesktopMsg {
+    CheckSelected,
+    Select(String),
+    SetLocale(Option<String>),
+}
+
+pub struct DesktopInit {
+    pub desktops: Vec<Desktop>,
+    pub selected: Option<String>,
+}
+
+#[relm4::component(pub)]
+impl SimpleComponent for DesktopModel {
+    type Init = DesktopInit;
+    type Input = DesktopMsg;
+    type Output = AppMsg;
+
+    view! {
+        gtk::ScrolledWindow {
+            adw::Clamp {
+                gtk::Box {
+                    set_hexpand: true,
+                    set_vexpand: true,
+                    set_valign: gtk::Align::Center,
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 20,
+                    set_margin_all: 20,
+                    gtk::Label {
+                        add_css_class: "title-1",
+                        #[track(model.changed(DesktopModel::locale()))]
+                        set_label: &gettext("Desktop"),
+                    },
+                    #[local_ref]
+                    group -> adw::PreferencesGroup {
+
+                    },
+                }
+            }
+        }
+    }
+
+    fn init(
+        init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let group = adw::PreferencesGroup::new();
+        let radiogroup = gtk::CheckButton::new();
+        let mut rows = vec![];
+
+        for desktop in init.desktops {
+            let checkbtn = gtk::CheckButton::new();
+            checkbtn.set_group(Some(&radiogroup));
+            checkbtn.set_active(init.selected.as_ref() == Some(&desktop.id));
+            let id = desktop.id.to_string();
+            let sender = sender.clone();
+            checkbtn.connect_toggled(move |checkbtn| {
+                if checkbtn.is_active() {
+                    sender.input(DesktopMsg::Select(id.to_string()));
+                }
+            });
+            let row = adw::ActionRow::new();
+            row.add_prefix(&checkbtn);
+            row.set_activatable_widget(Some(&checkbtn));
+            group.add(&row);
+            rows.push((desktop, row));
+        }
+
+        let model = DesktopModel {
+            group,
+            rows,
+            selected: init.selected,
+            locale: None,
+            tracker: 0,
+        };
+        model.update_rows();
+
+        let group = &model.group;
+        let widgets = view_output!();
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
+        self.reset();
+        match msg {
+            DesktopMsg::CheckSelected => {
+                let _ = sender.output(AppMsg::SetCanGoForward(self.selected.is_some()));
+            }
+            DesktopMsg::Select(id) => {
+                trace!("DesktopMsg::Select {}", id);
+                self.set_selected(Some(id));
+                let _ = sender.output(AppMsg::SetDesktopConfig(self.selected.clone()));
+                let _ = sender.output(AppMsg::SetCanGoForward(true));
+            }
+            DesktopMsg::SetLocale(locale) => {
+                self.set_locale(locale);
+                self.update_rows();
+            }
+        }
+    }
+}
+
+impl DesktopModel {
+    /// Translates the desktop names and descriptions.
+    fn update_rows(&self) {
+        for (desktop, row) in &self.rows {
+            row.set_title(&text_i18n(&desktop.name));
+            row.set_subtitle(&desktop.description.as_ref().map(text_i18n).unwrap_or_default());
+        }
+    }
+}
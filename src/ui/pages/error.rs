@@ -1,13 +1,13 @@
-use crate::{config::LIBEXECDIR, ui::window::AppMsg};
+use crate::{ui::window::AppMsg, utils::helper::run_helper};
 use adw::prelude::*;
 use anyhow::{Context, Result};
 use gettextrs::gettext;
 use log::error;
 use relm4::*;
-use std::process::Command;
 use tokio::io::AsyncWriteExt;
 
 pub struct ErrorModel {
+    message: Option<String>,
     messegebuffer: gtk::TextBuffer,
     uploadbutton: UploadButton,
     url: String,
@@ -23,7 +23,7 @@ pub enum UploadButton {
 
 #[derive(Debug)]
 pub enum ErrorMsg {
-    Show,
+    Show(Option<String>),
     UploadReport,
     SetUrl(String),
     SetUploadButton(UploadButton),
@@ -55,6 +55,15 @@ impl SimpleComponent for ErrorModel {
                         set_icon_name: Some("process-stop-symbolic"),
                         set_pixel_size: 128,
                     },
+                    gtk::Label {
+                        add_css_class: "title-3",
+                        set_wrap: true,
+                        set_justify: gtk::Justification::Center,
+                        #[watch]
+                        set_visible: model.message.is_some(),
+                        #[watch]
+                        set_label: model.message.as_deref().unwrap_or_default(),
+                    },
                     gtk::Frame {
                         gtk::ScrolledWindow {
                             set_height_request: 300,
@@ -113,6 +122,7 @@ impl SimpleComponent for ErrorModel {
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
         let model = ErrorModel {
+            message: None,
             uploadbutton: UploadButton::Button,
             url: String::new(),
             messegebuffer: gtk::TextBuffer::new(None),
@@ -120,18 +130,15 @@ impl SimpleComponent for ErrorModel {
         };
         let spinner = model.spinner.clone();
         let widgets = view_output!();
-        sender.input(ErrorMsg::Show);
+        sender.input(ErrorMsg::Show(None));
         ComponentParts { model, widgets }
     }
 
     fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
         match msg {
-            ErrorMsg::Show => {
-                if let Err(e) = Command::new("pkexec")
-                    .arg(&format!("{}/icicle-helper", LIBEXECDIR))
-                    .arg("unmount")
-                    .output()
-                {
+            ErrorMsg::Show(message) => {
+                self.message = message;
+                if let Err(e) = run_helper(&["unmount"], None) {
                     error!("Failed to unmount partitions: {}", e);
                 }
 
@@ -0,0 +1,225 @@
+use crate::{ui::window::AppMsg, utils::i18n::text_i18n};
+use adw::prelude::*;
+use icicle_core::{
+    config::{Field, FieldKind},
+    form::{default_values, FieldValue},
+    text::Text,
+};
+use log::trace;
+use relm4::*;
+use std::collections::HashMap;
+
+#[tracker::track]
+pub struct FormModel {
+    id: String,
+    title: Text,
+    #[tracker::no_eq]
+    group: adw::PreferencesGroup,
+    /// Every field with its row
+    #[tracker::no_eq]
+    rows: Vec<(Field, adw::PreferencesRow)>,
+    values: HashMap<String, FieldValue>,
+    locale: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum FormMsg {
+    CheckSelected,
+    SetValue(String, FieldValue),
+    SetLocale(Option<String>),
+}
+
+pub struct FormInit {
+    pub id: String,
+    pub title: Text,
+    pub fields: Vec<Field>,
+}
+
+#[relm4::component(pub)]
+impl SimpleComponent for FormModel {
+    type Init = FormInit;
+    type Input = FormMsg;
+    type Output = AppMsg;
+
+    view! {
+        gtk::ScrolledWindow {
+            adw::Clamp {
+                gtk::Box {
+                    set_hexpand: true,
+                    set_vexpand: true,
+                    set_valign: gtk::Align::Center,
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 20,
+                    set_margin_all: 20,
+                    gtk::Label {
+                        add_css_class: "title-1",
+                        #[track(model.changed(FormModel::locale()))]
+                        set_label: &text_i18n(&model.title),
+                    },
+                    #[local_ref]
+                    group -> adw::PreferencesGroup {
+
+                    },
+                }
+            }
+        }
+    }
+
+    fn init(
+        init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let values = default_values(&init.fields);
+        let group = adw::PreferencesGroup::new();
+        let mut rows = vec![];
+
+        for field in init.fields {
+            let id = field.id.to_string();
+            let row: adw::PreferencesRow = match &field.kind {
+                FieldKind::Text { default, .. } => {
+                    let row = adw::EntryRow::new();
+                    row.set_text(default.as_deref().unwrap_or_default());
+                    let sender = sender.clone();
+                    row.connect_changed(move |row| {
+                        sender.input(FormMsg::SetValue(
+                            id.to_string(),
+                            FieldValue::Text(row.text().to_string()),
+                        ));
+                    });
+                    row.upcast()
+                }
+                FieldKind::Number { min, max, .. } => {
+                    let spin = gtk::SpinButton::with_range(
+                        min.map_or(i32::MIN as f64, |min| min as f64),
+                        max.map_or(i32::MAX as f64, |max| max as f64),
+                        1.0,
+                    );
+                    spin.set_valign(gtk::Align::Center);
+                    if let FieldValue::Number(value) = field.default_value() {
+                        spin.set_value(value as f64);
+                    }
+                    let sender = sender.clone();
+                    spin.connect_value_changed(move |spin| {
+                        sender.input(FormMsg::SetValue(
+                            id.to_string(),
+                            FieldValue::Number(spin.value() as i64),
+                        ));
+                    });
+                    let row = adw::ActionRow::new();
+                    row.add_suffix(&spin);
+                    row.upcast()
+                }
+                FieldKind::Toggle { default } => {
+                    let switch = gtk::Switch::new();
+                    switch.set_valign(gtk::Align::Center);
+                    switch.set_active(*default);
+                    let sender = sender.clone();
+                    switch.connect_active_notify(move |switch| {
+                        sender.input(FormMsg::SetValue(
+                            id.to_string(),
+                            FieldValue::Toggle(switch.is_active()),
+                        ));
+                    });
+                    let row = adw::ActionRow::new();
+                    row.add_suffix(&switch);
+                    row.set_activatable_widget(Some(&switch));
+                    row.upcast()
+                }
+                FieldKind::Dropdown { options, .. } => {
+                    let model = gtk::StringList::new(
+                        &options.iter().map(|o| o.as_str()).collect::<Vec<_>>(),
+                    );
+                    let row = adw::ComboRow::new();
+                    row.set_model(Some(&model));
+                    if let FieldValue::Text(value) = field.default_value() {
+                        if let Some(i) = options.iter().position(|o| o == &value) {
+                            row.set_selected(i as u32);
+                        }
+                    }
+                    let sender = sender.clone();
+                    let options = options.clone();
+                    row.connect_selected_notify(move |row| {
+                        if let Some(option) = options.get(row.selected() as usize) {
+                            sender.input(FormMsg::SetValue(
+                                id.to_string(),
+                                FieldValue::Text(option.to_string()),
+                            ));
+                        }
+                    });
+                    row.upcast()
+                }
+            };
+            group.add(&row);
+            rows.push((field, row));
+        }
+
+        let model = FormModel {
+            id: init.id,
+            title: init.title,
+            group,
+            rows,
+            values,
+            locale: None,
+            tracker: 0,
+        };
+        model.update_rows();
+
+        let group = &model.group;
+        let widgets = view_output!();
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
+        self.reset();
+        match msg {
+            FormMsg::CheckSelected => {
+                let _ = sender.output(AppMsg::SetCanGoForward(self.is_valid()));
+            }
+            FormMsg::SetValue(id, value) => {
+                trace!("FormMsg::SetValue {} {:?}", id, value);
+                self.values.insert(id, value);
+                self.update_rows();
+                let _ = sender.output(AppMsg::SetFormConfig(
+                    self.id.to_string(),
+                    self.values.clone(),
+                ));
+                let _ = sender.output(AppMsg::SetCanGoForward(self.is_valid()));
+            }
+            FormMsg::SetLocale(locale) => {
+                self.set_locale(locale);
+                self.update_rows();
+            }
+        }
+    }
+}
+
+impl FormModel {
+    fn is_valid(&self) -> bool {
+        self.rows.iter().all(|(field, _)| {
+            self.values
+                .get(&field.id)
+                .map_or(false, |value| field.is_valid(value))
+        })
+    }
+
+    /// Translates the labels and marks rows whose value is not accepted.
+    fn update_rows(&self) {
+        for (field, row) in &self.rows {
+            row.set_title(&text_i18n(&field.label));
+            if let Some(row) = row.downcast_ref::<adw::ActionRow>() {
+                row.set_subtitle(&field.description.as_ref().map(text_i18n).unwrap_or_default());
+            }
+            let valid = self
+                .values
+                .get(&field.id)
+                .map_or(false, |value| field.is_valid(value));
+            if valid {
+                row.remove_css_class("error");
+            } else {
+                row.add_css_class("error");
+            }
+        }
+    }
+}
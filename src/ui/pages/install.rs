@@ -1,7 +1,10 @@
-use crate::{config::SYSCONFDIR, ui::window::AppMsg, utils::parse::parse_branding};
+use crate::{
+    ui::window::AppMsg,
+    utils::{helper::paths, i18n::text_i18n},
+};
 use adw::prelude::*;
-use gettextrs::gettext;
 use gtk::gio;
+use icicle_core::{config::parse_branding, text::Text};
 use log::{debug, error, info};
 use relm4::{factory::*, *};
 use std::fs::File;
@@ -111,16 +114,14 @@ impl SimpleComponent for InstallModel {
             locale: None
         };
 
-        if let Ok(brandingconfig) = parse_branding(&branding) {
+        let brandingdir = paths().config_dir().join("branding").join(&branding);
+        if let Ok(brandingconfig) = parse_branding(&paths().config_dir(), &branding) {
             let mut slides_guard = model.slides.guard();
             for slide in brandingconfig.slides {
                 slides_guard.push_back(InstallSlide {
                     title: slide.title,
                     subtitle: slide.subtitle,
-                    image: format!(
-                        "{}/icicle/branding/{}/{}",
-                        SYSCONFDIR, branding, slide.image
-                    ),
+                    image: brandingdir.join(&slide.image).to_string_lossy().to_string(),
                     locale: model.locale.clone(),
                     tracker: 0,
                 });
@@ -214,7 +215,7 @@ impl SimpleComponent for InstallModel {
                         let _ = sender.output(AppMsg::FinishInstall);
                     } else {
                         debug!("Installation Failed!");
-                        let _ = sender.output(AppMsg::Error);
+                        let _ = sender.output(AppMsg::Error(None));
                     }
                 }
             }
@@ -233,8 +234,8 @@ impl SimpleComponent for InstallModel {
 #[derive(Debug)]
 #[tracker::track]
 pub struct InstallSlide {
-    title: String,
-    subtitle: String,
+    title: Text,
+    subtitle: Text,
     image: String,
     locale: Option<String>,
 }
@@ -259,12 +260,12 @@ impl FactoryComponent for InstallSlide {
                 set_halign: gtk::Align::Center,
                 add_css_class: "title-3",
                 #[track(self.changed(InstallSlide::locale()))]
-                set_label: &gettext(&self.title)
+                set_label: &text_i18n(&self.title)
             },
             gtk::Label {
                 set_halign: gtk::Align::Center,
                 #[track(self.changed(InstallSlide::locale()))]
-                set_label: &gettext(&self.subtitle)
+                set_label: &text_i18n(&self.subtitle)
             },
             gtk::Picture {
                 set_margin_all: 50,
@@ -1,10 +1,10 @@
-use crate::ui::window::AppMsg;
+use crate::{ui::window::AppMsg, utils::helper::system};
 use adw::prelude::*;
 use gettextrs::gettext;
 use gnome_desktop::{self, XkbInfo, XkbInfoExt};
+use icicle_core::system::Cmd;
 use log::trace;
 use relm4::*;
-use std::process::Command;
 
 #[tracker::track]
 #[derive(Debug)]
@@ -290,23 +290,21 @@ impl SimpleComponent for KeyboardModel {
                 }
                 self.selected = layout;
                 if let Some(selected) = &self.selected {
-                    let _ = Command::new("gsettings")
-                        .arg("set")
-                        .arg("org.gnome.desktop.input-sources")
-                        .arg("sources")
-                        .arg(&format!("[('xkb','{}')]", selected))
-                        .spawn();
+                    let system = system();
+                    let _ = system.spawn(&Cmd::new("gsettings").args([
+                        "set",
+                        "org.gnome.desktop.input-sources",
+                        "sources",
+                        &format!("[('xkb','{}')]", selected),
+                    ]));
                     if let (Some(layout), Some(variant)) =
                         (selected.split('+').next(), selected.split('+').nth(1))
                     {
-                        let _ = Command::new("setxkbmap")
-                            .arg("-layout")
-                            .arg(layout)
-                            .arg("-variant")
-                            .arg(variant)
-                            .spawn();
+                        let _ = system.spawn(
+                            &Cmd::new("setxkbmap").args(["-layout", layout, "-variant", variant]),
+                        );
                     } else {
-                        let _ = Command::new("setxkbmap").arg(selected).spawn();
+                        let _ = system.spawn(&Cmd::new("setxkbmap").arg(selected));
                     }
                 }
             }
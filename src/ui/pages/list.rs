@@ -1,13 +1,23 @@
-use crate::{ui::window::AppMsg, utils::parse::Choice};
+use crate::{
+    ui::window::AppMsg,
+    utils::i18n::{config_i18n, i18n_f, text_i18n},
+};
 use adw::prelude::*;
 use gettextrs::gettext;
+use icicle_core::{
+    choices::{conflict, defaults, dependents, required},
+    conditions::{is_shown, Selections},
+    config::Choice,
+    facts::Facts,
+    text::Text,
+};
 use relm4::{factory::*, *};
 use std::collections::HashMap;
 
 #[tracker::track]
 pub struct ListModel {
     id: String,
-    title: String,
+    title: Text,
     #[tracker::no_eq]
     list: FactoryVecDeque<ListItem>,
     #[tracker::no_eq]
@@ -16,6 +26,10 @@ pub struct ListModel {
     group: Option<gtk::CheckButton>,
     required: bool,
     locale: Option<String>,
+    #[tracker::no_eq]
+    facts: Facts,
+    /// Choices whose `when` condition does not hold
+    hidden: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -23,15 +37,18 @@ pub enum ListMsg {
     CheckSelected,
     Select(String),
     Deselect(String),
-    SetLocale(Option<String>)
+    SetLocale(Option<String>),
+    /// Re-evaluates choice conditions against the selections of shown lists
+    SetSelections(Selections),
 }
 
 pub struct ListInit {
     pub id: String,
     pub multiple: bool,
     pub required: bool,
-    pub title: String,
+    pub title: Text,
     pub choices: Vec<HashMap<String, Choice>>,
+    pub facts: Facts,
 }
 
 #[relm4::component(pub)]
@@ -53,7 +70,7 @@ impl SimpleComponent for ListModel {
                     gtk::Label {
                         add_css_class: "title-1",
                         #[track(model.changed(ListModel::locale()))]
-                        set_label: &gettext(&model.title),
+                        set_label: &text_i18n(&model.title),
                     },
                     #[local_ref]
                     group -> adw::PreferencesGroup {
@@ -103,8 +120,15 @@ impl SimpleComponent for ListModel {
                 Some(gtk::CheckButton::new())
             },
             locale: None,
+            facts: init.facts,
+            hidden: vec![],
             tracker: 0
         };
+        model.hidden = model.hidden_choices(&Selections::new());
+        let defaults = defaults(&model.choices)
+            .into_iter()
+            .filter(|key| !model.hidden.contains(key))
+            .collect::<Vec<_>>();
 
         let mut list_guard = model.list.guard();
         for (key, choice) in &model.choices {
@@ -113,11 +137,17 @@ impl SimpleComponent for ListModel {
                 description: choice.description.clone().unwrap_or_default(),
                 group: model.group.clone(),
                 locale: model.locale.clone(),
+                visible: !model.hidden.contains(key),
+                active: defaults.contains(key),
+                conflict: None,
                 tracker: 0,
             };
             list_guard.push_back(item);
         }
         list_guard.drop();
+        for key in defaults {
+            sender.input(ListMsg::Select(key));
+        }
         let group = model.list.widget();
         let widgets = view_output!();
         ComponentParts { model, widgets }
@@ -135,18 +165,32 @@ impl SimpleComponent for ListModel {
                 let _ = sender.output(AppMsg::SetCanGoForward(cangoforward));
             }
             ListMsg::Select(key) => {
-                self.selected.push(key);
+                if self.selected.contains(&key) {
+                    return;
+                }
+                if self.group.is_some() {
+                    self.selected = vec![key];
+                } else {
+                    for key in required(&self.choices, &key) {
+                        if !self.selected.contains(&key) {
+                            self.selected.push(key);
+                        }
+                    }
+                }
+                self.update_items();
                 sender.input(ListMsg::CheckSelected);
-                let mut selected = self.choices.iter().cloned().collect::<HashMap<_, _>>();
-                selected.retain(|k, _| self.selected.contains(k));
-                let _ = sender.output(AppMsg::SetListConfig(self.id.to_string(), selected));
+                self.send_selected(&sender);
             }
             ListMsg::Deselect(key) => {
-                self.selected.retain(|k| k != &key);
+                if !self.selected.contains(&key) {
+                    return;
+                }
+                let mut deselected = dependents(&self.choices, &key);
+                deselected.push(key);
+                self.selected.retain(|k| !deselected.contains(k));
+                self.update_items();
                 sender.input(ListMsg::CheckSelected);
-                let mut selected = self.choices.iter().cloned().collect::<HashMap<_, _>>();
-                selected.retain(|k, _| self.selected.contains(k));
-                let _ = sender.output(AppMsg::SetListConfig(self.id.to_string(), selected));
+                self.send_selected(&sender);
             }
             ListMsg::SetLocale(locale) => {
                 self.set_locale(locale);
@@ -155,17 +199,93 @@ impl SimpleComponent for ListModel {
                     item.set_locale(self.locale.clone());
                 }
                 list_guard.drop();
+                self.update_items();
+            }
+            ListMsg::SetSelections(selections) => {
+                let hidden = self.hidden_choices(&selections);
+                if hidden != self.hidden {
+                    let mut list_guard = self.list.guard();
+                    for item in list_guard.iter_mut() {
+                        item.set_visible(!hidden.contains(&item.title));
+                    }
+                    list_guard.drop();
+
+                    // Hidden choices are deselected, along with everything requiring them
+                    let mut deselected = vec![];
+                    for key in self.selected.iter().filter(|k| hidden.contains(k)) {
+                        deselected.push(key.to_string());
+                        deselected.extend(dependents(&self.choices, key));
+                    }
+                    self.set_hidden(hidden);
+                    if !deselected.is_empty() {
+                        self.selected.retain(|k| !deselected.contains(k));
+                        self.update_items();
+                        sender.input(ListMsg::CheckSelected);
+                        self.send_selected(&sender);
+                    }
+                }
             }
         }
     }
 }
 
+impl ListModel {
+    /// Syncs check buttons with the selection and disables conflicting choices.
+    fn update_items(&mut self) {
+        let mut list_guard = self.list.guard();
+        for item in list_guard.iter_mut() {
+            let selected = self.selected.contains(&item.title);
+            item.set_active(selected);
+            item.set_conflict(if selected {
+                None
+            } else {
+                conflict(&self.choices, &item.title, &self.selected).map(|other| {
+                    // Translators: Do NOT translate the '{}'
+                    i18n_f("Conflicts with {}", &[&config_i18n(other)])
+                })
+            });
+        }
+        list_guard.drop();
+    }
+
+    fn send_selected(&self, sender: &ComponentSender<Self>) {
+        let mut selected = self.choices.iter().cloned().collect::<HashMap<_, _>>();
+        selected.retain(|k, _| self.selected.contains(k));
+        let _ = sender.output(AppMsg::SetListConfig(self.id.to_string(), selected));
+    }
+
+    fn hidden_choices(&self, selections: &Selections) -> Vec<String> {
+        self.choices
+            .iter()
+            .filter(|(_, choice)| !is_shown(choice.when.as_ref(), &self.facts, selections))
+            .map(|(key, _)| key.to_string())
+            .collect()
+    }
+}
+
 #[tracker::track]
 pub struct ListItem {
     title: String,
-    description: String,
+    description: Text,
     group: Option<gtk::CheckButton>,
     locale: Option<String>,
+    visible: bool,
+    active: bool,
+    /// Why the choice can not be selected
+    conflict: Option<String>,
+}
+
+impl ListItem {
+    fn subtitle(&self) -> String {
+        let mut subtitle = text_i18n(&self.description);
+        if let Some(conflict) = &self.conflict {
+            if !subtitle.is_empty() {
+                subtitle.push('\n');
+            }
+            subtitle.push_str(conflict);
+        }
+        subtitle
+    }
 }
 
 #[derive(Debug)]
@@ -186,16 +306,22 @@ impl FactoryComponent for ListItem {
     view! {
         adw::ActionRow {
             #[track(self.changed(ListItem::locale()))]
-            set_title: &gettext(&self.title),
-            #[track(self.changed(ListItem::locale()))]
-            set_subtitle: &gettext(&self.description),
+            set_title: &config_i18n(&self.title),
+            #[track(self.changed(ListItem::locale()) || self.changed(ListItem::conflict()))]
+            set_subtitle: &self.subtitle(),
+            #[track(self.changed(ListItem::conflict()))]
+            set_sensitive: self.conflict.is_none(),
             set_activatable: true,
+            #[track(self.changed(ListItem::visible()))]
+            set_visible: self.visible,
             connect_activated[checkbtn] => move |_| {
                 checkbtn.activate();
             },
             #[name(checkbtn)]
             add_suffix = &gtk::CheckButton {
                 set_group: self.group.as_ref(),
+                #[track(self.changed(ListItem::active()))]
+                set_active: self.active,
                 connect_toggled[sender, title = self.title.to_string()] => move |checkbtn| {
                     if checkbtn.is_active() {
                         sender.output(ListItemMsg::Select(title.to_string()));
@@ -0,0 +1,220 @@
+use crate::{
+    ui::window::AppMsg,
+    utils::{helper::system, i18n::i18n_f},
+};
+use adw::prelude::*;
+use gettextrs::gettext;
+use gtk::gio;
+use icicle_core::{
+    is_efi,
+    bootloader::BootloaderConfig,
+    mounts::{check_mounts, parent_disk, target_mounts, Mount, MountProblem},
+    protocol::{PartitionSchema, TARGET_ROOT},
+};
+use log::{debug, error, trace};
+use relm4::*;
+use vte::{self, TerminalExt, TerminalExtManual};
+
+pub struct ManualModel {
+    terminal: vte::Terminal,
+    mountlist: gtk::ListBox,
+    mounts: Vec<Mount>,
+    problems: Vec<MountProblem>,
+    efi: bool,
+    /// Mountpoint of the EFI system partition
+    esp: String,
+}
+
+#[derive(Debug)]
+pub enum ManualMsg {
+    SpawnShell,
+    SetEsp(String),
+    CheckSelected,
+}
+
+#[relm4::component(pub)]
+impl SimpleComponent for ManualModel {
+    type Init = ();
+    type Input = ManualMsg;
+    type Output = AppMsg;
+
+    view! {
+        gtk::ScrolledWindow {
+            set_hexpand: true,
+            set_vexpand: true,
+            adw::Clamp {
+                set_maximum_size: 800,
+                gtk::Box {
+                    set_hexpand: true,
+                    set_vexpand: true,
+                    set_valign: gtk::Align::Center,
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 20,
+                    set_margin_all: 20,
+                    gtk::Label {
+                        add_css_class: "title-1",
+                        #[watch]
+                        set_label: &gettext("Manual Partitioning"),
+                    },
+                    gtk::Label {
+                        set_wrap: true,
+                        set_justify: gtk::Justification::Center,
+                        #[watch]
+                        set_label: &if model.efi {
+                            // Translators: Do NOT translate the '{}'
+                            i18n_f("Partition and format your disks, then mount the root partition at {} and the EFI system partition at {}. Use sudo to run commands as root.", &[TARGET_ROOT, &format!("{}{}", TARGET_ROOT, model.esp)])
+                        } else {
+                            // Translators: Do NOT translate the '{}'
+                            i18n_f("Partition and format your disks, then mount the root partition at {}. Use sudo to run commands as root.", &[TARGET_ROOT])
+                        },
+                    },
+                    gtk::Frame {
+                        #[local_ref]
+                        terminal -> vte::Terminal {
+                            set_hexpand: true,
+                            set_height_request: 300,
+                            connect_child_exited[sender] => move |_term, status| {
+                                debug!("Manual partitioning shell exited with status: {}", status);
+                                sender.input(ManualMsg::SpawnShell);
+                            },
+                        }
+                    },
+                    adw::PreferencesGroup {
+                        #[watch]
+                        set_title: &gettext("Mounted Partitions"),
+                        #[watch]
+                        set_visible: !model.mounts.is_empty(),
+                        #[local_ref]
+                        mountlist -> gtk::ListBox {
+                            add_css_class: "boxed-list",
+                            set_selection_mode: gtk::SelectionMode::None,
+                        }
+                    },
+                    gtk::Label {
+                        add_css_class: "error",
+                        set_wrap: true,
+                        set_justify: gtk::Justification::Center,
+                        #[watch]
+                        set_visible: !model.problems.is_empty(),
+                        #[watch]
+                        set_label: &model.problems.iter().map(describe_problem).collect::<Vec<_>>().join("\n"),
+                    },
+                    gtk::Button {
+                        add_css_class: "pill",
+                        set_halign: gtk::Align::Center,
+                        #[watch]
+                        set_label: &gettext("Check Mounts"),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(ManualMsg::CheckSelected);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn init(
+        _parent_window: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = ManualModel {
+            terminal: vte::Terminal::new(),
+            mountlist: gtk::ListBox::new(),
+            mounts: vec![],
+            problems: vec![],
+            efi: is_efi(system().as_ref()),
+            esp: BootloaderConfig::default().esp.mountpoint().to_string(),
+        };
+
+        let terminal = &model.terminal;
+        let mountlist = &model.mountlist;
+        let widgets = view_output!();
+
+        sender.input(ManualMsg::SpawnShell);
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
+        match msg {
+            ManualMsg::SpawnShell => {
+                let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+                self.terminal.spawn_async(
+                    vte::PtyFlags::DEFAULT,
+                    Some("/"),
+                    &[&shell],
+                    &[],
+                    glib::SpawnFlags::DEFAULT,
+                    || (),
+                    -1,
+                    gio::Cancellable::NONE,
+                    |_term, pid, err| (debug!("VTE Shell: {:?} {:?}", pid, err)),
+                );
+            }
+            ManualMsg::SetEsp(esp) => {
+                trace!("ManualMsg::SetEsp {}", esp);
+                self.esp = esp;
+            }
+            ManualMsg::CheckSelected => {
+                let system = system();
+                self.mounts = match target_mounts(system.as_ref()) {
+                    Ok(mounts) => mounts,
+                    Err(e) => {
+                        error!("{:#}", e);
+                        vec![]
+                    }
+                };
+                self.problems = check_mounts(&self.mounts, self.efi.then_some(self.esp.as_str()));
+                trace!("ManualMsg::CheckSelected {:?}", self.problems);
+
+                while let Some(row) = self.mountlist.first_child() {
+                    self.mountlist.remove(&row);
+                }
+                for mount in &self.mounts {
+                    let row = adw::ActionRow::builder()
+                        .title(&mount.mountpoint)
+                        .subtitle(&mount.device)
+                        .build();
+                    row.add_suffix(&gtk::Label::new(Some(&mount.fstype)));
+                    self.mountlist.append(&row);
+                }
+
+                if self.problems.is_empty() {
+                    let bootdisk = self
+                        .mounts
+                        .iter()
+                        .find(|m| m.mountpoint == "/")
+                        .and_then(|m| parent_disk(system.as_ref(), &m.device));
+                    let _ = sender.output(AppMsg::SetPartitionConfig(Some(
+                        PartitionSchema::Premounted { bootdisk },
+                    )));
+                    let _ = sender.output(AppMsg::SetCanGoForward(true));
+                } else {
+                    let _ = sender.output(AppMsg::SetPartitionConfig(None));
+                    let _ = sender.output(AppMsg::SetCanGoForward(false));
+                }
+            }
+        }
+    }
+}
+
+fn describe_problem(problem: &MountProblem) -> String {
+    match problem {
+        MountProblem::NoRoot => {
+            // Translators: Do NOT translate the '{}'
+            i18n_f("Nothing is mounted at {}", &[TARGET_ROOT])
+        }
+        MountProblem::NoEsp(esp) => {
+            // Translators: Do NOT translate the '{}'
+            i18n_f(
+                "No EFI system partition is mounted at {}",
+                &[&format!("{}{}", TARGET_ROOT, esp)],
+            )
+        }
+        MountProblem::EspNotFat(fstype) => {
+            // Translators: Do NOT translate the '{}'
+            i18n_f("The EFI system partition must be FAT formatted, not {}", &[fstype])
+        }
+    }
+}
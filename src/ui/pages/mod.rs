@@ -1,7 +1,12 @@
+pub mod bootloader;
+pub mod desktop;
 pub mod error;
+pub mod form;
 pub mod install;
 pub mod keyboard;
 pub mod list;
+pub mod manual;
+pub mod nvidia;
 pub mod partitions;
 pub mod summary;
 pub mod timezone;
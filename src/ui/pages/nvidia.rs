@@ -0,0 +1,173 @@
+use crate::ui::window::AppMsg;
+use adw::prelude::*;
+use gettextrs::gettext;
+use icicle_core::nvidia::{NvidiaDriver, PrimeMode};
+use log::trace;
+use relm4::*;
+
+#[tracker::track]
+pub struct NvidiaModel {
+    driver: NvidiaDriver,
+    mode: PrimeMode,
+}
+
+#[derive(Debug)]
+pub enum NvidiaMsg {
+    CheckSelected,
+    SetDriver(NvidiaDriver),
+    SetMode(PrimeMode),
+}
+
+#[relm4::component(pub)]
+impl SimpleComponent for NvidiaModel {
+    type Init = ();
+    type Input = NvidiaMsg;
+    type Output = AppMsg;
+
+    view! {
+        gtk::ScrolledWindow {
+            adw::Clamp {
+                gtk::Box {
+                    set_hexpand: true,
+                    set_vexpand: true,
+                    set_valign: gtk::Align::Center,
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 20,
+                    set_margin_all: 20,
+                    gtk::Label {
+                        add_css_class: "title-1",
+                        #[watch]
+                        set_label: &gettext("NVIDIA Graphics"),
+                    },
+                    gtk::Label {
+                        set_wrap: true,
+                        set_justify: gtk::Justification::Center,
+                        #[watch]
+                        set_label: &gettext("This computer has an NVIDIA graphics card next to its integrated graphics. Choose how it should be used."),
+                    },
+                    adw::PreferencesGroup {
+                        #[watch]
+                        set_title: &gettext("Driver"),
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Proprietary"),
+                            #[watch]
+                            set_subtitle: &gettext("Supports every NVIDIA graphics card"),
+                            set_activatable: true,
+                            connect_activated[proprietarybtn] => move |_| {
+                                proprietarybtn.activate();
+                            },
+                            #[name(proprietarybtn)]
+                            add_prefix = &gtk::CheckButton {
+                                set_active: model.driver == NvidiaDriver::Proprietary,
+                                connect_toggled[sender] => move |btn| {
+                                    if btn.is_active() {
+                                        sender.input(NvidiaMsg::SetDriver(NvidiaDriver::Proprietary));
+                                    }
+                                },
+                            },
+                        },
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Open"),
+                            #[watch]
+                            set_subtitle: &gettext("Open source kernel modules, for GeForce 16 and RTX series or newer cards"),
+                            set_activatable: true,
+                            connect_activated[openbtn] => move |_| {
+                                openbtn.activate();
+                            },
+                            #[name(openbtn)]
+                            add_prefix = &gtk::CheckButton {
+                                set_group: Some(&proprietarybtn),
+                                set_active: model.driver == NvidiaDriver::Open,
+                                connect_toggled[sender] => move |btn| {
+                                    if btn.is_active() {
+                                        sender.input(NvidiaMsg::SetDriver(NvidiaDriver::Open));
+                                    }
+                                },
+                            },
+                        },
+                    },
+                    adw::PreferencesGroup {
+                        #[watch]
+                        set_title: &gettext("Mode"),
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Offload"),
+                            #[watch]
+                            set_subtitle: &gettext("Use the NVIDIA card only for applications started with \"nvidia-offload\", saving power"),
+                            set_activatable: true,
+                            connect_activated[offloadbtn] => move |_| {
+                                offloadbtn.activate();
+                            },
+                            #[name(offloadbtn)]
+                            add_prefix = &gtk::CheckButton {
+                                set_active: model.mode == PrimeMode::Offload,
+                                connect_toggled[sender] => move |btn| {
+                                    if btn.is_active() {
+                                        sender.input(NvidiaMsg::SetMode(PrimeMode::Offload));
+                                    }
+                                },
+                            },
+                        },
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Sync"),
+                            #[watch]
+                            set_subtitle: &gettext("Always use the NVIDIA card, for better performance at the cost of battery life"),
+                            set_activatable: true,
+                            connect_activated[syncbtn] => move |_| {
+                                syncbtn.activate();
+                            },
+                            #[name(syncbtn)]
+                            add_prefix = &gtk::CheckButton {
+                                set_group: Some(&offloadbtn),
+                                set_active: model.mode == PrimeMode::Sync,
+                                connect_toggled[sender] => move |btn| {
+                                    if btn.is_active() {
+                                        sender.input(NvidiaMsg::SetMode(PrimeMode::Sync));
+                                    }
+                                },
+                            },
+                        },
+                    },
+                }
+            }
+        }
+    }
+
+    fn init(
+        _init: Self::Init,
+        root: &Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let model = NvidiaModel {
+            driver: NvidiaDriver::default(),
+            mode: PrimeMode::default(),
+            tracker: 0,
+        };
+
+        let widgets = view_output!();
+
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
+        self.reset();
+        match msg {
+            NvidiaMsg::CheckSelected => {
+                let _ = sender.output(AppMsg::SetCanGoForward(true));
+            }
+            NvidiaMsg::SetDriver(driver) => {
+                trace!("NvidiaMsg::SetDriver {:?}", driver);
+                self.set_driver(driver);
+                let _ = sender.output(AppMsg::SetNvidiaConfig(self.driver, self.mode));
+            }
+            NvidiaMsg::SetMode(mode) => {
+                trace!("NvidiaMsg::SetMode {:?}", mode);
+                self.set_mode(mode);
+                let _ = sender.output(AppMsg::SetNvidiaConfig(self.driver, self.mode));
+            }
+        }
+    }
+}
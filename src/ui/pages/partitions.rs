@@ -1,10 +1,21 @@
-use crate::{config::LIBEXECDIR, ui::window::AppMsg, utils::i18n::i18n_f};
+use crate::{
+    ui::window::AppMsg,
+    utils::{
+        helper::{run_helper, system},
+        i18n::i18n_f,
+    },
+};
 use adw::prelude::*;
 use gettextrs::gettext;
+use icicle_core::{
+    bootloader::{check_bios_disk, BiosDiskProblem, BootloaderConfig, Windows},
+    is_efi,
+    protocol::{CustomPartition, Disk, DiskLayout, PartitionSchema, Response, RootFilesystem},
+    system::Cmd,
+};
 use log::{debug, error, info, trace};
 use relm4::{factory::*, *};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, process::Command};
+use std::collections::HashMap;
 
 pub struct PartitionModel {
     disks: FactoryVecDeque<WholeDisk>,
@@ -13,6 +24,26 @@ pub struct PartitionModel {
     diskgroupbtn: gtk::CheckButton,
     schema: Option<PartitionSchema>,
     efi: bool,
+    /// Mountpoint of the EFI system partition
+    esp: String,
+    /// Every disk with its partitions, as found by the helper
+    diskinfo: Vec<Disk>,
+    /// Disk GRUB is installed to on BIOS machines, the disk holding `/` when `None`
+    bootdisk: Option<String>,
+    bootdiskrow: adw::ComboRow,
+    /// Whether a kept Windows installation is added to the boot menu
+    windowsbootentry: bool,
+    /// Whether the hardware clock is kept in local time for a kept Windows installation
+    localtime: bool,
+    /// Whether an installation to a removable disk boots on other machines too
+    portable: bool,
+    /// Filesystem of the root partition when a whole disk is chosen
+    filesystem: RootFilesystem,
+    /// Whether a whole disk is encrypted, with LVM inside the LUKS container
+    encrypt: bool,
+    passphrase: Option<String>,
+    confirm_passphrase: Option<String>,
+    confirm_passphrase_row: adw::PasswordEntryRow,
 }
 
 #[derive(Debug)]
@@ -24,6 +55,16 @@ pub enum PartitionMsg {
     RemoveFormatPartition(String),
     RemoveMountPartition(String),
     AddPartition(String, CustomPartition),
+    SetEsp(String),
+    SetBootDisk(Option<String>),
+    SetWindowsBootEntry(bool),
+    SetLocalTime(bool),
+    SetPortable(bool),
+    SetFilesystem(RootFilesystem),
+    SetEncrypt(bool),
+    PassphraseChanged(String),
+    ConfirmPassphraseChanged(String),
+    SetPassphraseStyle,
     CheckSelected,
     Refresh,
 }
@@ -36,19 +77,6 @@ pub enum PartitionMethod {
     Advanced,
 }
 
-#[derive(Serialize, Debug, Clone)]
-pub enum PartitionSchema {
-    FullDisk(String),
-    Custom(HashMap<String, CustomPartition>),
-}
-
-#[derive(Serialize, Debug, Clone)]
-pub struct CustomPartition {
-    pub format: Option<String>,
-    pub mountpoint: Option<String>,
-    pub device: String,
-}
-
 #[relm4::component(pub)]
 impl SimpleComponent for PartitionModel {
     type Input = PartitionMsg;
@@ -92,6 +120,72 @@ impl SimpleComponent for PartitionModel {
                                 set_hexpand: true,
                                 set_selection_mode: gtk::SelectionMode::None,
                             },
+                            gtk::Label {
+                                add_css_class: "warning",
+                                set_wrap: true,
+                                set_justify: gtk::Justification::Center,
+                                #[watch]
+                                set_visible: !model.erased_systems().is_empty(),
+                                #[watch]
+                                // Translators: Do NOT translate the '{}'
+                                set_label: &i18n_f("{} will be erased along with everything else on this disk", &[&model.erased_systems().join(", ")]),
+                            },
+                            adw::PreferencesGroup {
+                                adw::ActionRow {
+                                    set_title: "ZFS",
+                                    #[watch]
+                                    set_subtitle: &gettext("Create a ZFS pool with datasets for the system, the Nix store and home directories, instead of an ext4 partition"),
+                                    set_activatable_widget: Some(&zfsswitch),
+                                    #[name(zfsswitch)]
+                                    add_suffix = &gtk::Switch {
+                                        set_valign: gtk::Align::Center,
+                                        #[watch]
+                                        set_active: model.filesystem == RootFilesystem::Zfs,
+                                        connect_active_notify[sender] => move |switch| {
+                                            sender.input(PartitionMsg::SetFilesystem(if switch.is_active() {
+                                                RootFilesystem::Zfs
+                                            } else {
+                                                RootFilesystem::Ext4
+                                            }));
+                                        },
+                                    },
+                                },
+                                adw::ActionRow {
+                                    #[watch]
+                                    set_title: &gettext("Encrypt the disk"),
+                                    #[watch]
+                                    set_subtitle: &gettext("Create an LVM volume group with root and swap volumes inside a LUKS container, unlocked with a passphrase at boot"),
+                                    set_activatable_widget: Some(&encryptswitch),
+                                    #[name(encryptswitch)]
+                                    add_suffix = &gtk::Switch {
+                                        set_valign: gtk::Align::Center,
+                                        #[watch]
+                                        set_active: model.encrypt,
+                                        connect_active_notify[sender] => move |switch| {
+                                            sender.input(PartitionMsg::SetEncrypt(switch.is_active()));
+                                        },
+                                    },
+                                },
+                                adw::PasswordEntryRow {
+                                    #[watch]
+                                    set_visible: model.encrypt,
+                                    #[watch]
+                                    set_title: &gettext("Passphrase"),
+                                    connect_changed[sender] => move |entry| {
+                                        sender.input(PartitionMsg::PassphraseChanged(entry.text().to_string()));
+                                    }
+                                },
+                                #[local_ref]
+                                confirm_passphrase_row -> adw::PasswordEntryRow {
+                                    #[watch]
+                                    set_visible: model.encrypt,
+                                    #[watch]
+                                    set_title: &gettext("Confirm passphrase"),
+                                    connect_changed[sender] => move |entry| {
+                                        sender.input(PartitionMsg::ConfirmPassphraseChanged(entry.text().to_string()));
+                                    }
+                                },
+                            },
                             gtk::Box {
                                 set_orientation: gtk::Orientation::Horizontal,
                                 set_spacing: 20,
@@ -130,59 +224,15 @@ impl SimpleComponent for PartitionModel {
 
                             gtk::Button {
                                 #[watch]
-                                set_css_classes: if let Some(PartitionSchema::Custom(schema)) = &model.schema {
-                                    let mut root = false;
-                                    let mut bootefi = !model.efi;
-                                    for v in schema.values() {
-                                        if let Some(file) = &v.mountpoint {
-                                            if file == "/" {
-                                                root = true;
-                                            }
-                                            if file == "/boot/efi" {
-                                                bootefi = true;
-                                            }
-                                        }
-                                    }
-                                    match (root, bootefi) {
-                                        (true, true) => &["pill", "success"],
-                                        (true, false) => &["pill", "error"],
-                                        (false, true) => &["pill", "error"],
-                                        (false, false) => &["pill", "error"],
-                                    }
+                                set_css_classes: if model.problem().is_none() {
+                                    &["pill", "success"]
                                 } else {
                                     &["pill", "error"]
                                 },
                                 set_can_target: false,
                                 gtk::Label {
                                     #[watch]
-                                    set_markup: &if let Some(PartitionSchema::Custom(schema)) = &model.schema {
-                                        let mut root = false;
-                                        let mut bootefi = !model.efi;
-                                        for v in schema.values() {
-                                            if let Some(file) = &v.mountpoint {
-                                                if file == "/" {
-                                                    root = true;
-                                                }
-                                                if file == "/boot/efi" {
-                                                    bootefi = true;
-                                                }
-                                            }
-                                        }
-                                        match (root, bootefi) {
-                                            (true, true) => gettext("Ready to install!"),
-                                            // Translators: Do NOT translate anything between the <tt> tags
-                                            (true, false) => gettext("Missing <tt>/boot/efi</tt> partition"),
-                                            // Translators: Do NOT translate anything between the <tt> tags
-                                            (false, true) => gettext("Missing <tt>/</tt> partition"),
-                                            // Translators: Do NOT translate anything between the <tt> tags
-                                            (false, false) => gettext("Missing <tt>/</tt> and <tt>/boot/efi</tt> partitions"),
-                                        }
-                                    } else if model.efi {
-                                        // Translators: Do NOT translate anything between the <tt> tags
-                                        gettext("Missing <tt>/</tt> and <tt>/boot/efi</tt> partitions")
-                                    } else {
-                                        gettext("Missing <tt>/</tt> partition")
-                                    }
+                                    set_markup: &model.problem().unwrap_or_else(|| gettext("Ready to install!")),
                                 }
                             },
 
@@ -192,6 +242,24 @@ impl SimpleComponent for PartitionModel {
                                 set_orientation: gtk::Orientation::Vertical,
                                 set_spacing: 20,
                             },
+                            adw::PreferencesGroup {
+                                set_visible: !model.efi,
+                                #[local_ref]
+                                bootdiskrow -> adw::ComboRow {
+                                    #[watch]
+                                    set_title: &gettext("Install bootloader to"),
+                                    connect_selected_notify[sender] => move |row| {
+                                        let bootdisk = if row.selected() == 0 {
+                                            None
+                                        } else {
+                                            row.selected_item()
+                                                .and_then(|item| item.downcast::<gtk::StringObject>().ok())
+                                                .map(|item| item.string().to_string())
+                                        };
+                                        sender.input(PartitionMsg::SetBootDisk(bootdisk));
+                                    }
+                                }
+                            },
                             gtk::Button {
                                 add_css_class: "pill",
                                 adw::ButtonContent {
@@ -201,8 +269,7 @@ impl SimpleComponent for PartitionModel {
                                 },
                                 set_halign: gtk::Align::Center,
                                 connect_clicked => move |_| {
-                                    let cmd = Command::new("gparted").spawn();
-                                    if let Err(e) = cmd {
+                                    if let Err(e) = system().spawn(&Cmd::new("gparted")) {
                                         error!("Failed to launch GParted: {}", e);
                                     }
                                 }
@@ -235,7 +302,65 @@ impl SimpleComponent for PartitionModel {
                             }
                         }
                     },
-
+                    adw::PreferencesGroup {
+                        #[watch]
+                        set_visible: model.removable_target(),
+                        #[watch]
+                        set_title: &gettext("Removable Disk"),
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Portable install"),
+                            #[watch]
+                            set_subtitle: &gettext("Boots on other computers too, and leaves the boot entries of this computer alone"),
+                            set_activatable_widget: Some(&portableswitch),
+                            #[name(portableswitch)]
+                            add_suffix = &gtk::Switch {
+                                set_valign: gtk::Align::Center,
+                                set_active: true,
+                                connect_active_notify[sender] => move |switch| {
+                                    sender.input(PartitionMsg::SetPortable(switch.is_active()));
+                                },
+                            },
+                        },
+                    },
+                    adw::PreferencesGroup {
+                        #[watch]
+                        set_visible: model.windows_kept(),
+                        #[watch]
+                        set_title: &gettext("Windows"),
+                        #[watch]
+                        set_description: Some(&gettext("Windows is installed on this computer and will be kept")),
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Add Windows to the boot menu"),
+                            #[watch]
+                            set_subtitle: &gettext("GRUB finds Windows with os-prober, systemd-boot lists it when it shares the EFI system partition"),
+                            set_activatable_widget: Some(&bootentryswitch),
+                            #[name(bootentryswitch)]
+                            add_suffix = &gtk::Switch {
+                                set_valign: gtk::Align::Center,
+                                set_active: true,
+                                connect_active_notify[sender] => move |switch| {
+                                    sender.input(PartitionMsg::SetWindowsBootEntry(switch.is_active()));
+                                },
+                            },
+                        },
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Keep the hardware clock in local time"),
+                            #[watch]
+                            set_subtitle: &gettext("Windows expects the hardware clock in local time, otherwise its time is off after running NixOS"),
+                            set_activatable_widget: Some(&localtimeswitch),
+                            #[name(localtimeswitch)]
+                            add_suffix = &gtk::Switch {
+                                set_valign: gtk::Align::Center,
+                                set_active: true,
+                                connect_active_notify[sender] => move |switch| {
+                                    sender.input(PartitionMsg::SetLocalTime(switch.is_active()));
+                                },
+                            },
+                        },
+                    },
                 }
             }
         }
@@ -255,13 +380,27 @@ impl SimpleComponent for PartitionModel {
             ),
             diskgroupbtn: gtk::CheckButton::new(),
             schema: None,
-            efi: distinst_disks::Bootloader::detect() == distinst_disks::Bootloader::Efi,
+            efi: is_efi(system().as_ref()),
+            esp: BootloaderConfig::default().esp.mountpoint().to_string(),
+            diskinfo: vec![],
+            bootdisk: None,
+            bootdiskrow: adw::ComboRow::new(),
+            windowsbootentry: true,
+            localtime: true,
+            portable: true,
+            filesystem: RootFilesystem::default(),
+            encrypt: false,
+            passphrase: None,
+            confirm_passphrase: None,
+            confirm_passphrase_row: adw::PasswordEntryRow::new(),
         };
 
         sender.input(PartitionMsg::Refresh);
 
         let diskbox = model.disks.widget();
         let partitionbox = model.partition_groups.widget();
+        let bootdiskrow = &model.bootdiskrow;
+        let confirm_passphrase_row = &model.confirm_passphrase_row;
 
         let widgets = view_output!();
         widgets.liststack.set_vhomogeneous(false);
@@ -278,73 +417,57 @@ impl SimpleComponent for PartitionModel {
                 disks_guard.clear();
                 partition_groups_guard.clear();
 
-                let out = Command::new("pkexec")
-                    .arg(&format!("{}/icicle-helper", LIBEXECDIR))
-                    .arg("get-partitions")
-                    .output();
-
-                match out {
-                    Ok(out) => {
-                        let output = String::from_utf8(out.stdout).unwrap();
-                        let stderr = String::from_utf8(out.stderr).unwrap();
-                        #[derive(Deserialize, Debug)]
-                        struct InputDisk {
-                            name: String,
-                            size: u64,
-                            partitions: Vec<InputPartition>,
-                        }
-
-                        #[derive(Deserialize, Debug)]
-                        struct InputPartition {
-                            name: String,
-                            #[serde(rename = "format")]
-                            _format: String,
-                            size: u64,
-                        }
-                        let disks: serde_json::Result<Vec<InputDisk>> =
-                            serde_json::from_str(&output);
-                        if let Ok(disks) = disks {
-                            debug!("Got disks: {:?}", disks);
+                match run_helper(&["get-partitions"], None) {
+                    Ok(Response::Disks(disks)) => {
+                        debug!("Got disks: {:?}", disks);
+                        self.diskinfo = disks.clone();
 
-                            for disk in disks {
+                        for disk in disks {
+                            // Volume groups only offer their logical volumes in custom mode
+                            if !disk.volume_group {
                                 disks_guard.push_back(WholeDisk {
                                     name: disk.name.to_string(),
                                     size: disk.size,
                                     group: self.diskgroupbtn.clone(),
+                                    removable: disk.removable,
+                                    os: disk
+                                        .partitions
+                                        .iter()
+                                        .filter_map(|part| part.os.clone())
+                                        .collect(),
                                 });
+                            }
 
-                                let mut part_factoryvec: FactoryVecDeque<Partition> =
-                                    FactoryVecDeque::new(
-                                        gtk::ListBox::new(),
-                                        sender.input_sender(),
-                                    );
-                                let mut part_guard = part_factoryvec.guard();
-
-                                for part in disk.partitions {
-                                    info!(
-                                        "Partition: {:?} length {}",
-                                        part.name,
-                                        size::Size::from_bytes(part.size)
-                                    );
-                                    part_guard.push_back(PartitionInit {
-                                        name: part.name,
-                                        size: part.size,
-                                        mountrow: adw::ComboRow::new(),
-                                        device: disk.name.to_string(),
-                                    });
-                                }
-
-                                part_guard.drop();
+                            let mut part_factoryvec: FactoryVecDeque<Partition> =
+                                FactoryVecDeque::new(gtk::ListBox::new(), sender.input_sender());
+                            let mut part_guard = part_factoryvec.guard();
 
-                                partition_groups_guard.push_back(PartitionGroup {
-                                    name: disk.name.to_string(),
-                                    partitions: part_factoryvec,
+                            for part in disk.partitions {
+                                info!(
+                                    "Partition: {:?} length {}",
+                                    part.name,
+                                    size::Size::from_bytes(part.size)
+                                );
+                                part_guard.push_back(PartitionInit {
+                                    name: part.name,
+                                    size: part.size,
+                                    mountrow: adw::ComboRow::new(),
+                                    device: disk.name.to_string(),
+                                    os: part.os,
                                 });
                             }
-                        } else {
-                            error!("Failed to parse partitions: {} : {}", output, stderr);
+
+                            part_guard.drop();
+
+                            partition_groups_guard.push_back(PartitionGroup {
+                                name: disk.name.to_string(),
+                                partitions: part_factoryvec,
+                            });
                         }
                     }
+                    Ok(response) => {
+                        error!("Unexpected response to get-partitions: {:?}", response);
+                    }
                     Err(e) => {
                         error!("Failed to get partitions: {}", e);
                     }
@@ -353,6 +476,19 @@ impl SimpleComponent for PartitionModel {
                 disks_guard.drop();
                 partition_groups_guard.drop();
                 self.schema = None;
+                self.bootdisk = None;
+
+                let rootdisk = gettext("Disk holding the root partition");
+                let mut bootdisks = vec![rootdisk.as_str()];
+                bootdisks.extend(
+                    self.diskinfo
+                        .iter()
+                        .filter(|disk| !disk.volume_group)
+                        .map(|disk| disk.name.as_str()),
+                );
+                self.bootdiskrow
+                    .set_model(Some(&gtk::StringList::new(&bootdisks)));
+                self.bootdiskrow.set_selected(0);
             }
             PartitionMsg::SetMethod(method) => {
                 self.method = method;
@@ -493,30 +629,110 @@ impl SimpleComponent for PartitionModel {
                 sender.input(PartitionMsg::CheckSelected);
                 trace!("Schema: {:?}", self.schema);
             }
+            PartitionMsg::SetBootDisk(bootdisk) => {
+                trace!("SetBootDisk {:?}", bootdisk);
+                self.bootdisk = bootdisk;
+                sender.input(PartitionMsg::CheckSelected);
+            }
+            PartitionMsg::SetWindowsBootEntry(bootentry) => {
+                trace!("SetWindowsBootEntry {}", bootentry);
+                self.windowsbootentry = bootentry;
+                sender.input(PartitionMsg::CheckSelected);
+            }
+            PartitionMsg::SetLocalTime(localtime) => {
+                trace!("SetLocalTime {}", localtime);
+                self.localtime = localtime;
+                sender.input(PartitionMsg::CheckSelected);
+            }
+            PartitionMsg::SetPortable(portable) => {
+                trace!("SetPortable {}", portable);
+                self.portable = portable;
+                sender.input(PartitionMsg::CheckSelected);
+            }
+            PartitionMsg::SetFilesystem(filesystem) => {
+                trace!("SetFilesystem {:?}", filesystem);
+                self.filesystem = filesystem;
+                // The LVM on LUKS layout has no place for a ZFS pool
+                if filesystem == RootFilesystem::Zfs {
+                    self.encrypt = false;
+                }
+                sender.input(PartitionMsg::CheckSelected);
+            }
+            PartitionMsg::SetEncrypt(encrypt) => {
+                trace!("SetEncrypt {}", encrypt);
+                self.encrypt = encrypt;
+                if encrypt {
+                    self.filesystem = RootFilesystem::Ext4;
+                }
+                sender.input(PartitionMsg::CheckSelected);
+            }
+            PartitionMsg::PassphraseChanged(passphrase) => {
+                self.passphrase = if passphrase.is_empty() {
+                    None
+                } else {
+                    Some(passphrase)
+                };
+                sender.input(PartitionMsg::SetPassphraseStyle);
+            }
+            PartitionMsg::ConfirmPassphraseChanged(confirm_passphrase) => {
+                self.confirm_passphrase = if confirm_passphrase.is_empty() {
+                    None
+                } else {
+                    Some(confirm_passphrase)
+                };
+                sender.input(PartitionMsg::SetPassphraseStyle);
+            }
+            PartitionMsg::SetPassphraseStyle => {
+                if self.passphrase == self.confirm_passphrase {
+                    if self.passphrase.is_some() {
+                        self.confirm_passphrase_row.add_css_class("success");
+                        self.confirm_passphrase_row.remove_css_class("error");
+                    } else {
+                        self.confirm_passphrase_row.remove_css_class("success");
+                        self.confirm_passphrase_row.remove_css_class("error");
+                    }
+                } else {
+                    self.confirm_passphrase_row.add_css_class("error");
+                    self.confirm_passphrase_row.remove_css_class("success");
+                }
+                sender.input(PartitionMsg::CheckSelected);
+            }
+            PartitionMsg::SetEsp(esp) => {
+                trace!("SetEsp {}", esp);
+                self.esp = esp;
+            }
             PartitionMsg::CheckSelected => {
                 trace!("PartitionMsg::CheckSelected: {:?}", self.schema);
+                let _ = sender.output(AppMsg::SetWindowsConfig(self.windows_kept().then_some(
+                    Windows {
+                        boot_entry: self.windowsbootentry,
+                        local_time: self.localtime,
+                    },
+                )));
+                let _ = sender.output(AppMsg::SetPortableConfig(
+                    self.portable && self.removable_target(),
+                ));
                 match &self.schema {
                     Some(PartitionSchema::FullDisk(_disk)) => {
-                        let _ = sender.output(AppMsg::SetCanGoForward(true));
-                        let _ = sender.output(AppMsg::SetPartitionConfig(self.schema.clone()));
-                    }
-                    Some(PartitionSchema::Custom(schema)) => {
-                        let mut root = false;
-                        let mut bootefi = false;
-                        for part in schema.values() {
-                            if part.mountpoint == Some("/".to_string()) {
-                                root = true;
-                            }
-                            if part.mountpoint == Some("/boot/efi".to_string()) || !self.efi {
-                                bootefi = true;
-                            }
+                        let layout = self.layout();
+                        let _ = sender.output(AppMsg::SetCanGoForward(layout.is_some()));
+                        if let Some(layout) = layout {
+                            let _ = sender.output(AppMsg::SetPartitionConfig(self.schema.clone()));
+                            let _ = sender.output(AppMsg::SetBootDiskConfig(None));
+                            let _ = sender.output(AppMsg::SetFilesystemConfig(self.filesystem));
+                            let _ = sender.output(AppMsg::SetLayoutConfig(layout));
                         }
-                        let _ = sender.output(AppMsg::SetCanGoForward(root && bootefi));
-                        if root && bootefi {
+                    }
+                    Some(PartitionSchema::Custom(_schema)) => {
+                        let ready = self.problem().is_none();
+                        let _ = sender.output(AppMsg::SetCanGoForward(ready));
+                        if ready {
                             let _ = sender.output(AppMsg::SetPartitionConfig(self.schema.clone()));
+                            let _ = sender.output(AppMsg::SetBootDiskConfig(self.bootdisk.clone()));
+                            let _ = sender.output(AppMsg::SetLayoutConfig(DiskLayout::Plain));
                         }
                     }
-                    None => {
+                    Some(PartitionSchema::Premounted { .. }) | None => {
                         let _ = sender.output(AppMsg::SetCanGoForward(false));
                     }
                 }
@@ -525,11 +741,141 @@ impl SimpleComponent for PartitionModel {
     }
 }
 
+impl PartitionModel {
+    /// Operating systems on the disk that would be partitioned as a whole.
+    fn erased_systems(&self) -> Vec<String> {
+        match &self.schema {
+            Some(PartitionSchema::FullDisk(name)) => self
+                .diskinfo
+                .iter()
+                .filter(|disk| &disk.name == name)
+                .flat_map(|disk| disk.partitions.iter().filter_map(|part| part.os.clone()))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Whether Windows is found on a disk that is not partitioned as a whole.
+    fn windows_kept(&self) -> bool {
+        let erased = match &self.schema {
+            Some(PartitionSchema::FullDisk(name)) => Some(name),
+            _ => None,
+        };
+        self.diskinfo
+            .iter()
+            .filter(|disk| erased != Some(&disk.name))
+            .any(|disk| disk.partitions.iter().any(|part| part.has_windows()))
+    }
+
+    /// Layout of the whole disk, `None` while the passphrase of an encrypted disk is
+    /// missing or not confirmed.
+    fn layout(&self) -> Option<DiskLayout> {
+        if !self.encrypt {
+            return Some(DiskLayout::Plain);
+        }
+        match &self.passphrase {
+            Some(passphrase) if self.passphrase == self.confirm_passphrase => {
+                Some(DiskLayout::LvmOnLuks {
+                    passphrase: passphrase.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the disk holding the root partition is removable.
+    fn removable_target(&self) -> bool {
+        let target = match &self.schema {
+            Some(PartitionSchema::FullDisk(name)) => Some(name.to_string()),
+            Some(PartitionSchema::Custom(schema)) => schema
+                .values()
+                .find(|part| part.mountpoint.as_deref() == Some("/"))
+                .map(|part| part.device.to_string()),
+            _ => None,
+        };
+        self.diskinfo
+            .iter()
+            .any(|disk| Some(&disk.name) == target.as_ref() && disk.removable)
+    }
+
+    /// Why the selected partitions can not be installed to, as markup.
+    fn problem(&self) -> Option<String> {
+        let schema = match &self.schema {
+            Some(PartitionSchema::Custom(schema)) => schema,
+            _ if self.efi => {
+                // Translators: Do NOT translate anything between the <tt> tags, or the '{}'
+                return Some(i18n_f(
+                    "Missing <tt>/</tt> and <tt>{}</tt> partitions",
+                    &[&self.esp],
+                ));
+            }
+            // Translators: Do NOT translate anything between the <tt> tags
+            _ => return Some(gettext("Missing <tt>/</tt> partition")),
+        };
+        let mounted = |mountpoint: &str| {
+            schema
+                .values()
+                .any(|part| part.mountpoint.as_deref() == Some(mountpoint))
+        };
+        if schema.values().any(|part| {
+            part.format.as_deref() == Some("zfs") && part.mountpoint.as_deref() != Some("/")
+        }) {
+            // Translators: Do NOT translate anything between the <tt> tags
+            return Some(gettext("ZFS partitions must be mounted at <tt>/</tt>"));
+        }
+        match (mounted("/"), !self.efi || mounted(&self.esp)) {
+            (true, true) => {}
+            // Translators: Do NOT translate anything between the <tt> tags, or the '{}'
+            (true, false) => return Some(i18n_f("Missing <tt>{}</tt> partition", &[&self.esp])),
+            // Translators: Do NOT translate anything between the <tt> tags
+            (false, true) => return Some(gettext("Missing <tt>/</tt> partition")),
+            (false, false) => {
+                // Translators: Do NOT translate anything between the <tt> tags, or the '{}'
+                return Some(i18n_f(
+                    "Missing <tt>/</tt> and <tt>{}</tt> partitions",
+                    &[&self.esp],
+                ));
+            }
+        }
+        if self.efi {
+            return None;
+        }
+
+        let bootdisk = self.bootdisk.clone().or_else(|| {
+            schema
+                .values()
+                .find(|part| part.mountpoint.as_deref() == Some("/"))
+                .map(|part| part.device.to_string())
+        })?;
+        let disk = self.diskinfo.iter().find(|disk| disk.name == bootdisk)?;
+        check_bios_disk(disk).map(|problem| match problem {
+            BiosDiskProblem::NoTable => {
+                // Translators: Do NOT translate anything between the <tt> tags, or the '{}'
+                i18n_f("<tt>{}</tt> has no partition table for the bootloader", &[&bootdisk])
+            }
+            BiosDiskProblem::NoBiosBootPartition => {
+                // Translators: Do NOT translate anything between the <tt> tags, or the '{}'
+                i18n_f("<tt>{}</tt> needs a BIOS boot partition for the bootloader", &[&bootdisk])
+            }
+            BiosDiskProblem::NoMbrGap => {
+                // Translators: Do NOT translate anything between the <tt> tags, or the '{}'
+                i18n_f(
+                    "<tt>{}</tt> needs 1 MiB of free space before its first partition for the bootloader",
+                    &[&bootdisk],
+                )
+            }
+        })
+    }
+}
+
 #[derive(Default, Debug, PartialEq, Eq, Clone)]
 pub struct WholeDisk {
     name: String,
     size: u64,
     group: gtk::CheckButton,
+    removable: bool,
+    /// Operating systems found on the disk
+    os: Vec<String>,
 }
 
 #[relm4::factory(pub)]
@@ -550,6 +896,16 @@ impl FactoryComponent for WholeDisk {
             set_subtitle: &if self.size > 21_474_836_480  { size::Size::from_bytes(self.size).to_string() } else { i18n_f("{} (20 GB minimum needed)", &[&size::Size::from_bytes(self.size).to_string()]) },
             set_activatable: true,
             set_sensitive: self.size > 21_474_836_480, // 20GB
+            add_suffix = &gtk::Label {
+                add_css_class: "dim-label",
+                set_visible: !self.os.is_empty(),
+                set_label: &self.os.join(", "),
+            },
+            add_suffix = &gtk::Image {
+                add_css_class: "dim-label",
+                set_visible: self.removable,
+                set_icon_name: Some("media-removable-symbolic"),
+            },
             #[name(checkbtn)]
             add_suffix = &gtk::CheckButton {
                 set_group: Some(&self.group),
@@ -577,6 +933,8 @@ pub struct Partition {
     size: u64,
     mountrow: adw::ComboRow,
     device: String,
+    /// Operating system booted from the partition
+    os: Option<String>,
     swap: bool,
     donotmount: String,
     donotformat: String,
@@ -588,6 +946,7 @@ pub struct PartitionInit {
     size: u64,
     mountrow: adw::ComboRow,
     device: String,
+    os: Option<String>,
 }
 
 #[derive(Debug)]
@@ -608,12 +967,15 @@ impl FactoryComponent for Partition {
     view! {
         adw::ExpanderRow {
             set_title: &self.name,
-            set_subtitle: &size::Size::from_bytes(self.size).to_string(),
+            set_subtitle: &match &self.os {
+                Some(os) => format!("{} – {}", size::Size::from_bytes(self.size), os),
+                None => size::Size::from_bytes(self.size).to_string(),
+            },
             add_row = &adw::ComboRow {
                 #[watch]
                 set_title: &gettext("Format"),
                 // TODO: When switching language the "Leave as is" option does not update
-                set_model: Some(&gtk::StringList::new(&[&self.donotformat, "btrfs", "ext4", "ext3", "fat32", "ntfs", "xfs", "swap"])),
+                set_model: Some(&gtk::StringList::new(&[&self.donotformat, "btrfs", "ext4", "ext3", "fat32", "ntfs", "xfs", "zfs", "swap"])),
                 connect_selected_notify[sender, name = self.name.to_string(), device = self.device.to_string(), formatstring = self.donotformat.to_string()] => move |row| {
                     if let Some(item) = row.selected_item() {
                         if let Ok(item) = item.downcast::<gtk::StringObject>() {
@@ -665,6 +1027,7 @@ impl FactoryComponent for Partition {
             size: parent.size,
             mountrow: parent.mountrow,
             device: parent.device,
+            os: parent.os,
             swap: false,
             donotmount: gettext("Do not mount"),
             donotformat: gettext("Leave as is"),
@@ -1,8 +1,13 @@
-use super::partitions::{CustomPartition, PartitionSchema};
-use crate::ui::window::{AppMsg, UserConfig};
+use crate::{ui::window::AppMsg, utils::i18n::text_i18n};
 use adw::prelude::*;
 use gettextrs::gettext;
 use gnome_desktop::{self, XkbInfo, XkbInfoExt};
+use icicle_core::{
+    answers::UserConfig,
+    protocol::{CustomPartition, PartitionSchema, TARGET_ROOT},
+    quirks::Quirk,
+    text::Text,
+};
 use log::debug;
 use relm4::{factory::*, *};
 
@@ -14,12 +19,19 @@ pub struct SummaryModel {
     #[tracker::no_eq]
     partitionconfig: Option<PartitionSchema>,
     userconfig: Option<UserConfig>,
+    /// Name of the desktop to install
+    desktop: Option<Text>,
 
     prettylanguage: Option<String>,
     prettykeyboard: Option<String>,
 
     #[tracker::no_eq]
     partitions: FactoryVecDeque<Partition>,
+    /// Hardware quirks that apply to the machine
+    #[tracker::no_eq]
+    quirks: FactoryVecDeque<QuirkRow>,
+    /// Name of the machine the nixos-hardware module is for
+    hardwaremodule: Option<Text>,
 
     showhostname: bool,
 }
@@ -34,6 +46,10 @@ pub enum SummaryMsg {
         Box<Option<UserConfig>>,
     ),
     ShowHostname(bool),
+    SetDesktop(Option<Text>),
+    SetQuirks(Vec<Quirk>),
+    SetHardwareModule(Option<Text>),
+    SetUseHardwareModule(bool),
 }
 
 #[relm4::component(pub)]
@@ -108,6 +124,19 @@ impl SimpleComponent for SummaryModel {
                                 },
                             }
                         }
+                        Some(PartitionSchema::Premounted { .. }) => {
+                            adw::PreferencesGroup {
+                                #[watch]
+                                set_title: &gettext("Partitions"),
+                                adw::ActionRow {
+                                    set_activatable: false,
+                                    #[watch]
+                                    set_title: TARGET_ROOT,
+                                    #[watch]
+                                    set_subtitle: &gettext("Mounted manually"),
+                                },
+                            }
+                        }
                         Some(PartitionSchema::Custom(_partitions)) => {
                             #[local]
                             custompartitiongroup -> adw::PreferencesGroup {
@@ -126,6 +155,47 @@ impl SimpleComponent for SummaryModel {
                             }
                         }
                     },
+                    adw::PreferencesGroup {
+                        #[watch]
+                        set_title: &gettext("Desktop"),
+                        #[watch]
+                        set_visible: model.desktop.is_some(),
+                        adw::ActionRow {
+                            set_activatable: false,
+                            #[watch]
+                            set_title: &model.desktop.as_ref().map(text_i18n).unwrap_or_default(),
+                        },
+                    },
+                    #[local_ref]
+                    quirkgroup -> adw::PreferencesGroup {
+                        #[watch]
+                        set_title: &gettext("Hardware"),
+                        #[watch]
+                        set_description: Some(&gettext("Configuration added for the hardware of this computer")),
+                        #[watch]
+                        set_visible: !model.quirks.is_empty(),
+                    },
+                    adw::PreferencesGroup {
+                        #[watch]
+                        set_title: &gettext("Hardware Profile"),
+                        #[watch]
+                        set_visible: model.hardwaremodule.is_some(),
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &model.hardwaremodule.as_ref().map(text_i18n).unwrap_or_default(),
+                            #[watch]
+                            set_subtitle: &gettext("Settings for this computer from nixos-hardware"),
+                            set_activatable_widget: Some(&hardwareswitch),
+                            #[name(hardwareswitch)]
+                            add_suffix = &gtk::Switch {
+                                set_valign: gtk::Align::Center,
+                                set_active: true,
+                                connect_active_notify[sender] => move |switch| {
+                                    sender.input(SummaryMsg::SetUseHardwareModule(switch.is_active()));
+                                },
+                            },
+                        },
+                    },
                     adw::PreferencesGroup {
                         #[watch]
                         set_title: &gettext("User"),
@@ -202,20 +272,24 @@ impl SimpleComponent for SummaryModel {
             timezoneconfig: None,
             partitionconfig: None,
             userconfig: None,
+            desktop: None,
             prettylanguage: None,
             prettykeyboard: None,
             partitions: FactoryVecDeque::new(adw::PreferencesGroup::new(), sender.input_sender()),
+            quirks: FactoryVecDeque::new(adw::PreferencesGroup::new(), sender.input_sender()),
+            hardwaremodule: None,
             showhostname: false,
             tracker: 0,
         };
 
         let custompartitiongroup = model.partitions.widget().clone();
+        let quirkgroup = model.quirks.widget();
 
         let widgets = view_output!();
         ComponentParts { model, widgets }
     }
 
-    fn update(&mut self, msg: Self::Input, _sender: ComponentSender<Self>) {
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
         self.reset();
         match msg {
             SummaryMsg::SetConfig(
@@ -267,6 +341,23 @@ impl SimpleComponent for SummaryModel {
             SummaryMsg::ShowHostname(showhostname) => {
                 self.showhostname = showhostname;
             }
+            SummaryMsg::SetDesktop(desktop) => {
+                self.desktop = desktop;
+            }
+            SummaryMsg::SetQuirks(quirks) => {
+                let mut quirks_guard = self.quirks.guard();
+                quirks_guard.clear();
+                for quirk in quirks {
+                    quirks_guard.push_back(quirk);
+                }
+                quirks_guard.drop();
+            }
+            SummaryMsg::SetHardwareModule(hardwaremodule) => {
+                self.hardwaremodule = hardwaremodule;
+            }
+            SummaryMsg::SetUseHardwareModule(usehardwaremodule) => {
+                let _ = sender.output(AppMsg::SetUseHardwareModule(usehardwaremodule));
+            }
         }
     }
 }
@@ -348,3 +439,33 @@ impl FactoryComponent for Partition {
         }
     }
 }
+
+pub struct QuirkRow {
+    name: String,
+    description: String,
+}
+
+#[relm4::factory(pub)]
+impl FactoryComponent for QuirkRow {
+    type Init = Quirk;
+    type Input = ();
+    type Output = ();
+    type ParentWidget = adw::PreferencesGroup;
+    type ParentInput = SummaryMsg;
+    type CommandOutput = ();
+
+    view! {
+        adw::ActionRow {
+            set_activatable: false,
+            set_title: &self.name,
+            set_subtitle: &self.description,
+        }
+    }
+
+    fn init_model(quirk: Self::Init, _index: &DynamicIndex, _sender: FactorySender<Self>) -> Self {
+        QuirkRow {
+            name: text_i18n(&quirk.name),
+            description: quirk.description.as_ref().map(text_i18n).unwrap_or_default(),
+        }
+    }
+}
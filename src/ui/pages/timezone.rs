@@ -1,11 +1,12 @@
-use crate::ui::window::AppMsg;
+use crate::{ui::window::AppMsg, utils::helper::system};
 use adw::prelude::*;
 use gettextrs::gettext;
 use glib::TimeZone;
 use gnome_desktop::{self, WallClockExt};
 use log::{trace, debug};
+use icicle_core::system::Cmd;
 use relm4::*;
-use std::{collections::HashMap, process::Command};
+use std::collections::HashMap;
 
 #[tracker::track]
 #[derive(Debug)]
@@ -337,11 +338,9 @@ impl SimpleComponent for TimeZoneModel {
                 }
                 self.selected = layout;
                 if let Some(selected) = &self.selected {
-                    let _ = Command::new("timedatectl")
-                        .arg("--no-ask-password")
-                        .arg("set-timezone")
-                        .arg(selected)
-                        .spawn();
+                    let _ = system().spawn(
+                        &Cmd::new("timedatectl").args(["--no-ask-password", "set-timezone", selected]),
+                    );
                 }
             }
             TimeZoneMsg::CheckSelected => {
@@ -1,6 +1,7 @@
-use crate::ui::window::{AppMsg, UserConfig};
+use crate::ui::window::AppMsg;
 use adw::prelude::*;
 use gettextrs::gettext;
+use icicle_core::answers::UserConfig;
 use log::{debug, trace};
 use relm4::*;
 
@@ -1,4 +1,7 @@
-use crate::{ui::window::AppMsg, utils::language::get_languages};
+use crate::{
+    ui::window::AppMsg,
+    utils::{i18n::set_language, language::get_languages},
+};
 use adw::prelude::*;
 use gettextrs::gettext;
 use log::{info, trace};
@@ -295,6 +298,7 @@ impl SimpleComponent for WelcomeModel {
                         .next()
                         .unwrap_or_default(),
                 );
+                set_language(self.selected.as_deref());
             }
             WelcomeMsg::CheckSelected => {
                 trace!("WelcomeMsg::CheckSelected {}", self.selected.is_some());
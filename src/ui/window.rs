@@ -1,9 +1,14 @@
 use super::pages::{
+    bootloader::{BootloaderModel, BootloaderMsg},
+    desktop::{DesktopInit, DesktopModel, DesktopMsg},
     error::ErrorModel,
+    form::{FormInit, FormModel, FormMsg},
     install::{InstallModel, InstallMsg},
     keyboard::{KeyboardModel, KeyboardMsg},
     list::ListModel,
-    partitions::{PartitionMsg, PartitionSchema},
+    manual::{ManualModel, ManualMsg},
+    nvidia::{NvidiaModel, NvidiaMsg},
+    partitions::PartitionMsg,
     summary::{SummaryModel, SummaryMsg},
     timezone::TimeZoneMsg,
     user::UserModel,
@@ -23,17 +28,33 @@ use crate::{
         quitdialog::{QuitDialogModel, QuitDialogMsg},
     },
     utils::{
-        i18n::i18n_f,
+        helper::{paths, system},
+        i18n::{i18n_f, set_config_domain, text_i18n},
         install::{InstallAsyncModel, InstallAsyncMsg},
         language::{get_country, get_lang},
-        parse::{parse_config, Choice, ChoiceEnum, IcicleConfig, InstallationConfig, StepType},
     },
 };
 use adw::prelude::*;
 use gettextrs::gettext;
-use log::{debug, error, info, trace, warn};
+use icicle_core::{
+    answers::{Answers, UserConfig},
+    bootloader::{BootloaderConfig, Windows},
+    conditions::{is_shown, Selections},
+    config::{
+        parse_config, Choice, ChoiceEnum, Desktop, IcicleConfig, InstallationConfig, Step,
+        StepType,
+    },
+    facts::{Facts, Firmware},
+    form::{default_values, FieldValue},
+    install::ImageTarget,
+    nvidia::{NvidiaConfig, NvidiaDriver, Prime, PrimeMode},
+    protocol::{DiskLayout, PartitionSchema, RootFilesystem},
+    quirks::{matching_module, matching_quirks, HardwareModule, Quirk},
+    system::Cmd,
+};
+use log::{debug, error, info, trace};
 use relm4::*;
-use std::{collections::HashMap, convert::identity, process::Command};
+use std::{collections::HashMap, convert::identity};
 
 #[tracker::track]
 pub struct AppModel {
@@ -51,8 +72,14 @@ pub struct AppModel {
     #[tracker::no_eq]
     partition: Controller<PartitionModel>,
     #[tracker::no_eq]
+    manual: Controller<ManualModel>,
+    #[tracker::no_eq]
     user: Controller<UserModel>,
     #[tracker::no_eq]
+    nvidia: Controller<NvidiaModel>,
+    #[tracker::no_eq]
+    bootloader: Controller<BootloaderModel>,
+    #[tracker::no_eq]
     summary: Controller<SummaryModel>,
     #[tracker::no_eq]
     install: Controller<InstallModel>,
@@ -61,6 +88,12 @@ pub struct AppModel {
     #[tracker::no_eq]
     listconfig: HashMap<String, HashMap<String, Choice>>,
     #[tracker::no_eq]
+    desktop: Option<Controller<DesktopModel>>,
+    #[tracker::no_eq]
+    form: HashMap<String, Controller<FormModel>>,
+    #[tracker::no_eq]
+    formconfig: HashMap<String, HashMap<String, FieldValue>>,
+    #[tracker::no_eq]
     error: Controller<ErrorModel>,
     #[tracker::no_eq]
     quitdialog: Controller<QuitDialogModel>,
@@ -68,8 +101,24 @@ pub struct AppModel {
     can_go_back: bool,
     can_go_forward: bool,
     carousel: adw::Carousel,
+    /// Every step of the installation config with its page, shown or not
+    #[tracker::no_eq]
+    steps: Vec<(Step, gtk::Widget)>,
     #[tracker::no_eq]
     carouselpages: HashMap<usize, StepType>,
+    #[tracker::no_eq]
+    facts: Facts,
+    /// Hardware quirks that apply to the machine
+    #[tracker::no_eq]
+    quirks: Vec<Quirk>,
+    /// nixos-hardware module for the machine
+    #[tracker::no_eq]
+    hardwaremodule: Option<HardwareModule>,
+    /// Whether the user kept the nixos-hardware module on the summary page
+    usehardwaremodule: bool,
+    /// GPUs of a hybrid graphics machine
+    #[tracker::no_eq]
+    prime: Option<Prime>,
     current_page: u32,
 
     languageconfig: Option<String>,
@@ -77,22 +126,24 @@ pub struct AppModel {
     timezoneconfig: Option<String>,
     #[tracker::no_eq]
     partitionconfig: Option<PartitionSchema>,
+    /// Disk image given on the command line, installed to instead of a chosen disk
+    #[tracker::no_eq]
+    image: Option<ImageTarget>,
+    bootdiskconfig: Option<String>,
+    windowsconfig: Option<Windows>,
+    portableconfig: bool,
+    filesystemconfig: RootFilesystem,
+    layoutconfig: DiskLayout,
     userconfig: Option<UserConfig>,
+    desktopconfig: Option<String>,
+    nvidiadriver: NvidiaDriver,
+    primemode: PrimeMode,
+    bootloaderconfig: BootloaderConfig,
 
     #[tracker::no_eq]
     installworker: WorkerController<InstallAsyncModel>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct UserConfig {
-    pub name: String,
-    pub username: String,
-    pub password: String,
-    pub hostname: String,
-    pub rootpassword: Option<String>,
-    pub autologin: bool,
-}
-
 #[derive(Debug)]
 pub enum AppMsg {
     ChangePage(u32),
@@ -104,15 +155,26 @@ pub enum AppMsg {
     SetKeyboardConfig(Option<String>),
     SetTimezoneConfig(Option<String>),
     SetPartitionConfig(Option<PartitionSchema>),
+    SetBootDiskConfig(Option<String>),
+    SetWindowsConfig(Option<Windows>),
+    SetPortableConfig(bool),
+    SetFilesystemConfig(RootFilesystem),
+    SetLayoutConfig(DiskLayout),
     SetUserConfig(Option<UserConfig>),
+    SetDesktopConfig(Option<String>),
+    SetNvidiaConfig(NvidiaDriver, PrimeMode),
+    SetBootloaderConfig(BootloaderConfig),
+    SetUseHardwareModule(bool),
 
     SetListConfig(String, HashMap<String, Choice>),
+    SetFormConfig(String, HashMap<String, FieldValue>),
 
     Install,
     FinishInstall,
 
     Finished,
-    Error,
+    /// Installation failed, with an optional translated reason
+    Error(Option<String>),
 }
 
 #[derive(Debug)]
@@ -133,7 +195,7 @@ pub enum StackPage {
 #[relm4::component(pub)]
 #[allow(unused_parens)] // For relm4 match stack macro
 impl Component for AppModel {
-    type Init = ();
+    type Init = Option<ImageTarget>;
     type Input = AppMsg;
     type Output = ();
     type CommandOutput = AppAsyncMsg;
@@ -311,6 +373,24 @@ impl Component for AppModel {
                                         set_icon_name: Some("emblem-ok-symbolic"),
                                         set_pixel_size: 256,
                                     },
+                                    gtk::Box {
+                                        set_orientation: gtk::Orientation::Vertical,
+                                        set_spacing: 10,
+                                        #[watch]
+                                        set_visible: model.bootloaderconfig.uses_lanzaboote(model.facts.firmware == Firmware::Efi),
+                                        gtk::Label {
+                                            add_css_class: "title-4",
+                                            #[watch]
+                                            set_label: &gettext("Enroll the Secure Boot keys"),
+                                        },
+                                        gtk::Label {
+                                            set_wrap: true,
+                                            set_justify: gtk::Justification::Center,
+                                            set_use_markup: true,
+                                            #[watch]
+                                            set_label: &gettext("The installed system is signed with its own keys, which the firmware does not know yet. In the firmware settings, reset Secure Boot to Setup Mode, or turn it off. Then start the installed system and run <tt>sudo sbctl enroll-keys --microsoft</tt>. Turn Secure Boot back on in the firmware settings afterwards, <tt>sbctl status</tt> tells whether it is active."),
+                                        },
+                                    },
                                     gtk::Button {
                                         add_css_class: "suggested-action",
                                         add_css_class: "pill",
@@ -319,7 +399,7 @@ impl Component for AppModel {
                                         #[watch]
                                         set_label: &gettext("Reboot"),
                                         connect_clicked => move |_| {
-                                            let _ = Command::new("systemctl").arg("reboot").arg("-i").spawn();
+                                            let _ = system().spawn(&Cmd::new("systemctl").args(["reboot", "-i"]));
                                         }
                                     }
                                 }
@@ -343,11 +423,23 @@ impl Component for AppModel {
     }
 
     fn init(
-        _application: Self::Init,
+        image: Self::Init,
         root: &Self::Root,
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
-        let config = parse_config().unwrap();
+        let config = match parse_config(&paths().config_dir()) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Failed to load the installer configuration: {:#}", e);
+                std::process::exit(1);
+            }
+        };
+        if let Some(domain) = &config.gettext_domain {
+            let localedir = paths()
+                .config_dir()
+                .join(config.localedir.as_deref().unwrap_or("locale"));
+            set_config_domain(domain, &localedir);
+        }
         let welcomepage = WelcomeModel::builder()
             .launch(())
             .forward(sender.input_sender(), identity);
@@ -360,9 +452,18 @@ impl Component for AppModel {
         let partitionpage = PartitionModel::builder()
             .launch_with_broker((), &PARTITION_BROKER)
             .forward(sender.input_sender(), identity);
+        let manualpage = ManualModel::builder()
+            .launch(())
+            .forward(sender.input_sender(), identity);
         let userpage = UserModel::builder()
             .launch(())
             .forward(sender.input_sender(), identity);
+        let nvidiapage = NvidiaModel::builder()
+            .launch(())
+            .forward(sender.input_sender(), identity);
+        let bootloaderpage = BootloaderModel::builder()
+            .launch(())
+            .forward(sender.input_sender(), identity);
         let summarypage = SummaryModel::builder()
             .launch(())
             .forward(sender.input_sender(), identity);
@@ -409,6 +510,22 @@ impl Component for AppModel {
             });
         }
 
+        let facts = Facts::detect(system().as_ref());
+        let quirks = matching_quirks(&config.quirks, &facts);
+        info!(
+            "Hardware quirks: {:?}",
+            quirks.iter().map(|quirk| &quirk.id).collect::<Vec<_>>()
+        );
+        let hardwaremodule = matching_module(&config.nixos_hardware, &facts);
+        info!(
+            "nixos-hardware module: {:?}",
+            hardwaremodule.as_ref().map(|module| &module.module)
+        );
+        let prime = Prime::detect(&facts.pci_devices);
+        info!("Hybrid graphics: {:?}", prime);
+        info!("Secure Boot: {:?}", facts.secure_boot);
+        bootloaderpage.emit(BootloaderMsg::SetSecureBoot(facts.secure_boot));
+
         let model = AppModel {
             page: startpage,
             config,
@@ -417,23 +534,48 @@ impl Component for AppModel {
             keyboard: keyboardpage,
             timezone: timezonepage,
             partition: partitionpage,
+            manual: manualpage,
             user: userpage,
+            nvidia: nvidiapage,
+            bootloader: bootloaderpage,
             summary: summarypage,
             install: installpage,
             list: HashMap::new(),
             listconfig: HashMap::new(),
+            desktop: None,
+            form: HashMap::new(),
+            formconfig: HashMap::new(),
             error: errorpage,
             quitdialog,
             can_go_back: true,
             can_go_forward: true,
             carousel: adw::Carousel::new(),
+            steps: vec![],
             carouselpages: HashMap::new(),
+            facts,
+            quirks,
+            hardwaremodule,
+            usehardwaremodule: true,
+            prime,
             current_page: 0,
             languageconfig: None,
             keyboardconfig: None,
             timezoneconfig: None,
-            partitionconfig: None,
+            // Shown on the summary, the partitioning step is skipped for images
+            partitionconfig: image
+                .as_ref()
+                .map(|image| PartitionSchema::FullDisk(image.path.to_string_lossy().to_string())),
+            image,
+            bootdiskconfig: None,
+            windowsconfig: None,
+            portableconfig: false,
+            filesystemconfig: RootFilesystem::default(),
+            layoutconfig: DiskLayout::default(),
             userconfig: None,
+            desktopconfig: None,
+            nvidiadriver: NvidiaDriver::default(),
+            primemode: PrimeMode::default(),
+            bootloaderconfig: BootloaderConfig::default(),
             installworker,
             tracker: 0,
         };
@@ -451,7 +593,7 @@ impl Component for AppModel {
                             set_halign: gtk::Align::Center,
                             set_valign: gtk::Align::Center,
                             connect_clicked[sender, config] => move |_| {
-                                sender.input(AppMsg::SetStackPageConfig(StackPage::Carousel, Some(config.clone())));
+                                sender.input(AppMsg::SetStackPageConfig(StackPage::Carousel, Some(*config.clone())));
                             },
                             gtk::Box {
                                 set_orientation: gtk::Orientation::Vertical,
@@ -466,7 +608,7 @@ impl Component for AppModel {
                                     set_valign: gtk::Align::Center,
                                 },
                                 gtk::Label {
-                                    set_label: &gettext(&config.config_name),
+                                    set_label: &text_i18n(&config.config_name),
                                     set_halign: gtk::Align::Center,
                                     set_valign: gtk::Align::Center,
                                     set_wrap: true,
@@ -550,12 +692,21 @@ impl Component for AppModel {
                         StepType::Partitioning => {
                             self.partition.emit(PartitionMsg::CheckSelected);
                         }
+                        StepType::Manual => {
+                            self.manual.emit(ManualMsg::CheckSelected);
+                        }
                         StepType::User {
                             root: _,
                             hostname: _,
                         } => {
                             self.user.emit(UserMsg::CheckSelected);
                         }
+                        StepType::Nvidia => {
+                            self.nvidia.emit(NvidiaMsg::CheckSelected);
+                        }
+                        StepType::Bootloader => {
+                            self.bootloader.emit(BootloaderMsg::CheckSelected);
+                        }
                         StepType::Summary => {
                             self.summary.emit(SummaryMsg::SetConfig(
                                 self.languageconfig.clone(),
@@ -564,25 +715,46 @@ impl Component for AppModel {
                                 self.partitionconfig.clone(),
                                 Box::new(self.userconfig.clone()),
                             ));
+                            self.summary.emit(SummaryMsg::SetDesktop(
+                                self.selected_desktop().map(|desktop| desktop.name),
+                            ));
+                            self.summary.emit(SummaryMsg::SetQuirks(self.quirks.clone()));
+                            self.summary.emit(SummaryMsg::SetHardwareModule(
+                                self.hardwaremodule.as_ref().map(|module| module.name.clone()),
+                            ));
                             self.can_go_forward = true;
                         }
                         StepType::List {
-                            id: _,
+                            id,
                             multiple: _,
                             required,
-                            title,
+                            title: _,
                             choices: _,
                         } => {
                             if *required {
-                                if let Some(listpage) = self.list.get(title) {
+                                if let Some(listpage) = self.list.get(id) {
                                     listpage.emit(ListMsg::CheckSelected)
                                 } else {
-                                    error!("List page not found: {}", title);
+                                    error!("List page not found: {}", id);
                                 }
                             } else {
                                 self.can_go_forward = true;
                             }
                         }
+                        StepType::Desktop { .. } => {
+                            if let Some(desktoppage) = &self.desktop {
+                                desktoppage.emit(DesktopMsg::CheckSelected)
+                            } else {
+                                error!("Desktop page not found");
+                            }
+                        }
+                        StepType::Form { id, .. } => {
+                            if let Some(formpage) = self.form.get(id) {
+                                formpage.emit(FormMsg::CheckSelected)
+                            } else {
+                                error!("Form page not found: {}", id);
+                            }
+                        }
                         _ => {}
                     }
                 }
@@ -613,43 +785,34 @@ impl Component for AppModel {
                 self.page = page;
                 self.installconfig = installconfig;
                 if let Some(cfg) = &self.installconfig {
-                    let mut i = 0;
+                    self.desktopconfig = cfg.desktop.clone();
+                    self.bootloaderconfig = cfg.bootloader.clone().unwrap_or_default();
+                    self.bootloader
+                        .emit(BootloaderMsg::SetConfig(self.bootloaderconfig.clone()));
                     for step in &cfg.steps {
-                        match step {
+                        let widget: gtk::Widget = match &step.step {
                             StepType::Welcome => {
                                 trace!("Welcome append");
-                                self.carousel.append(self.welcome.widget());
-                                self.carouselpages.insert(i, StepType::Welcome);
-                                i += 1;
+                                self.welcome.widget().clone().upcast()
                             }
                             StepType::Keyboard => {
                                 trace!("Keyboard append");
-                                self.carousel.append(self.keyboard.widget());
-                                self.carouselpages.insert(i, StepType::Keyboard);
-                                i += 1;
+                                self.keyboard.widget().clone().upcast()
                             }
                             StepType::Location => {
                                 trace!("Timezone append");
-                                self.carousel.append(self.timezone.widget());
-                                self.carouselpages.insert(i, StepType::Location);
-                                i += 1;
+                                self.timezone.widget().clone().upcast()
                             }
                             StepType::Partitioning => {
                                 trace!("Partitioning append");
-                                self.carousel.append(self.partition.widget());
-                                self.carouselpages.insert(i, StepType::Partitioning);
-                                i += 1;
+                                self.partition.widget().clone().upcast()
+                            }
+                            StepType::Manual => {
+                                trace!("Manual append");
+                                self.manual.widget().clone().upcast()
                             }
                             StepType::User { root, hostname } => {
                                 trace!("User append");
-                                self.carousel.append(self.user.widget());
-                                self.carouselpages.insert(
-                                    i,
-                                    StepType::User {
-                                        root: *root,
-                                        hostname: *hostname,
-                                    },
-                                );
                                 self.user.emit(UserMsg::SetConfig(
                                     if let Some(root) = root { *root } else { false },
                                     if let Some(hostname) = hostname {
@@ -661,13 +824,19 @@ impl Component for AppModel {
                                 ));
                                 self.summary
                                     .emit(SummaryMsg::ShowHostname(hostname.unwrap_or(false)));
-                                i += 1;
+                                self.user.widget().clone().upcast()
+                            }
+                            StepType::Nvidia => {
+                                trace!("Nvidia append");
+                                self.nvidia.widget().clone().upcast()
+                            }
+                            StepType::Bootloader => {
+                                trace!("Bootloader append");
+                                self.bootloader.widget().clone().upcast()
                             }
                             StepType::Summary => {
                                 trace!("Summary append");
-                                self.carousel.append(self.summary.widget());
-                                self.carouselpages.insert(i, StepType::Summary);
-                                i += 1;
+                                self.summary.widget().clone().upcast()
                             }
                             StepType::List {
                                 id,
@@ -676,37 +845,64 @@ impl Component for AppModel {
                                 title,
                                 choices,
                             } => {
-                                trace!("List append: {}", title);
+                                trace!("List append: {}", id);
                                 let listpage = ListModel::builder()
                                     .launch(ListInit {
                                         id: id.to_string(),
                                         multiple: *multiple,
                                         required: *required,
-                                        title: title.to_string(),
+                                        title: title.clone(),
                                         choices: choices.clone(),
+                                        facts: self.facts.clone(),
                                     })
                                     .forward(sender.input_sender(), identity);
-                                self.carousel.append(listpage.widget());
-                                self.list.insert(title.to_string(), listpage);
-                                self.carouselpages.insert(
-                                    i,
-                                    StepType::List {
-                                        id: id.to_string(),
-                                        multiple: *multiple,
-                                        required: *required,
-                                        title: title.to_string(),
-                                        choices: choices.clone(),
-                                    },
-                                );
+                                let widget = listpage.widget().clone().upcast();
+                                self.list.insert(id.to_string(), listpage);
                                 self.listconfig.insert(id.to_string(), HashMap::new());
-                                i += 1;
+                                widget
                             }
-                            _ => {
-                                warn!("Unimplemented step: {:?}", step);
+                            StepType::Desktop { desktops } => {
+                                trace!("Desktop append");
+                                let desktoppage = DesktopModel::builder()
+                                    .launch(DesktopInit {
+                                        desktops: self
+                                            .config
+                                            .desktops
+                                            .iter()
+                                            .filter(|desktop| {
+                                                desktops
+                                                    .as_ref()
+                                                    .map_or(true, |ids| ids.contains(&desktop.id))
+                                            })
+                                            .cloned()
+                                            .collect(),
+                                        selected: self.desktopconfig.clone(),
+                                    })
+                                    .forward(sender.input_sender(), identity);
+                                let widget = desktoppage.widget().clone().upcast();
+                                self.desktop = Some(desktoppage);
+                                widget
                             }
-                        }
+                            StepType::Form { id, title, fields } => {
+                                trace!("Form append: {}", id);
+                                let formpage = FormModel::builder()
+                                    .launch(FormInit {
+                                        id: id.to_string(),
+                                        title: title.clone(),
+                                        fields: fields.clone(),
+                                    })
+                                    .forward(sender.input_sender(), identity);
+                                let widget = formpage.widget().clone().upcast();
+                                self.form.insert(id.to_string(), formpage);
+                                self.formconfig.insert(id.to_string(), default_values(fields));
+                                widget
+                            }
+                        };
+                        self.steps.push((step.clone(), widget));
                     }
                 }
+                self.update_steps();
+                self.set_esp();
                 sender.input(AppMsg::ChangePage(0));
             }
             AppMsg::SetLanguageConfig(language) => {
@@ -714,6 +910,12 @@ impl Component for AppModel {
                 for listpage in self.list.values() {
                     listpage.emit(ListMsg::SetLocale(self.languageconfig.clone()));
                 }
+                for formpage in self.form.values() {
+                    formpage.emit(FormMsg::SetLocale(self.languageconfig.clone()));
+                }
+                if let Some(desktoppage) = &self.desktop {
+                    desktoppage.emit(DesktopMsg::SetLocale(self.languageconfig.clone()));
+                }
                 self.install.emit(InstallMsg::SetLocale(self.languageconfig.clone()));
                 if let Some(language) = &self.languageconfig {
                     if let (Ok(lang), Ok(country)) =
@@ -732,29 +934,77 @@ impl Component for AppModel {
             AppMsg::SetPartitionConfig(partition) => {
                 self.partitionconfig = partition;
             }
+            AppMsg::SetBootDiskConfig(bootdisk) => {
+                self.bootdiskconfig = bootdisk;
+            }
+            AppMsg::SetWindowsConfig(windows) => {
+                self.windowsconfig = windows;
+            }
+            AppMsg::SetPortableConfig(portable) => {
+                self.portableconfig = portable;
+            }
+            AppMsg::SetFilesystemConfig(filesystem) => {
+                self.filesystemconfig = filesystem;
+            }
+            AppMsg::SetLayoutConfig(layout) => {
+                self.layoutconfig = layout;
+            }
             AppMsg::SetUserConfig(user) => {
                 self.userconfig = user;
             }
-            AppMsg::SetListConfig(title, list) => {
-                info!("SetListConfig: {} {:?}", title, list);
-                self.listconfig.insert(title, list);
+            AppMsg::SetDesktopConfig(desktop) => {
+                self.desktopconfig = desktop;
+            }
+            AppMsg::SetUseHardwareModule(usehardwaremodule) => {
+                self.usehardwaremodule = usehardwaremodule;
+            }
+            AppMsg::SetNvidiaConfig(driver, mode) => {
+                self.nvidiadriver = driver;
+                self.primemode = mode;
+            }
+            AppMsg::SetBootloaderConfig(bootloader) => {
+                self.bootloaderconfig = bootloader;
+                self.set_esp();
+            }
+            AppMsg::SetListConfig(id, list) => {
+                info!("SetListConfig: {} {:?}", id, list);
+                self.listconfig.insert(id, list);
                 info!("ListConfig: {:?}", self.listconfig);
+                self.update_steps();
+            }
+            AppMsg::SetFormConfig(id, values) => {
+                info!("SetFormConfig: {} {:?}", id, values);
+                self.formconfig.insert(id, values);
             }
             AppMsg::Install => {
                 debug!("Installing!");
-                if let Some(id) = &self
-                    .installconfig
-                    .as_ref()
-                    .map(|cfg| cfg.config_id.to_string())
-                {
+                if let Some(cfg) = &self.installconfig {
                     self.installworker.emit(InstallAsyncMsg::Install(
-                        id.to_string(),
-                        self.languageconfig.clone(),
-                        self.timezoneconfig.clone(),
-                        self.keyboardconfig.clone(),
-                        Box::new(self.partitionconfig.clone()),
-                        Box::new(self.userconfig.clone()),
-                        self.listconfig.clone(),
+                        Box::new(cfg.clone()),
+                        Box::new(Answers {
+                            language: self.languageconfig.clone(),
+                            timezone: self.timezoneconfig.clone(),
+                            keyboard: self.keyboardconfig.clone(),
+                            partitions: self.partitionconfig.clone(),
+                            user: self.userconfig.clone(),
+                            desktop: self.selected_desktop(),
+                            lists: self.shown_lists(),
+                            forms: self.shown_forms(),
+                            quirks: self.quirks.clone(),
+                            nixos_hardware: self
+                                .hardwaremodule
+                                .as_ref()
+                                .filter(|_| self.usehardwaremodule)
+                                .map(|module| module.module.to_string()),
+                            nvidia: self.nvidia_config(),
+                            bootloader: self.bootloaderconfig.clone(),
+                            bootdisk: self.bootdiskconfig.clone(),
+                            windows: self.windowsconfig,
+                            portable: self.portableconfig,
+                            filesystem: self.filesystemconfig,
+                            layout: self.layoutconfig.clone(),
+                        }),
+                        self.image.clone(),
                     ));
                 }
             }
@@ -766,10 +1016,10 @@ impl Component for AppModel {
                 debug!("Finished!");
                 self.page = StackPage::Finished;
             }
-            AppMsg::Error => {
-                debug!("Error!");
+            AppMsg::Error(message) => {
+                debug!("Error! {:?}", message);
                 self.page = StackPage::Error;
-                self.error.emit(ErrorMsg::Show);
+                self.error.emit(ErrorMsg::Show(message));
             }
         }
     }
@@ -787,3 +1037,138 @@ impl Component for AppModel {
         }
     }
 }
+
+impl AppModel {
+    /// Shows the pages of the steps whose `when` conditions hold for the current
+    /// answers. Conditions only see selections of lists that are shown themselves.
+    fn update_steps(&mut self) {
+        let mut selections = Selections::new();
+        let mut pages = vec![];
+        for (step, widget) in &self.steps {
+            if !is_shown(step.when.as_ref(), &self.facts, &selections) {
+                continue;
+            }
+            if matches!(step.step, StepType::Nvidia) && self.prime.is_none() {
+                continue;
+            }
+            if matches!(step.step, StepType::Bootloader) && self.facts.firmware == Firmware::Bios {
+                continue;
+            }
+            if matches!(step.step, StepType::Partitioning) && self.image.is_some() {
+                continue;
+            }
+            if let StepType::List { id, .. } = &step.step {
+                if let Some(list) = self.listconfig.get(id) {
+                    selections.insert(id.to_string(), list.keys().cloned().collect());
+                }
+            }
+            pages.push((step.step.clone(), widget.clone()));
+        }
+        for listpage in self.list.values() {
+            listpage.emit(ListMsg::SetSelections(selections.clone()));
+        }
+
+        let current = if self.carousel.n_pages() > 0 {
+            Some(self.carousel.nth_page(self.current_page))
+        } else {
+            None
+        };
+        // Drop pages that are no longer shown, then insert new ones in step order
+        for i in (0..self.carousel.n_pages()).rev() {
+            let page = self.carousel.nth_page(i);
+            if !pages.iter().any(|(_, widget)| widget == &page) {
+                self.carousel.remove(&page);
+            }
+        }
+        for (i, (_, widget)) in pages.iter().enumerate() {
+            if i as u32 >= self.carousel.n_pages() || &self.carousel.nth_page(i as u32) != widget
+            {
+                self.carousel.insert(widget, i as i32);
+            }
+        }
+        if let Some(current) = current {
+            if let Some(i) = pages.iter().position(|(_, widget)| widget == &current) {
+                self.current_page = i as u32;
+            }
+        }
+        self.carouselpages = pages
+            .into_iter()
+            .enumerate()
+            .map(|(i, (step, _))| (i, step))
+            .collect();
+    }
+
+    /// Selections of every list, lists that are not shown select nothing.
+    fn shown_lists(&self) -> HashMap<String, HashMap<String, Choice>> {
+        let shown = self
+            .carouselpages
+            .values()
+            .filter_map(|step| match step {
+                StepType::List { id, .. } => Some(id.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        self.listconfig
+            .iter()
+            .map(|(id, choices)| {
+                if shown.contains(id) {
+                    (id.to_string(), choices.clone())
+                } else {
+                    (id.to_string(), HashMap::new())
+                }
+            })
+            .collect()
+    }
+
+    /// PRIME configuration of a hybrid graphics machine, with the defaults unless
+    /// changed on the NVIDIA step.
+    fn nvidia_config(&self) -> Option<NvidiaConfig> {
+        Some(NvidiaConfig {
+            prime: self.prime.clone()?,
+            driver: self.nvidiadriver,
+            mode: self.primemode,
+        })
+    }
+
+    /// Tells the partitioning pages where the EFI system partition must be mounted.
+    fn set_esp(&self) {
+        let esp = self.bootloaderconfig.esp.mountpoint().to_string();
+        self.partition.emit(PartitionMsg::SetEsp(esp.clone()));
+        self.manual.emit(ManualMsg::SetEsp(esp));
+    }
+
+    /// The desktop chosen on a shown desktop step, or else the installation config's default.
+    fn selected_desktop(&self) -> Option<Desktop> {
+        let shown = self
+            .carouselpages
+            .values()
+            .any(|step| matches!(step, StepType::Desktop { .. }));
+        let id = if shown {
+            self.desktopconfig.clone()
+        } else {
+            self.installconfig.as_ref()?.desktop.clone()
+        }?;
+        self.config
+            .desktops
+            .iter()
+            .find(|desktop| desktop.id == id)
+            .cloned()
+    }
+
+    /// Values of every form field, fields of forms that are not shown keep their defaults.
+    fn shown_forms(&self) -> HashMap<String, FieldValue> {
+        let mut values = HashMap::new();
+        for (step, _) in &self.steps {
+            if let StepType::Form { id, fields, .. } = &step.step {
+                let shown = self.carouselpages.values().any(|step| {
+                    matches!(step, StepType::Form { id: shown, .. } if shown == id)
+                });
+                match self.formconfig.get(id) {
+                    Some(config) if shown => values.extend(config.clone()),
+                    _ => values.extend(default_values(fields)),
+                }
+            }
+        }
+        values
+    }
+}
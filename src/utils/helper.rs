@@ -0,0 +1,52 @@
+use crate::config::{LIBEXECDIR, SYSCONFDIR};
+use gettextrs::gettext;
+use icicle_core::{
+    protocol::{ErrorCode, HelperError, Response},
+    system::{RealSystem, System},
+    Paths,
+};
+use std::sync::Arc;
+
+pub fn paths() -> Paths {
+    Paths::new(SYSCONFDIR, LIBEXECDIR)
+}
+
+/// The host system, with the installed `icicle-helper`.
+pub fn system() -> Arc<dyn System> {
+    Arc::new(RealSystem::new(paths().helper()))
+}
+
+/// Runs the installed `icicle-helper` through pkexec.
+pub fn run_helper(args: &[&str], input: Option<&[u8]>) -> Result<Response, HelperError> {
+    system().helper(args, input)
+}
+
+/// Translated description of a helper failure, if `error` came from the helper.
+pub fn describe_error(error: &anyhow::Error) -> Option<String> {
+    let error = error.downcast_ref::<HelperError>()?;
+    Some(match error.code {
+        ErrorCode::NotRoot | ErrorCode::NotAuthorized => {
+            gettext("Administrator privileges are required to install")
+        }
+        ErrorCode::InvalidRequest | ErrorCode::UnsupportedVersion => {
+            gettext("The installer helper does not match this version of the installer")
+        }
+        ErrorCode::DiskNotFound => gettext("The selected disk could not be found"),
+        ErrorCode::PartitionNotFound => gettext("A selected partition could not be found"),
+        ErrorCode::PartitionTable => gettext("Failed to create a partition table"),
+        ErrorCode::CreatePartition => gettext("Failed to create partitions"),
+        ErrorCode::Commit => gettext("Failed to write changes to the disk"),
+        ErrorCode::Format => gettext("Failed to format partitions"),
+        ErrorCode::Mount => gettext("Failed to mount partitions"),
+        ErrorCode::Unmount => gettext("Failed to unmount partitions"),
+        ErrorCode::Swap => gettext("Failed to enable swap"),
+        ErrorCode::Zfs => gettext("Failed to create the ZFS pool"),
+        ErrorCode::Encrypt => gettext("Failed to encrypt the disk"),
+        ErrorCode::Lvm => gettext("Failed to create logical volumes"),
+        ErrorCode::PathOutsideTarget => {
+            gettext("Refused to write a file outside of the installation target")
+        }
+        ErrorCode::Image => gettext("Failed to prepare the disk image"),
+        ErrorCode::Io => gettext("An unexpected system error occurred"),
+    })
+}
@@ -1,12 +1,23 @@
 // Source: GNOME Social by Christopher Davis
 // https://gitlab.gnome.org/World/Social
 
+use gettextrs::dgettext;
 use gettextrs::gettext;
 use gettextrs::ngettext;
 use gettextrs::npgettext;
 use gettextrs::pgettext;
+use icicle_core::text::Text;
+use log::error;
 use regex::Captures;
 use regex::Regex;
+use std::env;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Gettext domain of the distribution's configs, if they have their own
+static CONFIG_DOMAIN: Mutex<Option<String>> = Mutex::new(None);
+/// Language chosen on the welcome page
+static LANGUAGE: Mutex<Option<String>> = Mutex::new(None);
 
 #[allow(dead_code)]
 fn freplace(input: String, args: &[&str]) -> String {
@@ -50,6 +61,59 @@ pub fn i18n_k(format: &str, kwargs: &[(&str, &str)]) -> String {
     kreplace(s, kwargs)
 }
 
+// Translations of strings from the configs
+
+/// Binds the gettext domain translating the strings of the configs.
+pub fn set_config_domain(domain: &str, localedir: &Path) {
+    if let Err(e) = gettextrs::bindtextdomain(domain, localedir) {
+        error!("Unable to bind the text domain {}: {}", domain, e);
+        return;
+    }
+    let _ = gettextrs::bind_textdomain_codeset(domain, "UTF-8");
+    *CONFIG_DOMAIN.lock().unwrap() = Some(domain.to_string());
+}
+
+/// Sets the language inline translations are picked for, e.g. `de_DE.UTF-8`.
+pub fn set_language(language: Option<&str>) {
+    *LANGUAGE.lock().unwrap() = language.map(|l| l.to_string());
+}
+
+fn language() -> String {
+    LANGUAGE
+        .lock()
+        .unwrap()
+        .clone()
+        .or_else(|| {
+            ["LC_ALL", "LC_MESSAGES", "LANG"]
+                .iter()
+                .find_map(|var| env::var(var).ok().filter(|l| !l.is_empty()))
+        })
+        .unwrap_or_default()
+}
+
+/// Translates a string from the configs through their gettext domain,
+/// falling back to Icicle's own translations.
+pub fn config_i18n(msgid: &str) -> String {
+    if msgid.is_empty() {
+        return String::new();
+    }
+    if let Some(domain) = CONFIG_DOMAIN.lock().unwrap().as_deref() {
+        let translated = dgettext(domain, msgid);
+        if translated != msgid {
+            return translated;
+        }
+    }
+    gettext(msgid)
+}
+
+/// Translates a string from the configs, through gettext or inline.
+pub fn text_i18n(text: &Text) -> String {
+    match text {
+        Text::Gettext(msgid) => config_i18n(msgid),
+        Text::Inline(_) => text.pick(&language()).to_string(),
+    }
+}
+
 // Singular and plural translations functions
 
 #[allow(dead_code)]
@@ -1,4 +1,4 @@
+pub mod helper;
 pub mod i18n;
 pub mod install;
 pub mod language;
-pub mod parse;